@@ -1,4 +1,4 @@
-use gameboy_emulator::bits;
+use gameboy_emulator_macros::bits;
 
 #[test]
 fn simple_bitstring_matching() {
@@ -0,0 +1,126 @@
+use std::{fmt, str::FromStr};
+
+use crate::errors::EmulatorError;
+
+/// Size of the DMG, MGB and SGB boot ROMs.
+const BOOT_ROM_SIZE: usize = 0x100;
+/// Size of the CGB boot ROM, including the unmapped 0x0100-0x01FF where the cartridge header is.
+const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+/// The console models, which differ in their boot ROM and the state it leaves behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Dmg,
+    /// Game Boy Pocket
+    Mgb,
+    /// Super Game Boy
+    Sgb,
+    /// Game Boy Color
+    Cgb,
+}
+
+impl Model {
+    /// Guesses the model a boot ROM belongs to from its size. The boot ROMs of the DMG, MGB and
+    /// SGB have the same size, so these are taken as DMG.
+    pub fn from_boot_rom(boot_rom: &[u8]) -> Self {
+        if boot_rom.len() == CGB_BOOT_ROM_SIZE {
+            Model::Cgb
+        } else {
+            Model::Dmg
+        }
+    }
+
+    /// Number of bytes of the Nintendo logo in the cartridge header that the boot ROM compares,
+    /// locking up on a mismatch. The SGB boot ROM does not check the logo, the CGB boot ROM only
+    /// checks the upper half.
+    pub fn checked_logo_len(self) -> usize {
+        match self {
+            Model::Dmg | Model::Mgb => 0x30,
+            Model::Sgb => 0,
+            Model::Cgb => 0x18,
+        }
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Model::Dmg => "DMG",
+            Model::Mgb => "MGB",
+            Model::Sgb => "SGB",
+            Model::Cgb => "CGB",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Parses the model names as displayed, ignoring case.
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        [Model::Dmg, Model::Mgb, Model::Sgb, Model::Cgb]
+            .into_iter()
+            .find(|model| model.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(|| "expected one of dmg, mgb, sgb, cgb".to_string())
+    }
+}
+
+/// A boot ROM dumped from a console, which is mapped over the cartridge ROM at 0x0000-0x00FF, and
+/// on the CGB also at 0x0200-0x08FF, until the program writes to 0xFF50.
+pub struct BootRom {
+    data: Vec<u8>,
+    model: Model,
+}
+
+impl BootRom {
+    pub fn new(data: Vec<u8>, model: Model) -> Result<Self, EmulatorError> {
+        let size = match model {
+            Model::Cgb => CGB_BOOT_ROM_SIZE,
+            _ => BOOT_ROM_SIZE,
+        };
+        if data.len() != size {
+            return Err(EmulatorError::BootRomError(format!(
+                "The {model} boot ROM must be {size} bytes long, but has {} bytes.",
+                data.len()
+            )));
+        }
+        Ok(BootRom { data, model })
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Returns the byte at `address` if the boot ROM is mapped there.
+    #[inline]
+    pub fn read(&self, address: u16) -> Option<u8> {
+        match address {
+            0x0100..=0x01FF => None,
+            _ => self.data.get(address as usize).copied(),
+        }
+    }
+
+    /// Whether the boot ROM hides the cartridge ROM at `address`.
+    pub fn maps(&self, address: u16) -> bool {
+        self.read(address).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cgb_boot_rom_leaves_header_visible() {
+        let boot_rom = BootRom::new(vec![0x42; CGB_BOOT_ROM_SIZE], Model::Cgb).unwrap();
+        assert_eq!(boot_rom.read(0x00FF), Some(0x42));
+        assert_eq!(boot_rom.read(0x0100), None);
+        assert_eq!(boot_rom.read(0x08FF), Some(0x42));
+        assert_eq!(boot_rom.read(0x0900), None);
+
+        let boot_rom = BootRom::new(vec![0x42; BOOT_ROM_SIZE], Model::Mgb).unwrap();
+        assert!(!boot_rom.maps(0x0200));
+        assert!(BootRom::new(vec![0; BOOT_ROM_SIZE], Model::Cgb).is_err());
+    }
+}
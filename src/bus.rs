@@ -0,0 +1,105 @@
+/// Address of the IF register, which holds the interrupt request flags.
+pub const INTERRUPT_FLAG_ADDRESS: u16 = 0xFF0F;
+/// Address of the IE register, which holds the interrupt enable flags.
+pub const INTERRUPT_ENABLE_ADDRESS: u16 = 0xFFFF;
+
+/// Interrupt sources, ordered by priority. The discriminant is the bit in the IE and IF registers.
+/// The serial port and the joypad are not emulated, so their interrupts (bits 3 and 4) are never
+/// requested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank = 0,
+    Stat = 1,
+    Timer = 2,
+}
+
+impl Interrupt {
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Everything the CPU is connected to. Reading and writing do not advance time, the CPU calls
+/// `tick` once for every M-cycle it spends, including the ones it uses to access memory.
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+    /// Advances all components connected to the bus by one M-cycle.
+    fn tick(&mut self);
+    /// Returns the interrupts that are both requested and enabled (IF & IE) in the lower 5 bits.
+    fn pending_interrupts(&self) -> u8;
+    /// Clears the request flags in IF that are set in `mask`.
+    fn acknowledge_interrupts(&mut self, mask: u8);
+    /// Returns the bank that is currently mapped at `address`, or `None` if instructions at
+    /// `address` must not be cached because reading it can have side effects or its contents can
+    /// change without the CPU writing to it. Regions without banking report bank 0.
+    fn mapped_bank(&self, address: u16) -> Option<u16>;
+    /// Whether the CPU is paused this M-cycle, because a DMA uses the bus or the speed switches.
+    fn stalled(&self) -> bool {
+        false
+    }
+    /// Called by the stop instruction. Switches between normal and double speed and returns true
+    /// if a switch was prepared through KEY1, otherwise the CPU stops.
+    fn switch_speed(&mut self) -> bool {
+        false
+    }
+}
+
+/// A bus backed by a flat 64 KiB array without any memory mapped hardware. Writes to the ROM area
+/// (0x0000-0x7FFF) are ignored.
+#[cfg(test)]
+pub struct FlatBus {
+    memory: Box<[u8; 0x10000]>,
+}
+
+#[cfg(test)]
+impl FlatBus {
+    /// Creates a bus with the first 32 KiB of `rom` mapped to 0x0000-0x7FFF.
+    pub fn with_rom(rom: &[u8]) -> Self {
+        let mut memory = Box::new([0; 0x10000]);
+        let rom_size = rom.len().min(0x8000);
+        memory[..rom_size].copy_from_slice(&rom[..rom_size]);
+        FlatBus { memory }
+    }
+}
+
+/// Returns the index of `address` in memory, with echo RAM mirroring WRAM.
+#[cfg(test)]
+fn echo_ram_target(address: u16) -> usize {
+    match address {
+        0xE000..=0xFDFF => address as usize - 0x2000,
+        _ => address as usize,
+    }
+}
+
+#[cfg(test)]
+impl Bus for FlatBus {
+    fn read(&mut self, address: u16) -> u8 {
+        self.memory[echo_ram_target(address)]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.memory[echo_ram_target(address)] = value;
+        }
+    }
+
+    fn tick(&mut self) {}
+
+    fn pending_interrupts(&self) -> u8 {
+        self.memory[INTERRUPT_FLAG_ADDRESS as usize]
+            & self.memory[INTERRUPT_ENABLE_ADDRESS as usize]
+            & 0x1F
+    }
+
+    fn acknowledge_interrupts(&mut self, mask: u8) {
+        self.memory[INTERRUPT_FLAG_ADDRESS as usize] &= !mask;
+    }
+
+    fn mapped_bank(&self, address: u16) -> Option<u16> {
+        match address {
+            0x0000..=0x7FFF | 0xC000..=0xDFFF | 0xFF80..=0xFFFE => Some(0),
+            _ => None,
+        }
+    }
+}
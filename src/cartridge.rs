@@ -0,0 +1,528 @@
+mod eeprom;
+pub mod header;
+mod huc1;
+mod huc3;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod mbc7;
+mod mmm01;
+mod pocket_camera;
+mod rom_only;
+mod rtc;
+mod sachen;
+pub mod state;
+mod wisdom_tree;
+
+pub use header::{CartridgeHeader, MapperKind};
+pub use pocket_camera::CameraImage;
+
+use huc1::HuC1;
+use huc3::HuC3;
+use mbc1::Mbc1;
+use mbc2::Mbc2;
+use mbc3::Mbc3;
+use mbc5::Mbc5;
+use mbc7::Mbc7;
+use mmm01::Mmm01;
+use pocket_camera::PocketCamera;
+use rom_only::RomOnly;
+use sachen::Sachen;
+use wisdom_tree::WisdomTree;
+
+use crate::errors::EmulatorError;
+
+/// The mappers in the default `MapperRegistry`.
+const BUILT_IN_MAPPERS: [MapperKind; 13] = [
+    MapperKind::None,
+    MapperKind::Mbc1,
+    MapperKind::Mbc2,
+    MapperKind::Mmm01,
+    MapperKind::Mbc3,
+    MapperKind::Mbc5,
+    MapperKind::Mbc7,
+    MapperKind::PocketCamera,
+    MapperKind::HuC3,
+    MapperKind::HuC1,
+    MapperKind::WisdomTree,
+    MapperKind::SachenMmc1,
+    MapperKind::SachenMmc2,
+];
+
+/// Size of a single switchable ROM bank.
+pub const ROM_BANK_SIZE: usize = 0x4000;
+/// Size of a single switchable external RAM bank.
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+/// The banking hardware on a cartridge, which owns the ROM and external RAM.
+///
+/// Besides the built-in mappers, implementations for other cartridge hardware can be added to a
+/// `MapperRegistry`, which the cartridge asks for the mapper matching a ROM.
+pub trait Mapper {
+    /// Reads from the ROM area, `address` being in 0x0000-0x7FFF.
+    fn read_rom(&self, address: u16) -> u8;
+    /// Writes to the ROM area go to the mapper registers.
+    fn write_rom(&mut self, address: u16, value: u8);
+    /// Reads from the external RAM area, `address` being in 0xA000-0xBFFF.
+    fn read_ram(&self, address: u16) -> u8;
+    fn write_ram(&mut self, address: u16, value: u8);
+    /// Returns the ROM bank mapped at `address`, being in 0x0000-0x7FFF.
+    fn rom_bank(&self, address: u16) -> u16;
+    /// Returns the external RAM bank selected for 0xA000-0xBFFF, for mappers with banked RAM.
+    /// Mappers that map other hardware there, like a clock, report the value selecting it.
+    fn ram_bank(&self) -> u16 {
+        0
+    }
+    /// Advances the mapper by one M-cycle, for hardware running alongside the CPU like clocks.
+    fn tick(&mut self) {}
+    /// Returns whether the mapper needs to see every bus access through `observe_access`, which
+    /// is only the case for mappers that follow the boot sequence.
+    fn observes_accesses(&self) -> bool {
+        false
+    }
+    /// Called with the address of every memory access while `observes_accesses` returns true.
+    fn observe_access(&mut self, _address: u16) {}
+    /// Puts the mapper into the state the boot ROM would have left it in, when starting from the
+    /// post-boot state without running a boot ROM.
+    fn skip_boot(&mut self) {}
+    /// Returns the event caused by the last register or RAM write, if any.
+    fn take_event(&mut self) -> Option<CartridgeEvent> {
+        None
+    }
+    /// Sets the acceleration in g measured by a tilt sensor, positive `x` being right and positive
+    /// `y` being down.
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+    /// Sets the picture in front of a camera sensor.
+    fn set_camera_image(&mut self, _image: CameraImage) {}
+    /// Serializes everything except the ROM needed to continue emulation later, like registers
+    /// and RAM. Inputs set from outside like `set_tilt` are not part of the state.
+    fn save_state(&self) -> Vec<u8>;
+    /// Restores a state returned by `save_state` of the same mapper for the same ROM. On error,
+    /// the mapper may be partially restored.
+    fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError>;
+    /// Returns the memory that keeps its contents while the console is off on cartridges with a
+    /// battery. This is the external RAM for most mappers.
+    fn battery_data(&self) -> Vec<u8>;
+    /// Restores the memory returned by `battery_data`. Data of the wrong length is truncated or
+    /// padded.
+    fn load_battery_data(&mut self, data: &[u8]);
+}
+
+/// Changes of cartridge hardware that the player should notice outside the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CartridgeEvent {
+    /// The rumble motor was switched on or off.
+    Rumble { active: bool },
+    /// The infrared LED was switched on or off.
+    Infrared { active: bool },
+    /// The speaker started playing one of the built-in tones.
+    Tone { tone: u8 },
+}
+
+pub type CartridgeEventListener = Box<dyn FnMut(CartridgeEvent)>;
+
+/// The game cartridge, mapped to 0x0000-0x7FFF (ROM) and 0xA000-0xBFFF (external RAM).
+pub struct Cartridge {
+    header: CartridgeHeader,
+    mapper: Box<dyn Mapper>,
+    /// cached `Mapper::observes_accesses`
+    observes_accesses: bool,
+    listeners: Vec<CartridgeEventListener>,
+}
+
+impl Cartridge {
+    /// Creates a cartridge with the mapper specified by the header of `rom`, or detected by the
+    /// heuristics for unlicensed mappers.
+    pub fn new(rom: Vec<u8>) -> Result<Self, EmulatorError> {
+        Self::with_mapper(rom, None)
+    }
+
+    /// Creates a cartridge with the given mapper regardless of the header, for cartridges whose
+    /// cartridge type byte is wrong. With `None`, the mapper is detected as in `new`.
+    pub fn with_mapper(rom: Vec<u8>, mapper: Option<MapperKind>) -> Result<Self, EmulatorError> {
+        let name = mapper.map(|mapper| mapper.to_string());
+        Self::with_registry(rom, &MapperRegistry::default(), name.as_deref())
+    }
+
+    /// Creates a cartridge with a mapper from `registry`, either the one called `mapper` or the
+    /// first one detecting `rom`.
+    pub fn with_registry(
+        rom: Vec<u8>,
+        registry: &MapperRegistry,
+        mapper: Option<&str>,
+    ) -> Result<Self, EmulatorError> {
+        // the header of MMM01 multicarts is part of the menu at the end of the ROM
+        let mmm01_menu = mmm01::menu(&rom);
+        let header = match mmm01_menu {
+            Some(menu)
+                if mapper.is_none_or(|name| same_name(name, &MapperKind::Mmm01.to_string())) =>
+            {
+                CartridgeHeader::parse(menu)?
+            }
+            _ => CartridgeHeader::parse(&rom)?,
+        };
+        let mapper = registry.create(rom, &header, mapper)?;
+        Ok(Cartridge {
+            header,
+            observes_accesses: mapper.observes_accesses(),
+            mapper,
+            listeners: Vec::new(),
+        })
+    }
+
+    /// Calls `listener` on every `CartridgeEvent` from now on.
+    pub fn subscribe(&mut self, listener: CartridgeEventListener) {
+        self.listeners.push(listener);
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    pub fn read_rom(&self, address: u16) -> u8 {
+        self.mapper.read_rom(address)
+    }
+
+    pub fn write_rom(&mut self, address: u16, value: u8) {
+        self.mapper.write_rom(address, value);
+        self.notify();
+    }
+
+    pub fn read_ram(&self, address: u16) -> u8 {
+        self.mapper.read_ram(address)
+    }
+
+    pub fn write_ram(&mut self, address: u16, value: u8) {
+        self.mapper.write_ram(address, value);
+        self.notify();
+    }
+
+    pub fn rom_bank(&self, address: u16) -> u16 {
+        self.mapper.rom_bank(address)
+    }
+
+    pub fn ram_bank(&self) -> u16 {
+        self.mapper.ram_bank()
+    }
+
+    pub fn tick(&mut self) {
+        self.mapper.tick();
+    }
+
+    /// Lets the mapper see a memory access anywhere on the bus, if it needs to.
+    #[inline]
+    pub fn observe_access(&mut self, address: u16) {
+        if self.observes_accesses {
+            self.mapper.observe_access(address);
+            self.observes_accesses = self.mapper.observes_accesses();
+        }
+    }
+
+    /// Whether ROM reads currently depend on more than the mapped bank, so decoded instructions
+    /// can not be cached.
+    pub fn observes_accesses(&self) -> bool {
+        self.observes_accesses
+    }
+
+    /// Compares the first `len` bytes of the Nintendo logo in the header as the boot ROM does,
+    /// reading them through the mapper.
+    pub fn logo_matches(&self, len: usize) -> bool {
+        header::NINTENDO_LOGO[..len]
+            .iter()
+            .enumerate()
+            .all(|(index, &byte)| self.read_rom((header::LOGO_START + index) as u16) == byte)
+    }
+
+    pub fn skip_boot(&mut self) {
+        self.mapper.skip_boot();
+        self.observes_accesses = self.mapper.observes_accesses();
+    }
+
+    /// Feeds the tilt sensor of cartridges like the MBC7, see `Mapper::set_tilt`.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mapper.set_tilt(x, y);
+    }
+
+    /// Feeds the camera sensor of the Pocket Camera, see `Mapper::set_camera_image`.
+    pub fn set_camera_image(&mut self, image: CameraImage) {
+        self.mapper.set_camera_image(image);
+    }
+
+    /// Passes the event caused by the last write on to the listeners.
+    fn notify(&mut self) {
+        if let Some(event) = self.mapper.take_event() {
+            for listener in &mut self.listeners {
+                listener(event);
+            }
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.header.cartridge_type.battery
+    }
+
+    /// Returns the data to persist in a save file, or `None` if the cartridge has no battery.
+    pub fn battery_data(&self) -> Option<Vec<u8>> {
+        self.has_battery().then(|| self.mapper.battery_data())
+    }
+
+    pub fn load_battery_data(&mut self, data: &[u8]) {
+        self.mapper.load_battery_data(data);
+    }
+
+    /// Returns the mapper state, see `Mapper::save_state`.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.mapper.save_state()
+    }
+
+    /// Restores the mapper from `save_state`, see `Mapper::load_state`.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+        self.mapper.load_state(state)?;
+        self.observes_accesses = self.mapper.observes_accesses();
+        Ok(())
+    }
+}
+
+/// Decides whether a mapper is the right one for a ROM with the given header.
+pub type DetectMapper = Box<dyn Fn(&[u8], &CartridgeHeader) -> bool>;
+/// Creates a mapper owning the given ROM.
+pub type CreateMapper = Box<dyn Fn(Vec<u8>, &CartridgeHeader) -> Box<dyn Mapper>>;
+
+struct MapperEntry {
+    name: String,
+    /// `None` for the built-in mappers, which are detected together by `detect_mapper`.
+    detect: Option<DetectMapper>,
+    create: CreateMapper,
+}
+
+/// The mappers a cartridge can be created with, each with a name and a detection function.
+///
+/// The default registry contains the built-in mappers, named as `MapperKind` displays them.
+pub struct MapperRegistry {
+    entries: Vec<MapperEntry>,
+}
+
+impl Default for MapperRegistry {
+    fn default() -> Self {
+        let mut registry = MapperRegistry {
+            entries: Vec::new(),
+        };
+        for kind in BUILT_IN_MAPPERS {
+            registry.entries.push(MapperEntry {
+                name: kind.to_string(),
+                detect: None,
+                create: Box::new(move |rom, header| create_mapper(kind, rom, header)),
+            });
+        }
+        registry
+    }
+}
+
+impl MapperRegistry {
+    /// Adds a mapper, which is detected before all mappers registered earlier including the
+    /// built-in ones. A mapper with the same name is replaced.
+    pub fn register(&mut self, name: &str, detect: DetectMapper, create: CreateMapper) {
+        self.entries.retain(|entry| !same_name(&entry.name, name));
+        self.entries.insert(
+            0,
+            MapperEntry {
+                name: name.to_string(),
+                detect: Some(detect),
+                create,
+            },
+        );
+    }
+
+    /// Creates the mapper called `name`, or the first one detecting `rom` if `name` is `None`.
+    /// Names are compared ignoring case, with '-' and '_' matching spaces.
+    fn create(
+        &self,
+        rom: Vec<u8>,
+        header: &CartridgeHeader,
+        name: Option<&str>,
+    ) -> Result<Box<dyn Mapper>, EmulatorError> {
+        let entry = match name {
+            Some(name) => self
+                .entries
+                .iter()
+                .find(|entry| same_name(&entry.name, name))
+                .ok_or_else(|| {
+                    let names: Vec<String> = self
+                        .entries
+                        .iter()
+                        .map(|entry| entry.name.to_ascii_lowercase().replace(' ', "-"))
+                        .collect();
+                    EmulatorError::CartridgeError(format!(
+                        "Unknown mapper {name}, expected one of: {}",
+                        names.join(", ")
+                    ))
+                })?,
+            None => {
+                // scans the ROM once for all built-in mappers
+                let built_in = detect_mapper(&rom, header).to_string();
+                self.entries
+                    .iter()
+                    .find(|entry| match &entry.detect {
+                        Some(detect) => detect(&rom, header),
+                        None => entry.name == built_in,
+                    })
+                    .ok_or_else(|| {
+                        EmulatorError::CartridgeError(format!(
+                            "Cartridges with the {} mapper are not supported.",
+                            header.cartridge_type.mapper
+                        ))
+                    })?
+            }
+        };
+        Ok((entry.create)(rom, header))
+    }
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    let normalize = |name: &str| name.to_ascii_uppercase().replace(['-', '_'], " ");
+    normalize(a) == normalize(b)
+}
+
+/// Creates one of the `BUILT_IN_MAPPERS`.
+fn create_mapper(kind: MapperKind, rom: Vec<u8>, header: &CartridgeHeader) -> Box<dyn Mapper> {
+    let cartridge_type = header.cartridge_type;
+    let ram_size = if cartridge_type.ram {
+        header.ram_size.unwrap_or(0)
+    } else {
+        0
+    };
+    match kind {
+        MapperKind::Mbc1 => Box::new(Mbc1::new(rom, ram_size)),
+        MapperKind::Mbc2 => Box::new(Mbc2::new(rom)),
+        MapperKind::Mbc3 => Box::new(Mbc3::new(rom, ram_size, cartridge_type.timer)),
+        MapperKind::Mbc5 => Box::new(Mbc5::new(rom, ram_size, cartridge_type.rumble)),
+        MapperKind::Mbc7 => Box::new(Mbc7::new(rom)),
+        MapperKind::PocketCamera => Box::new(PocketCamera::new(rom)),
+        MapperKind::HuC1 => Box::new(HuC1::new(rom, ram_size)),
+        MapperKind::HuC3 => Box::new(HuC3::new(rom, ram_size)),
+        MapperKind::Mmm01 => Box::new(Mmm01::new(rom, ram_size)),
+        MapperKind::WisdomTree => Box::new(WisdomTree::new(rom)),
+        MapperKind::SachenMmc1 => Box::new(Sachen::new(rom, false)),
+        MapperKind::SachenMmc2 => Box::new(Sachen::new(rom, true)),
+        MapperKind::None => Box::new(RomOnly::new(rom, ram_size)),
+        _ => unreachable!("{kind} is not a built-in mapper"),
+    }
+}
+
+/// Picks the mapper for `rom`. Unlicensed cartridges often declare no or the wrong mapper in their
+/// header, so they are recognized by other traits first.
+fn detect_mapper(rom: &[u8], header: &CartridgeHeader) -> MapperKind {
+    if mmm01::menu(rom).is_some() {
+        MapperKind::Mmm01
+    } else if sachen::has_scrambled_logo(rom) {
+        // the MMC2 was made for cartridges with CGB support
+        match header.cgb_support {
+            header::CgbSupport::None => MapperKind::SachenMmc1,
+            _ => MapperKind::SachenMmc2,
+        }
+    } else if wisdom_tree::is_wisdom_tree(rom, header) {
+        MapperKind::WisdomTree
+    } else {
+        header.cartridge_type.mapper
+    }
+}
+
+/// Number of ROM banks, rounding partial banks up. Bank numbers wrap around at this count.
+pub fn rom_bank_count(rom: &[u8]) -> usize {
+    rom.len().div_ceil(ROM_BANK_SIZE).max(1)
+}
+
+/// Reads the byte at `address` (in 0x0000-0x7FFF) as if `bank` were mapped there. Bytes missing
+/// from a truncated ROM read as 0xFF.
+pub fn read_rom_bank(rom: &[u8], bank: usize, address: u16) -> u8 {
+    let bank = bank % rom_bank_count(rom);
+    let offset = bank * ROM_BANK_SIZE + (address as usize % ROM_BANK_SIZE);
+    rom.get(offset).copied().unwrap_or(0xFF)
+}
+
+/// Returns the offset into the external RAM of `address` (in 0xA000-0xBFFF) as if `bank` were
+/// mapped there. Bank numbers and addresses wrap around if the RAM is smaller.
+pub fn ram_offset(ram: &[u8], bank: usize, address: u16) -> usize {
+    (bank * RAM_BANK_SIZE + (address as usize % RAM_BANK_SIZE)) % ram.len()
+}
+
+/// Copies as much of `data` into `ram` as fits, for `Mapper::load_battery_data`.
+pub fn load_ram(ram: &mut [u8], data: &[u8]) {
+    let len = ram.len().min(data.len());
+    ram[..len].copy_from_slice(&data[..len]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::{StateReader, StateWriter};
+
+    /// Maps a single byte everywhere.
+    struct ConstantMapper(u8);
+
+    impl Mapper for ConstantMapper {
+        fn read_rom(&self, _address: u16) -> u8 {
+            self.0
+        }
+
+        fn write_rom(&mut self, _address: u16, value: u8) {
+            self.0 = value;
+        }
+
+        fn read_ram(&self, _address: u16) -> u8 {
+            self.0
+        }
+
+        fn write_ram(&mut self, _address: u16, _value: u8) {}
+
+        fn rom_bank(&self, _address: u16) -> u16 {
+            0
+        }
+
+        fn save_state(&self) -> Vec<u8> {
+            StateWriter::default().u8(self.0).finish()
+        }
+
+        fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+            let mut reader = StateReader::new(state);
+            self.0 = reader.u8()?;
+            reader.finish()
+        }
+
+        fn battery_data(&self) -> Vec<u8> {
+            Vec::new()
+        }
+
+        fn load_battery_data(&mut self, _data: &[u8]) {}
+    }
+
+    fn registry() -> MapperRegistry {
+        let mut registry = MapperRegistry::default();
+        registry.register(
+            "Constant",
+            Box::new(|rom, _header| rom[0x0150] == 0x42),
+            Box::new(|_rom, _header| Box::new(ConstantMapper(0x42))),
+        );
+        registry
+    }
+
+    #[test]
+    fn registered_mapper_takes_precedence() {
+        let mut rom = vec![0; 0x8000];
+        let cartridge = Cartridge::with_registry(rom.clone(), &registry(), None).unwrap();
+        assert_eq!(cartridge.read_rom(0x0150), 0x00);
+        rom[0x0150] = 0x42;
+        let mut cartridge = Cartridge::with_registry(rom.clone(), &registry(), None).unwrap();
+        assert_eq!(cartridge.read_rom(0x0000), 0x42);
+
+        let state = cartridge.save_state();
+        cartridge.write_rom(0x0000, 0x01);
+        cartridge.load_state(&state).unwrap();
+        assert_eq!(cartridge.read_rom(0x0000), 0x42);
+
+        let cartridge = Cartridge::with_registry(rom.clone(), &registry(), Some("rom")).unwrap();
+        assert_eq!(cartridge.read_rom(0x0000), 0x00);
+        assert!(Cartridge::with_registry(rom, &registry(), Some("mbc6")).is_err());
+    }
+}
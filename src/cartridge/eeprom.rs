@@ -0,0 +1,303 @@
+use super::state::{StateReader, StateWriter};
+use crate::errors::EmulatorError;
+
+/// Number of 16-bit words of the 93LC56.
+const WORD_COUNT: usize = 128;
+/// Start bit, 2 opcode bits and 8 address bits.
+const COMMAND_BITS: u8 = 11;
+
+/// The 93LC56 serial EEPROM with 2 Kibit organized as 16-bit words, as found on MBC7 cartridges.
+///
+/// Commands are shifted in MSB first on rising clock edges while chip select is high, beginning
+/// with a 1 start bit. Data is shifted out on DO after a dummy 0 bit. Writes complete instantly,
+/// so DO reports ready (1) whenever no data is being read.
+pub struct Eeprom {
+    words: [u16; WORD_COUNT],
+    write_enabled: bool,
+    chip_select: bool,
+    clock: bool,
+    data_out: bool,
+    state: State,
+}
+
+enum State {
+    /// waiting for the start bit
+    Idle,
+    /// shifting in the opcode and address after the start bit
+    Command { bits: u16, count: u8 },
+    /// shifting out the remaining bits of `data`
+    Reading { data: u16, remaining: u8 },
+    /// shifting in the data of a WRITE, or of a WRAL if `address` is `None`
+    Writing {
+        address: Option<usize>,
+        data: u16,
+        count: u8,
+    },
+}
+
+impl Eeprom {
+    pub fn new() -> Self {
+        Eeprom {
+            words: [0xFFFF; WORD_COUNT],
+            write_enabled: false,
+            chip_select: false,
+            clock: false,
+            data_out: true,
+            state: State::Idle,
+        }
+    }
+
+    /// Reads the pins as seen through the MBC7 register: CS in bit 7, CLK in bit 6, DI in bit 1
+    /// and DO in bit 0. DI reads back as DO.
+    pub fn read_pins(&self) -> u8 {
+        let data_out = self.data_out as u8;
+        (self.chip_select as u8) << 7 | (self.clock as u8) << 6 | data_out << 1 | data_out
+    }
+
+    pub fn write_pins(&mut self, value: u8) {
+        let chip_select = value & 0x80 != 0;
+        let clock = value & 0x40 != 0;
+        let data_in = value & 0x02 != 0;
+
+        if !chip_select {
+            self.state = State::Idle;
+            self.data_out = true;
+        } else if clock && !self.clock {
+            self.clock_in(data_in);
+        }
+        self.chip_select = chip_select;
+        self.clock = clock;
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect()
+    }
+
+    pub fn load_bytes(&mut self, data: &[u8]) {
+        for (word, bytes) in self.words.iter_mut().zip(data.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer
+            .bytes(&self.to_bytes())
+            .bool(self.write_enabled)
+            .bool(self.chip_select)
+            .bool(self.clock)
+            .bool(self.data_out);
+        // the state as a tag followed by the bits, data and count fields
+        let (tag, bits, count, address) = match self.state {
+            State::Idle => (0, 0, 0, None),
+            State::Command { bits, count } => (1, bits, count, None),
+            State::Reading { data, remaining } => (2, data, remaining, None),
+            State::Writing {
+                address,
+                data,
+                count,
+            } => (3, data, count, address),
+        };
+        writer
+            .u8(tag)
+            .u16(bits)
+            .u8(count)
+            .u8(address.map_or(0xFF, |address| address as u8));
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        let mut bytes = [0; WORD_COUNT * 2];
+        reader.bytes(&mut bytes)?;
+        self.load_bytes(&bytes);
+        self.write_enabled = reader.bool()?;
+        self.chip_select = reader.bool()?;
+        self.clock = reader.bool()?;
+        self.data_out = reader.bool()?;
+        let (tag, bits, count, address) = (reader.u8()?, reader.u16()?, reader.u8()?, reader.u8()?);
+        self.state = match tag {
+            1 => State::Command { bits, count },
+            2 => State::Reading {
+                data: bits,
+                remaining: count,
+            },
+            3 => State::Writing {
+                address: (address != 0xFF).then_some(address as usize),
+                data: bits,
+                count,
+            },
+            _ => State::Idle,
+        };
+        Ok(())
+    }
+
+    fn clock_in(&mut self, data_in: bool) {
+        self.state = match std::mem::replace(&mut self.state, State::Idle) {
+            State::Idle if data_in => State::Command { bits: 1, count: 1 },
+            State::Idle => State::Idle,
+            State::Command { bits, count } => {
+                let bits = bits << 1 | data_in as u16;
+                if count + 1 == COMMAND_BITS {
+                    self.execute(bits)
+                } else {
+                    State::Command {
+                        bits,
+                        count: count + 1,
+                    }
+                }
+            }
+            State::Reading { data, remaining } => {
+                if remaining == 0 {
+                    self.data_out = true;
+                    return self.clock_in(data_in);
+                }
+                self.data_out = data & 0x8000 != 0;
+                State::Reading {
+                    data: data << 1,
+                    remaining: remaining - 1,
+                }
+            }
+            State::Writing {
+                address,
+                data,
+                count,
+            } => {
+                let data = data << 1 | data_in as u16;
+                if count + 1 < 16 {
+                    State::Writing {
+                        address,
+                        data,
+                        count: count + 1,
+                    }
+                } else {
+                    if self.write_enabled {
+                        match address {
+                            Some(address) => self.words[address] = data,
+                            None => self.words = [data; WORD_COUNT],
+                        }
+                    }
+                    State::Idle
+                }
+            }
+        };
+    }
+
+    /// Executes a complete command, `bits` holding the start bit, opcode and address.
+    fn execute(&mut self, bits: u16) -> State {
+        let opcode = (bits >> 8) & 0b11;
+        let address = (bits & 0xFF) as u8;
+        let word = address as usize % WORD_COUNT;
+        match (opcode, address >> 6) {
+            // READ
+            (0b10, _) => {
+                self.data_out = false;
+                State::Reading {
+                    data: self.words[word],
+                    remaining: 16,
+                }
+            }
+            // WRITE
+            (0b01, _) => State::Writing {
+                address: Some(word),
+                data: 0,
+                count: 0,
+            },
+            // ERASE
+            (0b11, _) => {
+                if self.write_enabled {
+                    self.words[word] = 0xFFFF;
+                }
+                State::Idle
+            }
+            // EWEN
+            (_, 0b11) => {
+                self.write_enabled = true;
+                State::Idle
+            }
+            // EWDS
+            (_, 0b00) => {
+                self.write_enabled = false;
+                State::Idle
+            }
+            // ERAL
+            (_, 0b10) => {
+                if self.write_enabled {
+                    self.words = [0xFFFF; WORD_COUNT];
+                }
+                State::Idle
+            }
+            // WRAL
+            _ => State::Writing {
+                address: None,
+                data: 0,
+                count: 0,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shifts `count` bits of `value` into the EEPROM, MSB first.
+    fn send(eeprom: &mut Eeprom, value: u32, count: u8) {
+        for bit in (0..count).rev() {
+            let data_in = ((value >> bit) & 1) as u8;
+            eeprom.write_pins(0x80 | data_in << 1);
+            eeprom.write_pins(0xC0 | data_in << 1);
+        }
+    }
+
+    fn receive_word(eeprom: &mut Eeprom) -> u16 {
+        let mut word = 0;
+        for _ in 0..16 {
+            eeprom.write_pins(0x80);
+            eeprom.write_pins(0xC0);
+            word = word << 1 | (eeprom.read_pins() & 1) as u16;
+        }
+        word
+    }
+
+    fn deselect(eeprom: &mut Eeprom) {
+        eeprom.write_pins(0x00);
+    }
+
+    #[test]
+    fn write_requires_enable() {
+        let mut eeprom = Eeprom::new();
+        // WRITE 0x1234 to word 5 while writes are disabled
+        send(&mut eeprom, 0b101_0000_0101, 11);
+        send(&mut eeprom, 0x1234, 16);
+        deselect(&mut eeprom);
+        // READ word 5
+        send(&mut eeprom, 0b110_0000_0101, 11);
+        assert_eq!(eeprom.read_pins() & 1, 0);
+        assert_eq!(receive_word(&mut eeprom), 0xFFFF);
+        deselect(&mut eeprom);
+
+        // EWEN, then WRITE again
+        send(&mut eeprom, 0b100_1100_0000, 11);
+        deselect(&mut eeprom);
+        send(&mut eeprom, 0b101_0000_0101, 11);
+        send(&mut eeprom, 0x1234, 16);
+        deselect(&mut eeprom);
+        send(&mut eeprom, 0b110_0000_0101, 11);
+        assert_eq!(receive_word(&mut eeprom), 0x1234);
+        deselect(&mut eeprom);
+        assert_eq!(eeprom.to_bytes()[10..12], [0x34, 0x12]);
+    }
+
+    #[test]
+    fn erase_all() {
+        let mut eeprom = Eeprom::new();
+        eeprom.load_bytes(&[0; 256]);
+        send(&mut eeprom, 0b100_1100_0000, 11);
+        deselect(&mut eeprom);
+        // ERAL
+        send(&mut eeprom, 0b100_1000_0000, 11);
+        deselect(&mut eeprom);
+        assert!(eeprom.to_bytes().iter().all(|&byte| byte == 0xFF));
+    }
+}
@@ -0,0 +1,465 @@
+use std::{fmt, str::FromStr};
+
+use crate::errors::EmulatorError;
+
+/// The header occupies 0x0100-0x014F, so shorter ROMs can not be valid.
+pub const HEADER_END: usize = 0x0150;
+
+/// Location of the Nintendo logo, which the boot ROM compares against its own copy.
+pub const LOGO_START: usize = 0x0104;
+#[rustfmt::skip]
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+const TITLE_START: usize = 0x0134;
+const MANUFACTURER_CODE_START: usize = 0x013F;
+const CGB_FLAG_ADDRESS: usize = 0x0143;
+const NEW_LICENSEE_CODE_START: usize = 0x0144;
+const SGB_FLAG_ADDRESS: usize = 0x0146;
+const CARTRIDGE_TYPE_ADDRESS: usize = 0x0147;
+const ROM_SIZE_ADDRESS: usize = 0x0148;
+const RAM_SIZE_ADDRESS: usize = 0x0149;
+const DESTINATION_ADDRESS: usize = 0x014A;
+const OLD_LICENSEE_CODE_ADDRESS: usize = 0x014B;
+const VERSION_ADDRESS: usize = 0x014C;
+const HEADER_CHECKSUM_ADDRESS: usize = 0x014D;
+const GLOBAL_CHECKSUM_ADDRESS: usize = 0x014E;
+
+/// Old licensee code indicating that the new licensee code is used instead.
+const USE_NEW_LICENSEE_CODE: u8 = 0x33;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    /// made for the DMG, runs in compatibility mode on the CGB
+    None,
+    /// uses CGB features but also runs on the DMG
+    Enhanced,
+    /// only runs on the CGB
+    Only,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+/// The hardware that handles banking, identified by the cartridge type byte. Unlicensed mappers
+/// do not have a cartridge type byte of their own and are only found by heuristics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapperKind {
+    None,
+    Mbc1,
+    Mbc2,
+    Mmm01,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1,
+    WisdomTree,
+    SachenMmc1,
+    SachenMmc2,
+    /// a cartridge type byte without any known meaning
+    Unknown(u8),
+}
+
+impl MapperKind {
+    /// Every mapper except `Unknown`, which can be selected by name.
+    pub const NAMED: [MapperKind; 15] = [
+        MapperKind::None,
+        MapperKind::Mbc1,
+        MapperKind::Mbc2,
+        MapperKind::Mmm01,
+        MapperKind::Mbc3,
+        MapperKind::Mbc5,
+        MapperKind::Mbc6,
+        MapperKind::Mbc7,
+        MapperKind::PocketCamera,
+        MapperKind::BandaiTama5,
+        MapperKind::HuC3,
+        MapperKind::HuC1,
+        MapperKind::WisdomTree,
+        MapperKind::SachenMmc1,
+        MapperKind::SachenMmc2,
+    ];
+}
+
+/// The mapper and additional hardware on the cartridge, decoded from the cartridge type byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub mapper: MapperKind,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+    pub sensor: bool,
+}
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> Self {
+        let cartridge_type = |mapper| CartridgeType {
+            mapper,
+            ram: false,
+            battery: false,
+            timer: false,
+            rumble: false,
+            sensor: false,
+        };
+        let with_ram = |mapper| CartridgeType {
+            ram: true,
+            ..cartridge_type(mapper)
+        };
+        let with_battery = |mapper| CartridgeType {
+            battery: true,
+            ..with_ram(mapper)
+        };
+        match code {
+            0x00 => cartridge_type(MapperKind::None),
+            0x01 => cartridge_type(MapperKind::Mbc1),
+            0x02 => with_ram(MapperKind::Mbc1),
+            0x03 => with_battery(MapperKind::Mbc1),
+            0x05 => cartridge_type(MapperKind::Mbc2),
+            // the RAM of the MBC2 is built into the mapper chip
+            0x06 => CartridgeType {
+                battery: true,
+                ..cartridge_type(MapperKind::Mbc2)
+            },
+            0x08 => with_ram(MapperKind::None),
+            0x09 => with_battery(MapperKind::None),
+            0x0B => cartridge_type(MapperKind::Mmm01),
+            0x0C => with_ram(MapperKind::Mmm01),
+            0x0D => with_battery(MapperKind::Mmm01),
+            0x0F => CartridgeType {
+                battery: true,
+                timer: true,
+                ..cartridge_type(MapperKind::Mbc3)
+            },
+            0x10 => CartridgeType {
+                timer: true,
+                ..with_battery(MapperKind::Mbc3)
+            },
+            0x11 => cartridge_type(MapperKind::Mbc3),
+            0x12 => with_ram(MapperKind::Mbc3),
+            0x13 => with_battery(MapperKind::Mbc3),
+            0x19 => cartridge_type(MapperKind::Mbc5),
+            0x1A => with_ram(MapperKind::Mbc5),
+            0x1B => with_battery(MapperKind::Mbc5),
+            0x1C => CartridgeType {
+                rumble: true,
+                ..cartridge_type(MapperKind::Mbc5)
+            },
+            0x1D => CartridgeType {
+                rumble: true,
+                ..with_ram(MapperKind::Mbc5)
+            },
+            0x1E => CartridgeType {
+                rumble: true,
+                ..with_battery(MapperKind::Mbc5)
+            },
+            0x20 => cartridge_type(MapperKind::Mbc6),
+            0x22 => CartridgeType {
+                rumble: true,
+                sensor: true,
+                ..with_battery(MapperKind::Mbc7)
+            },
+            0xFC => with_battery(MapperKind::PocketCamera),
+            0xFD => cartridge_type(MapperKind::BandaiTama5),
+            0xFE => with_battery(MapperKind::HuC3),
+            0xFF => with_battery(MapperKind::HuC1),
+            _ => cartridge_type(MapperKind::Unknown(code)),
+        }
+    }
+}
+
+impl fmt::Display for MapperKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapperKind::None => write!(f, "ROM"),
+            MapperKind::Mbc1 => write!(f, "MBC1"),
+            MapperKind::Mbc2 => write!(f, "MBC2"),
+            MapperKind::Mmm01 => write!(f, "MMM01"),
+            MapperKind::Mbc3 => write!(f, "MBC3"),
+            MapperKind::Mbc5 => write!(f, "MBC5"),
+            MapperKind::Mbc6 => write!(f, "MBC6"),
+            MapperKind::Mbc7 => write!(f, "MBC7"),
+            MapperKind::PocketCamera => write!(f, "POCKET CAMERA"),
+            MapperKind::BandaiTama5 => write!(f, "BANDAI TAMA5"),
+            MapperKind::HuC3 => write!(f, "HuC3"),
+            MapperKind::HuC1 => write!(f, "HuC1"),
+            MapperKind::WisdomTree => write!(f, "WISDOM TREE"),
+            MapperKind::SachenMmc1 => write!(f, "SACHEN MMC1"),
+            MapperKind::SachenMmc2 => write!(f, "SACHEN MMC2"),
+            MapperKind::Unknown(code) => write!(f, "UNKNOWN (0x{code:02X})"),
+        }
+    }
+}
+
+/// Parses the name of a mapper as displayed, ignoring case and treating hyphens and underscores
+/// as spaces, e.g. "sachen-mmc1".
+impl FromStr for MapperKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let normalize = |name: &str| name.to_ascii_uppercase().replace(['-', '_'], " ");
+        MapperKind::NAMED
+            .into_iter()
+            .find(|kind| normalize(&kind.to_string()) == normalize(name))
+            .ok_or_else(|| {
+                let names: Vec<String> = MapperKind::NAMED
+                    .iter()
+                    .map(|kind| kind.to_string().to_ascii_lowercase().replace(' ', "-"))
+                    .collect();
+                format!("expected one of {}", names.join(", "))
+            })
+    }
+}
+
+/// Formats the cartridge type the way it is commonly listed, e.g. "MBC3+TIMER+RAM+BATTERY".
+impl fmt::Display for CartridgeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mapper)?;
+        let features = [
+            (self.timer, "TIMER"),
+            (self.rumble, "RUMBLE"),
+            (self.sensor, "SENSOR"),
+            (self.ram, "RAM"),
+            (self.battery, "BATTERY"),
+        ];
+        for (_, name) in features.iter().filter(|(present, _)| *present) {
+            write!(f, "+{name}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The cartridge header at 0x0100-0x014F.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    /// upper case ASCII title, at most 16 characters and shorter on newer cartridges
+    pub title: String,
+    /// four character code of newer cartridges
+    pub manufacturer_code: Option<String>,
+    pub cgb_support: CgbSupport,
+    pub sgb_support: bool,
+    pub cartridge_type: CartridgeType,
+    /// size of the ROM in bytes, `None` if the size byte is unknown
+    pub rom_size: Option<usize>,
+    /// size of the external RAM in bytes, `None` if the size byte is unknown
+    pub ram_size: Option<usize>,
+    pub destination: Destination,
+    pub old_licensee_code: u8,
+    /// two character code, only used if the old licensee code is 0x33
+    pub new_licensee_code: Option<String>,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    computed_header_checksum: u8,
+    computed_global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<Self, EmulatorError> {
+        if rom.len() < HEADER_END {
+            return Err(EmulatorError::CartridgeError(format!(
+                "ROM is only {} bytes long, which is too short to contain a cartridge header.",
+                rom.len()
+            )));
+        }
+
+        let cgb_support = match rom[CGB_FLAG_ADDRESS] {
+            0xC0 => CgbSupport::Only,
+            flag if flag & 0x80 != 0 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+
+        // cartridges with CGB support shortened the title to make room for the CGB flag and the
+        // manufacturer code, older cartridges do not have the latter
+        let manufacturer_code = &rom[MANUFACTURER_CODE_START..CGB_FLAG_ADDRESS];
+        let has_manufacturer_code =
+            cgb_support != CgbSupport::None && manufacturer_code.iter().all(u8::is_ascii_uppercase);
+        let title_end = match (has_manufacturer_code, cgb_support) {
+            (true, _) => MANUFACTURER_CODE_START,
+            (false, CgbSupport::None) => NEW_LICENSEE_CODE_START,
+            (false, _) => CGB_FLAG_ADDRESS,
+        };
+
+        let old_licensee_code = rom[OLD_LICENSEE_CODE_ADDRESS];
+        let new_licensee_code = (old_licensee_code == USE_NEW_LICENSEE_CODE)
+            .then(|| ascii_string(&rom[NEW_LICENSEE_CODE_START..SGB_FLAG_ADDRESS]));
+
+        Ok(CartridgeHeader {
+            title: ascii_string(&rom[TITLE_START..title_end]),
+            manufacturer_code: has_manufacturer_code.then(|| ascii_string(manufacturer_code)),
+            cgb_support,
+            // the SGB functions are only available if the old licensee code is 0x33 as well
+            sgb_support: rom[SGB_FLAG_ADDRESS] == 0x03
+                && old_licensee_code == USE_NEW_LICENSEE_CODE,
+            cartridge_type: CartridgeType::from_code(rom[CARTRIDGE_TYPE_ADDRESS]),
+            rom_size: rom_size(rom[ROM_SIZE_ADDRESS]),
+            ram_size: ram_size(rom[RAM_SIZE_ADDRESS]),
+            destination: match rom[DESTINATION_ADDRESS] {
+                0x00 => Destination::Japan,
+                _ => Destination::Overseas,
+            },
+            old_licensee_code,
+            new_licensee_code,
+            version: rom[VERSION_ADDRESS],
+            header_checksum: rom[HEADER_CHECKSUM_ADDRESS],
+            global_checksum: u16::from_be_bytes([
+                rom[GLOBAL_CHECKSUM_ADDRESS],
+                rom[GLOBAL_CHECKSUM_ADDRESS + 1],
+            ]),
+            computed_header_checksum: compute_header_checksum(rom),
+            computed_global_checksum: compute_global_checksum(rom),
+        })
+    }
+
+    /// The boot ROM refuses to start a cartridge whose header checksum does not match.
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    pub fn computed_header_checksum(&self) -> u8 {
+        self.computed_header_checksum
+    }
+
+    /// The global checksum is not verified by the hardware, but a mismatch hints at a bad dump.
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+
+    pub fn computed_global_checksum(&self) -> u16 {
+        self.computed_global_checksum
+    }
+}
+
+/// Interprets `bytes` as a zero-padded ASCII string, replacing unprintable characters.
+fn ascii_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                char::REPLACEMENT_CHARACTER
+            }
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+fn rom_size(code: u8) -> Option<usize> {
+    match code {
+        0x00..=0x08 => Some(0x8000 << code),
+        // sizes only mentioned in unofficial documentation and not used by any known cartridge
+        0x52 => Some(72 * 0x4000),
+        0x53 => Some(80 * 0x4000),
+        0x54 => Some(96 * 0x4000),
+        _ => None,
+    }
+}
+
+fn ram_size(code: u8) -> Option<usize> {
+    match code {
+        0x00 => Some(0),
+        // listed in some unofficial documentation, but not used by any known cartridge
+        0x01 => Some(0x800),
+        0x02 => Some(0x2000),
+        0x03 => Some(0x8000),
+        0x04 => Some(0x20000),
+        0x05 => Some(0x10000),
+        _ => None,
+    }
+}
+
+/// x = 0; for each byte in 0x0134-0x014C: x = x - byte - 1
+fn compute_header_checksum(rom: &[u8]) -> u8 {
+    rom[TITLE_START..HEADER_CHECKSUM_ADDRESS]
+        .iter()
+        .fold(0u8, |checksum, &byte| {
+            checksum.wrapping_sub(byte).wrapping_sub(1)
+        })
+}
+
+/// Sum of all bytes of the ROM except for the two checksum bytes themselves.
+fn compute_global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|&(address, _)| {
+            address != GLOBAL_CHECKSUM_ADDRESS && address != GLOBAL_CHECKSUM_ADDRESS + 1
+        })
+        .fold(0u16, |checksum, (_, &byte)| {
+            checksum.wrapping_add(byte as u16)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom_with_header() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[TITLE_START..TITLE_START + 8].copy_from_slice(b"EMULATOR");
+        rom[CGB_FLAG_ADDRESS] = 0x80;
+        rom[CARTRIDGE_TYPE_ADDRESS] = 0x13;
+        rom[ROM_SIZE_ADDRESS] = 0x01;
+        rom[RAM_SIZE_ADDRESS] = 0x03;
+        rom[DESTINATION_ADDRESS] = 0x01;
+        rom[OLD_LICENSEE_CODE_ADDRESS] = USE_NEW_LICENSEE_CODE;
+        rom[NEW_LICENSEE_CODE_START..SGB_FLAG_ADDRESS].copy_from_slice(b"01");
+        rom[HEADER_CHECKSUM_ADDRESS] = compute_header_checksum(&rom);
+        let [high, low] = compute_global_checksum(&rom).to_be_bytes();
+        rom[GLOBAL_CHECKSUM_ADDRESS] = high;
+        rom[GLOBAL_CHECKSUM_ADDRESS + 1] = low;
+        rom
+    }
+
+    #[test]
+    fn parse_header() {
+        let header = CartridgeHeader::parse(&rom_with_header()).unwrap();
+        assert_eq!(header.title, "EMULATOR");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.cgb_support, CgbSupport::Enhanced);
+        assert!(!header.sgb_support);
+        assert_eq!(header.cartridge_type.mapper, MapperKind::Mbc3);
+        assert!(header.cartridge_type.battery);
+        assert!(!header.cartridge_type.timer);
+        assert_eq!(header.rom_size, Some(0x10000));
+        assert_eq!(header.ram_size, Some(0x8000));
+        assert_eq!(header.destination, Destination::Overseas);
+        assert_eq!(header.new_licensee_code.as_deref(), Some("01"));
+        assert!(header.header_checksum_valid());
+        assert!(header.global_checksum_valid());
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut rom = rom_with_header();
+        rom[VERSION_ADDRESS] = 1;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.header_checksum_valid());
+        assert!(!header.global_checksum_valid());
+    }
+
+    #[test]
+    fn mapper_names() {
+        assert_eq!("mbc5".parse(), Ok(MapperKind::Mbc5));
+        assert_eq!("Sachen_MMC1".parse(), Ok(MapperKind::SachenMmc1));
+        assert_eq!("pocket-camera".parse(), Ok(MapperKind::PocketCamera));
+        assert!("mbc4".parse::<MapperKind>().is_err());
+    }
+
+    #[test]
+    fn too_short() {
+        assert!(CartridgeHeader::parse(&[0; 0x100]).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use super::{
+    load_ram, ram_offset, read_rom_bank,
+    state::{StateReader, StateWriter},
+    CartridgeEvent, Mapper,
+};
+use crate::errors::EmulatorError;
+
+/// Value of the mode register mapping the infrared port instead of the RAM.
+const IR_MODE: u8 = 0x0E;
+/// Infrared port reading without any light received.
+pub const IR_NO_LIGHT: u8 = 0xC0;
+
+/// Hudson's HuC1 mapper with up to 1 MiB of ROM, 32 KiB of RAM and an infrared port.
+///
+/// The register at 0x0000-0x1FFF selects whether 0xA000-0xBFFF maps the RAM or the infrared port.
+/// Bit 0 of the port drives the LED when written and reads whether light is received, which is
+/// never the case without a second console.
+pub struct HuC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ir_mode: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    ir_led: bool,
+    event: Option<CartridgeEvent>,
+}
+
+impl HuC1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        HuC1 {
+            rom,
+            ram: vec![0; ram_size],
+            ir_mode: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            ir_led: false,
+            event: None,
+        }
+    }
+}
+
+impl Mapper for HuC1 {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_rom_bank(&self.rom, self.rom_bank(address) as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ir_mode = value & 0x0F == IR_MODE,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if self.ir_mode {
+            return IR_NO_LIGHT;
+        }
+        if self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[ram_offset(&self.ram, self.ram_bank as usize, address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ir_mode {
+            let active = value & 1 != 0;
+            if active != self.ir_led {
+                self.ir_led = active;
+                self.event = Some(CartridgeEvent::Infrared { active });
+            }
+        } else if !self.ram.is_empty() {
+            let offset = ram_offset(&self.ram, self.ram_bank as usize, address);
+            self.ram[offset] = value;
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> u16 {
+        if address < 0x4000 {
+            0
+        } else {
+            self.rom_bank as u16
+        }
+    }
+
+    fn ram_bank(&self) -> u16 {
+        self.ram_bank as u16
+    }
+
+    fn take_event(&mut self) -> Option<CartridgeEvent> {
+        self.event.take()
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        writer.bytes(&self.ram);
+        writer.bool(self.ir_mode);
+        writer.u8(self.rom_bank);
+        writer.u8(self.ram_bank);
+        writer.bool(self.ir_led);
+        writer.finish()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+        let mut reader = StateReader::new(state);
+        reader.bytes(&mut self.ram)?;
+        self.ir_mode = reader.bool()?;
+        self.rom_bank = reader.u8()?;
+        self.ram_bank = reader.u8()?;
+        self.ir_led = reader.bool()?;
+        reader.finish()
+    }
+
+    fn battery_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infrared_mode() {
+        let mut huc1 = HuC1::new(vec![0; 0x8000], 0x2000);
+        huc1.write_ram(0xA000, 0x42);
+        huc1.write_rom(0x0000, IR_MODE);
+        assert_eq!(huc1.read_ram(0xA000), IR_NO_LIGHT);
+        huc1.write_ram(0xA000, 0x01);
+        assert_eq!(
+            huc1.take_event(),
+            Some(CartridgeEvent::Infrared { active: true })
+        );
+        huc1.write_rom(0x0000, 0x00);
+        assert_eq!(huc1.read_ram(0xA000), 0x42);
+    }
+}
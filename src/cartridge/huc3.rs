@@ -0,0 +1,312 @@
+use super::{
+    huc1::IR_NO_LIGHT,
+    load_ram, ram_offset, read_rom_bank,
+    rtc::unix_time,
+    state::{StateReader, StateWriter},
+    CartridgeEvent, Mapper,
+};
+use crate::errors::EmulatorError;
+use crate::gameboy::CYCLES_PER_SECOND;
+
+/// Length of the clock state appended to the save RAM: minutes and days as 32-bit words followed
+/// by a 64-bit UNIX timestamp, all little endian.
+const CLOCK_SAVE_SIZE: usize = 16;
+const MINUTES_PER_DAY: u16 = 24 * 60;
+const CYCLES_PER_MINUTE: u64 = 60 * CYCLES_PER_SECOND;
+
+/// Address of the tone number in the nibble memory of the RTC chip.
+const TONE_ADDRESS: usize = 0x26;
+
+/// Hudson's HuC3 mapper with up to 2 MiB of ROM, 32 KiB of RAM, an infrared port and a
+/// real-time clock chip with a speaker.
+///
+/// The register at 0x0000-0x1FFF selects what 0xA000-0xBFFF maps: the RAM (read-only with 0x0,
+/// writable with 0xA), the command register of the clock chip (0xB), its response (0xC), its ready
+/// flag (0xD) or the infrared port (0xE). The clock chip has 256 nibbles of memory accessed
+/// through an index, and extended commands copy the time between the clock and the memory.
+pub struct HuC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mode: u8,
+    rom_bank: u8,
+    ram_bank: u8,
+    clock: Clock,
+    /// nibble memory of the clock chip
+    memory: [u8; 0x100],
+    memory_index: u8,
+    /// result of the last command, its opcode in bits 4-6 and the value in bits 0-3
+    response: u8,
+    ir_led: bool,
+    event: Option<CartridgeEvent>,
+}
+
+/// The clock counts minutes of the day and 4096 days.
+#[derive(Default)]
+struct Clock {
+    minutes: u16,
+    days: u16,
+    subminute_cycles: u64,
+}
+
+impl Clock {
+    fn tick(&mut self) {
+        self.subminute_cycles += 1;
+        if self.subminute_cycles == CYCLES_PER_MINUTE {
+            self.subminute_cycles = 0;
+            self.advance_minutes(1);
+        }
+    }
+
+    fn advance_seconds(&mut self, seconds: u64) {
+        let cycles = self.subminute_cycles + seconds % 60 * CYCLES_PER_SECOND;
+        self.subminute_cycles = cycles % CYCLES_PER_MINUTE;
+        self.advance_minutes(seconds / 60 + cycles / CYCLES_PER_MINUTE);
+    }
+
+    fn advance_minutes(&mut self, minutes: u64) {
+        let total = self.minutes as u64 + minutes;
+        self.minutes = (total % MINUTES_PER_DAY as u64) as u16;
+        self.days = ((self.days as u64 + total / MINUTES_PER_DAY as u64) & 0xFFF) as u16;
+    }
+}
+
+impl HuC3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        HuC3 {
+            rom,
+            ram: vec![0; ram_size],
+            mode: 0,
+            rom_bank: 1,
+            ram_bank: 0,
+            clock: Clock::default(),
+            memory: [0; 0x100],
+            memory_index: 0,
+            response: 0,
+            ir_led: false,
+            event: None,
+        }
+    }
+
+    fn execute(&mut self, command: u8) {
+        let opcode = (command >> 4) & 0x07;
+        let argument = command & 0x0F;
+        let mut result = 0;
+        match opcode {
+            // read and increment
+            0x1 => {
+                result = self.memory[self.memory_index as usize];
+                self.memory_index = self.memory_index.wrapping_add(1);
+            }
+            // write and increment
+            0x3 => {
+                self.memory[self.memory_index as usize] = argument;
+                self.memory_index = self.memory_index.wrapping_add(1);
+            }
+            0x4 => self.memory_index = (self.memory_index & 0xF0) | argument,
+            0x5 => self.memory_index = (self.memory_index & 0x0F) | argument << 4,
+            0x6 => result = self.execute_extended(argument),
+            _ => {}
+        }
+        self.response = opcode << 4 | result;
+    }
+
+    fn execute_extended(&mut self, argument: u8) -> u8 {
+        match argument {
+            // copy the time to the memory
+            0x0 => {
+                let time = self.clock.minutes as u32 | (self.clock.days as u32) << 12;
+                for (index, nibble) in self.memory[..6].iter_mut().enumerate() {
+                    *nibble = ((time >> (4 * index)) & 0x0F) as u8;
+                }
+                0
+            }
+            // set the time from the memory
+            0x1 => {
+                let time = self.memory[..6]
+                    .iter()
+                    .enumerate()
+                    .fold(0, |time, (index, &nibble)| {
+                        time | (nibble as u32) << (4 * index)
+                    });
+                self.clock.minutes = (time & 0xFFF) as u16 % MINUTES_PER_DAY;
+                self.clock.days = (time >> 12) as u16;
+                self.clock.subminute_cycles = 0;
+                0
+            }
+            // status, the clock is always ready
+            0x2 => 1,
+            0xE => {
+                self.event = Some(CartridgeEvent::Tone {
+                    tone: self.memory[TONE_ADDRESS] & 0x03,
+                });
+                0
+            }
+            _ => 0,
+        }
+    }
+}
+
+impl Mapper for HuC3 {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_rom_bank(&self.rom, self.rom_bank(address) as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.mode = value & 0x0F,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        match self.mode {
+            0x0 | 0xA if !self.ram.is_empty() => {
+                self.ram[ram_offset(&self.ram, self.ram_bank as usize, address)]
+            }
+            0xC => 0x80 | self.response,
+            0xD => 0xFF,
+            0xE => IR_NO_LIGHT,
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        match self.mode {
+            0xA if !self.ram.is_empty() => {
+                let offset = ram_offset(&self.ram, self.ram_bank as usize, address);
+                self.ram[offset] = value;
+            }
+            0xB => self.execute(value),
+            0xE => {
+                let active = value & 1 != 0;
+                if active != self.ir_led {
+                    self.ir_led = active;
+                    self.event = Some(CartridgeEvent::Infrared { active });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> u16 {
+        if address < 0x4000 {
+            0
+        } else {
+            self.rom_bank as u16
+        }
+    }
+
+    fn ram_bank(&self) -> u16 {
+        self.ram_bank as u16
+    }
+
+    fn tick(&mut self) {
+        self.clock.tick();
+    }
+
+    fn take_event(&mut self) -> Option<CartridgeEvent> {
+        self.event.take()
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        writer.bytes(&self.ram);
+        writer.u8(self.mode);
+        writer.u8(self.rom_bank);
+        writer.u8(self.ram_bank);
+        writer.u16(self.clock.minutes);
+        writer.u16(self.clock.days);
+        writer.u64(self.clock.subminute_cycles);
+        writer.bytes(&self.memory);
+        writer.u8(self.memory_index);
+        writer.u8(self.response);
+        writer.bool(self.ir_led);
+        writer.finish()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+        let mut reader = StateReader::new(state);
+        reader.bytes(&mut self.ram)?;
+        self.mode = reader.u8()?;
+        self.rom_bank = reader.u8()?;
+        self.ram_bank = reader.u8()?;
+        self.clock.minutes = reader.u16()?;
+        self.clock.days = reader.u16()?;
+        self.clock.subminute_cycles = reader.u64()?;
+        reader.bytes(&mut self.memory)?;
+        self.memory_index = reader.u8()?;
+        self.response = reader.u8()?;
+        self.ir_led = reader.bool()?;
+        reader.finish()
+    }
+
+    /// The clock state follows the RAM, see `CLOCK_SAVE_SIZE`.
+    fn battery_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        data.extend_from_slice(&(self.clock.minutes as u32).to_le_bytes());
+        data.extend_from_slice(&(self.clock.days as u32).to_le_bytes());
+        data.extend_from_slice(&unix_time().to_le_bytes());
+        data
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        let ram_len = self.ram.len().min(data.len());
+        load_ram(&mut self.ram, &data[..ram_len]);
+        let Some(clock) = data[ram_len..].get(..CLOCK_SAVE_SIZE) else {
+            return;
+        };
+        let word = |index: usize| u32::from_le_bytes(clock[index..index + 4].try_into().unwrap());
+        self.clock.minutes = (word(0) as u16) % MINUTES_PER_DAY;
+        self.clock.days = (word(4) & 0xFFF) as u16;
+        let saved_at = u64::from_le_bytes(clock[8..16].try_into().unwrap());
+        self.clock
+            .advance_seconds(unix_time().saturating_sub(saved_at));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(huc3: &mut HuC3, value: u8) -> u8 {
+        huc3.write_rom(0x0000, 0x0B);
+        huc3.write_ram(0xA000, value);
+        huc3.write_rom(0x0000, 0x0C);
+        huc3.read_ram(0xA000)
+    }
+
+    #[test]
+    fn set_and_read_time() {
+        let mut huc3 = HuC3::new(vec![0; 0x8000], 0x2000);
+        // 0x005 days and 0x123 minutes
+        command(&mut huc3, 0x40);
+        command(&mut huc3, 0x50);
+        for nibble in [0x3, 0x2, 0x1, 0x5, 0x0, 0x0] {
+            command(&mut huc3, 0x30 | nibble);
+        }
+        command(&mut huc3, 0x61);
+        huc3.clock.advance_seconds(60);
+
+        command(&mut huc3, 0x60);
+        command(&mut huc3, 0x40);
+        let nibbles: Vec<u8> = (0..6).map(|_| command(&mut huc3, 0x10) & 0x0F).collect();
+        assert_eq!(nibbles, [0x4, 0x2, 0x1, 0x5, 0x0, 0x0]);
+        assert_eq!(command(&mut huc3, 0x62), 0xE1);
+    }
+
+    #[test]
+    fn clock_persists() {
+        let mut huc3 = HuC3::new(vec![0; 0x8000], 0x2000);
+        huc3.clock.minutes = MINUTES_PER_DAY - 1;
+        let mut data = huc3.battery_data();
+        assert_eq!(data.len(), 0x2000 + CLOCK_SAVE_SIZE);
+        let saved_at = unix_time() - 60;
+        data[0x2008..].copy_from_slice(&saved_at.to_le_bytes());
+
+        let mut restored = HuC3::new(vec![0; 0x8000], 0x2000);
+        restored.load_battery_data(&data);
+        assert_eq!((restored.clock.minutes, restored.clock.days), (0, 1));
+    }
+}
@@ -0,0 +1,206 @@
+use super::{
+    header::{LOGO_START, NINTENDO_LOGO},
+    load_ram, ram_offset, read_rom_bank,
+    state::{StateReader, StateWriter},
+    Mapper,
+};
+use crate::errors::EmulatorError;
+
+/// Multicarts consist of four 256 KiB games in a 1 MiB ROM.
+const MULTICART_ROM_SIZE: usize = 0x100000;
+const MULTICART_GAME_SIZE: usize = 0x40000;
+
+/// The MBC1 mapper with up to 2 MiB of ROM and 32 KiB of RAM.
+///
+/// BANK1 selects the lower 5 bits of the ROM bank mapped at 0x4000-0x7FFF, BANK2 either the upper 2
+/// bits of the ROM bank or the RAM bank. A zero in BANK1 is translated to one before combining it
+/// with BANK2, which is why banks 0x20, 0x40 and 0x60 can not be mapped to 0x4000-0x7FFF.
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    /// BANK1 register, never zero
+    bank1: u8,
+    /// BANK2 register
+    bank2: u8,
+    /// banking mode 1, in which BANK2 also applies to 0x0000-0x3FFF and the RAM
+    advanced_banking: bool,
+    /// MBC1M wiring, where BANK2 is shifted by 4 instead of 5 and bit 4 of BANK1 is unconnected
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        let multicart = is_multicart(&rom);
+        Mbc1 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            advanced_banking: false,
+            multicart,
+        }
+    }
+
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+}
+
+impl Mapper for Mbc1 {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_rom_bank(&self.rom, self.rom_bank(address) as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.bank1 = (value & 0x1F).max(1),
+            0x4000..=0x5FFF => self.bank2 = value & 0b11,
+            _ => self.advanced_banking = value & 1 != 0,
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[ram_offset(&self.ram, self.ram_bank() as usize, address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled && !self.ram.is_empty() {
+            let offset = ram_offset(&self.ram, self.ram_bank() as usize, address);
+            self.ram[offset] = value;
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> u16 {
+        let upper_bits = (self.bank2 << self.bank2_shift()) as u16;
+        if address < 0x4000 {
+            if self.advanced_banking {
+                upper_bits
+            } else {
+                0
+            }
+        } else {
+            let bank1_mask = if self.multicart { 0x0F } else { 0x1F };
+            upper_bits | (self.bank1 & bank1_mask) as u16
+        }
+    }
+
+    fn ram_bank(&self) -> u16 {
+        if self.advanced_banking {
+            self.bank2 as u16
+        } else {
+            0
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        writer.bytes(&self.ram);
+        writer.bool(self.ram_enabled);
+        writer.u8(self.bank1);
+        writer.u8(self.bank2);
+        writer.bool(self.advanced_banking);
+        writer.finish()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+        let mut reader = StateReader::new(state);
+        reader.bytes(&mut self.ram)?;
+        self.ram_enabled = reader.bool()?;
+        self.bank1 = reader.u8()?;
+        self.bank2 = reader.u8()?;
+        self.advanced_banking = reader.bool()?;
+        reader.finish()
+    }
+
+    fn battery_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+/// The header does not distinguish multicarts from regular MBC1 cartridges, but every game on a
+/// multicart has its own header including the Nintendo logo.
+fn is_multicart(rom: &[u8]) -> bool {
+    if rom.len() != MULTICART_ROM_SIZE {
+        return false;
+    }
+    let logo_count = (0..MULTICART_ROM_SIZE)
+        .step_by(MULTICART_GAME_SIZE)
+        .filter(|game_start| {
+            let logo_start = game_start + LOGO_START;
+            rom[logo_start..logo_start + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+        })
+        .count();
+    logo_count > 1
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::ROM_BANK_SIZE;
+
+    use super::*;
+
+    /// Creates a ROM of `size` bytes whose banks start with their bank number.
+    fn numbered_rom(size: usize) -> Vec<u8> {
+        let mut rom = vec![0; size];
+        for (bank, bank_data) in rom.chunks_mut(ROM_BANK_SIZE).enumerate() {
+            bank_data[0] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn bank_zero_quirk() {
+        let mut mbc1 = Mbc1::new(numbered_rom(0x200000), 0);
+        assert_eq!(mbc1.read_rom(0x4000), 1);
+        mbc1.write_rom(0x2000, 0x00);
+        assert_eq!(mbc1.read_rom(0x4000), 1);
+        mbc1.write_rom(0x4000, 0x01);
+        assert_eq!(mbc1.read_rom(0x4000), 0x21);
+        assert_eq!(mbc1.read_rom(0x0000), 0x00);
+        mbc1.write_rom(0x6000, 0x01);
+        assert_eq!(mbc1.read_rom(0x0000), 0x20);
+    }
+
+    #[test]
+    fn ram_banking() {
+        let mut mbc1 = Mbc1::new(numbered_rom(0x8000), 0x8000);
+        mbc1.write_ram(0xA000, 0x42);
+        assert_eq!(mbc1.read_ram(0xA000), 0xFF);
+        mbc1.write_rom(0x0000, 0x0A);
+        mbc1.write_ram(0xA000, 0x42);
+        mbc1.write_rom(0x4000, 0x02);
+        assert_eq!(mbc1.read_ram(0xA000), 0x42);
+        mbc1.write_rom(0x6000, 0x01);
+        assert_eq!(mbc1.read_ram(0xA000), 0x00);
+    }
+
+    #[test]
+    fn multicart() {
+        let mut rom = numbered_rom(MULTICART_ROM_SIZE);
+        for game_start in [0, MULTICART_GAME_SIZE] {
+            let logo_start = game_start + LOGO_START;
+            rom[logo_start..logo_start + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        }
+        let mut mbc1 = Mbc1::new(rom, 0);
+        assert!(mbc1.multicart);
+        mbc1.write_rom(0x4000, 0x01);
+        mbc1.write_rom(0x2000, 0x12);
+        assert_eq!(mbc1.read_rom(0x4000), 0x12);
+        mbc1.write_rom(0x6000, 0x01);
+        assert_eq!(mbc1.read_rom(0x0000), 0x10);
+    }
+}
@@ -0,0 +1,128 @@
+use super::{
+    read_rom_bank,
+    state::{StateReader, StateWriter},
+    Mapper,
+};
+use crate::errors::EmulatorError;
+
+/// Number of 4-bit cells of the RAM built into the MBC2.
+const RAM_SIZE: usize = 512;
+
+/// The MBC2 mapper with up to 256 KiB of ROM and 512 half-bytes of built-in RAM.
+///
+/// Both registers are in 0x0000-0x3FFF, address bit 8 selects between the RAM enable (clear) and
+/// the ROM bank register (set). The RAM only uses the lower 9 address bits, so it repeats
+/// throughout 0xA000-0xBFFF, and the upper 4 bits of every byte are unconnected and read as 1.
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    /// one cell per byte, only the lower 4 bits are used
+    ram: [u8; RAM_SIZE],
+    ram_enabled: bool,
+    /// never zero
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        Mbc2 {
+            rom,
+            ram: [0; RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Mapper for Mbc2 {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_rom_bank(&self.rom, self.rom_bank(address) as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        if address >= 0x4000 {
+            return;
+        }
+        if address & 0x0100 == 0 {
+            self.ram_enabled = value & 0x0F == 0x0A;
+        } else {
+            self.rom_bank = (value & 0x0F).max(1);
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        0xF0 | self.ram[address as usize % RAM_SIZE]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled {
+            self.ram[address as usize % RAM_SIZE] = value & 0x0F;
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> u16 {
+        if address < 0x4000 {
+            0
+        } else {
+            self.rom_bank as u16
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        writer.bytes(&self.ram);
+        writer.bool(self.ram_enabled);
+        writer.u8(self.rom_bank);
+        writer.finish()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+        let mut reader = StateReader::new(state);
+        reader.bytes(&mut self.ram)?;
+        self.ram_enabled = reader.bool()?;
+        self.rom_bank = reader.u8()?;
+        reader.finish()
+    }
+
+    fn battery_data(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        for (cell, byte) in self.ram.iter_mut().zip(data) {
+            *cell = byte & 0x0F;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_select_by_address_bit_8() {
+        let mut rom = vec![0; 0x40000];
+        rom[0x3 * 0x4000] = 0x33;
+        let mut mbc2 = Mbc2::new(rom);
+        // bit 8 clear, enables RAM instead of switching banks
+        mbc2.write_rom(0x2003, 0x0A);
+        assert_eq!(mbc2.rom_bank(0x4000), 1);
+        mbc2.write_rom(0x2103, 0x03);
+        assert_eq!(mbc2.read_rom(0x4000), 0x33);
+        mbc2.write_rom(0x0100, 0x00);
+        assert_eq!(mbc2.rom_bank(0x4000), 1);
+    }
+
+    #[test]
+    fn half_byte_ram_echoes() {
+        let mut mbc2 = Mbc2::new(vec![0; 0x8000]);
+        assert_eq!(mbc2.read_ram(0xA000), 0xFF);
+        mbc2.write_rom(0x0000, 0x0A);
+        mbc2.write_ram(0xA001, 0x5A);
+        assert_eq!(mbc2.read_ram(0xA001), 0xFA);
+        assert_eq!(mbc2.read_ram(0xA201), 0xFA);
+        assert_eq!(mbc2.read_ram(0xBE01), 0xFA);
+    }
+}
@@ -0,0 +1,220 @@
+use super::{
+    load_ram, ram_offset, read_rom_bank,
+    rtc::{Rtc, RTC_SAVE_SIZE},
+    state::{StateReader, StateWriter},
+    Mapper,
+};
+use crate::errors::EmulatorError;
+
+/// The MBC3 mapper with up to 2 MiB of ROM, 32 KiB of RAM and an optional real-time clock.
+///
+/// The RAM bank register either maps a RAM bank or one of the RTC registers 0x08-0x0C to
+/// 0xA000-0xBFFF. MBC30 variants with 4 MiB of ROM and 64 KiB of RAM are covered by not masking
+/// the bank numbers further than the ROM and RAM sizes do.
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rtc: Option<Rtc>,
+    /// enables both the RAM and the RTC registers
+    ram_enabled: bool,
+    /// never zero
+    rom_bank: u8,
+    /// RAM bank 0x00-0x07 or RTC register 0x08-0x0C
+    ram_bank: u8,
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rtc: bool) -> Self {
+        Mbc3 {
+            rom,
+            ram: vec![0; ram_size],
+            rtc: has_rtc.then(Rtc::default),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+
+    /// Returns the RTC if one of its registers is mapped.
+    fn mapped_rtc(&self) -> Option<&Rtc> {
+        self.rtc
+            .as_ref()
+            .filter(|_| (0x08..=0x0C).contains(&self.ram_bank))
+    }
+}
+
+impl Mapper for Mbc3 {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_rom_bank(&self.rom, self.rom_bank(address) as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value.max(1),
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(value);
+                }
+            }
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        if let Some(rtc) = self.mapped_rtc() {
+            return rtc.read(self.ram_bank);
+        }
+        if self.ram_bank >= 0x08 || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[ram_offset(&self.ram, self.ram_bank as usize, address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if self.mapped_rtc().is_some() {
+            if let Some(rtc) = &mut self.rtc {
+                rtc.write(self.ram_bank, value);
+            }
+        } else if self.ram_bank < 0x08 && !self.ram.is_empty() {
+            let offset = ram_offset(&self.ram, self.ram_bank as usize, address);
+            self.ram[offset] = value;
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> u16 {
+        if address < 0x4000 {
+            0
+        } else {
+            self.rom_bank as u16
+        }
+    }
+
+    fn ram_bank(&self) -> u16 {
+        self.ram_bank as u16
+    }
+
+    fn tick(&mut self) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick();
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        writer.bytes(&self.ram);
+        writer.bool(self.ram_enabled);
+        writer.u8(self.rom_bank);
+        writer.u8(self.ram_bank);
+        if let Some(rtc) = &self.rtc {
+            rtc.save_state(&mut writer);
+        }
+        writer.finish()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+        let mut reader = StateReader::new(state);
+        reader.bytes(&mut self.ram)?;
+        self.ram_enabled = reader.bool()?;
+        self.rom_bank = reader.u8()?;
+        self.ram_bank = reader.u8()?;
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load_state(&mut reader)?;
+        }
+        reader.finish()
+    }
+
+    /// The RTC state follows the RAM, see `RTC_SAVE_SIZE`.
+    fn battery_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(rtc) = &self.rtc {
+            data.extend(rtc.save());
+        }
+        data
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        let ram_len = self.ram.len().min(data.len());
+        load_ram(&mut self.ram, &data[..ram_len]);
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load(&data[ram_len..data.len().min(ram_len + RTC_SAVE_SIZE)]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rom_and_ram_banking() {
+        let mut rom = vec![0; 0x200000];
+        rom[0x7F * 0x4000] = 0x7F;
+        let mut mbc3 = Mbc3::new(rom, 0x8000, false);
+        mbc3.write_rom(0x2000, 0x7F);
+        assert_eq!(mbc3.read_rom(0x4000), 0x7F);
+        mbc3.write_rom(0x2000, 0x00);
+        assert_eq!(mbc3.rom_bank(0x4000), 1);
+
+        mbc3.write_rom(0x0000, 0x0A);
+        mbc3.write_rom(0x4000, 0x03);
+        mbc3.write_ram(0xA000, 0x42);
+        mbc3.write_rom(0x4000, 0x00);
+        assert_eq!(mbc3.read_ram(0xA000), 0x00);
+        mbc3.write_rom(0x4000, 0x03);
+        assert_eq!(mbc3.read_ram(0xA000), 0x42);
+        // no RTC on this cartridge
+        mbc3.write_rom(0x4000, 0x08);
+        assert_eq!(mbc3.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn rtc_registers() {
+        let mut mbc3 = Mbc3::new(vec![0; 0x8000], 0x2000, true);
+        mbc3.write_rom(0x0000, 0x0A);
+        mbc3.write_rom(0x4000, 0x09);
+        mbc3.write_ram(0xA000, 42);
+        mbc3.write_rom(0x6000, 0x00);
+        mbc3.write_rom(0x6000, 0x01);
+        assert_eq!(mbc3.read_ram(0xA000), 42);
+
+        let data = mbc3.battery_data();
+        assert_eq!(data.len(), 0x2000 + RTC_SAVE_SIZE);
+        let mut restored = Mbc3::new(vec![0; 0x8000], 0x2000, true);
+        restored.load_battery_data(&data);
+        restored.write_rom(0x0000, 0x0A);
+        restored.write_rom(0x4000, 0x09);
+        assert_eq!(restored.read_ram(0xA000), 42);
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let mut mbc3 = Mbc3::new(vec![0; 0x10000], 0x8000, true);
+        mbc3.write_rom(0x0000, 0x0A);
+        mbc3.write_rom(0x2000, 0x03);
+        mbc3.write_rom(0x4000, 0x02);
+        mbc3.write_ram(0xA000, 0x42);
+        mbc3.write_rom(0x4000, 0x0A);
+        mbc3.write_ram(0xA000, 5);
+        mbc3.write_rom(0x6000, 0x00);
+        let state = mbc3.save_state();
+
+        let mut restored = Mbc3::new(vec![0; 0x10000], 0x8000, true);
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.rom_bank(0x4000), 3);
+        // the armed latch is part of the state
+        restored.write_rom(0x6000, 0x01);
+        assert_eq!(restored.read_ram(0xA000), 5);
+        restored.write_rom(0x4000, 0x02);
+        assert_eq!(restored.read_ram(0xA000), 0x42);
+
+        let mut without_rtc = Mbc3::new(vec![0; 0x10000], 0x8000, false);
+        assert!(without_rtc.load_state(&state).is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use super::{
+    load_ram, ram_offset, read_rom_bank,
+    state::{StateReader, StateWriter},
+    CartridgeEvent, Mapper,
+};
+use crate::errors::EmulatorError;
+
+/// The MBC5 mapper with up to 8 MiB of ROM and 128 KiB of RAM.
+///
+/// Unlike on older mappers, bank 0 can be mapped to 0x4000-0x7FFF. On cartridges with a rumble
+/// motor, bit 3 of the RAM bank register drives the motor instead of selecting a RAM bank.
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    has_rumble: bool,
+    ram_enabled: bool,
+    /// 9 bits
+    rom_bank: u16,
+    ram_bank: u8,
+    rumble_active: bool,
+    event: Option<CartridgeEvent>,
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> Self {
+        Mbc5 {
+            rom,
+            ram: vec![0; ram_size],
+            has_rumble,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rumble_active: false,
+            event: None,
+        }
+    }
+}
+
+impl Mapper for Mbc5 {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_rom_bank(&self.rom, self.rom_bank(address) as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((value as u16 & 1) << 8),
+            0x4000..=0x5FFF if self.has_rumble => {
+                self.ram_bank = value & 0x07;
+                let active = value & 0x08 != 0;
+                if active != self.rumble_active {
+                    self.rumble_active = active;
+                    self.event = Some(CartridgeEvent::Rumble { active });
+                }
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[ram_offset(&self.ram, self.ram_bank as usize, address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled && !self.ram.is_empty() {
+            let offset = ram_offset(&self.ram, self.ram_bank as usize, address);
+            self.ram[offset] = value;
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> u16 {
+        if address < 0x4000 {
+            0
+        } else {
+            self.rom_bank
+        }
+    }
+
+    fn ram_bank(&self) -> u16 {
+        self.ram_bank as u16
+    }
+
+    fn take_event(&mut self) -> Option<CartridgeEvent> {
+        self.event.take()
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        writer.bytes(&self.ram);
+        writer.bool(self.ram_enabled);
+        writer.u16(self.rom_bank);
+        writer.u8(self.ram_bank);
+        writer.bool(self.rumble_active);
+        writer.finish()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+        let mut reader = StateReader::new(state);
+        reader.bytes(&mut self.ram)?;
+        self.ram_enabled = reader.bool()?;
+        self.rom_bank = reader.u16()?;
+        self.ram_bank = reader.u8()?;
+        self.rumble_active = reader.bool()?;
+        reader.finish()
+    }
+
+    fn battery_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nine_bit_rom_bank() {
+        let mut rom = vec![0; 0x800000];
+        rom[0x1FF * 0x4000] = 0xFF;
+        rom[0x4000] = 0x01;
+        let mut mbc5 = Mbc5::new(rom, 0, false);
+        mbc5.write_rom(0x2000, 0xFF);
+        mbc5.write_rom(0x3000, 0x01);
+        assert_eq!(mbc5.read_rom(0x4000), 0xFF);
+        mbc5.write_rom(0x2000, 0x00);
+        mbc5.write_rom(0x3000, 0x00);
+        assert_eq!(mbc5.rom_bank(0x4000), 0);
+        assert_eq!(mbc5.read_rom(0x4000), 0x00);
+    }
+
+    #[test]
+    fn rumble_uses_ram_bank_bit_3() {
+        let mut mbc5 = Mbc5::new(vec![0; 0x8000], 0x20000, true);
+        mbc5.write_rom(0x0000, 0x0A);
+        mbc5.write_ram(0xA000, 0x42);
+        mbc5.write_rom(0x4000, 0x08);
+        assert_eq!(
+            mbc5.take_event(),
+            Some(CartridgeEvent::Rumble { active: true })
+        );
+        assert_eq!(mbc5.read_ram(0xA000), 0x42);
+        mbc5.write_rom(0x4000, 0x08);
+        assert_eq!(mbc5.take_event(), None);
+        mbc5.write_rom(0x4000, 0x00);
+        assert_eq!(
+            mbc5.take_event(),
+            Some(CartridgeEvent::Rumble { active: false })
+        );
+    }
+}
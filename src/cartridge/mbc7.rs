@@ -0,0 +1,199 @@
+use super::{
+    eeprom::Eeprom,
+    read_rom_bank,
+    state::{StateReader, StateWriter},
+    Mapper,
+};
+use crate::errors::EmulatorError;
+
+/// Accelerometer reading when the cartridge is held level.
+const ACCELEROMETER_CENTER: f32 = 0x81D0 as f32;
+/// Change of the accelerometer reading per g of acceleration.
+const ACCELEROMETER_PER_G: f32 = 0x70 as f32;
+/// Accelerometer reading after erasing the latched values.
+const ACCELEROMETER_ERASED: u16 = 0x8000;
+
+/// The MBC7 mapper with up to 2 MiB of ROM, a two-axis accelerometer and a 93LC56 EEPROM instead
+/// of RAM.
+///
+/// The registers at 0xA000-0xAFFF are only accessible after enabling them with 0x0A at
+/// 0x0000-0x1FFF and 0x40 at 0x4000-0x5FFF, and repeat every 0x100 bytes with address bits 4-7
+/// selecting the register.
+pub struct Mbc7 {
+    rom: Vec<u8>,
+    eeprom: Eeprom,
+    ram_enabled: bool,
+    registers_enabled: bool,
+    rom_bank: u8,
+    /// current tilt in g, positive x being right and positive y being down
+    tilt: (f32, f32),
+    latched_x: u16,
+    latched_y: u16,
+    /// whether the latched values have been erased, which arms the latch
+    latch_erased: bool,
+}
+
+impl Mbc7 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        Mbc7 {
+            rom,
+            eeprom: Eeprom::new(),
+            ram_enabled: false,
+            registers_enabled: false,
+            rom_bank: 1,
+            tilt: (0.0, 0.0),
+            latched_x: ACCELEROMETER_ERASED,
+            latched_y: ACCELEROMETER_ERASED,
+            latch_erased: false,
+        }
+    }
+
+    fn accessible(&self) -> bool {
+        self.ram_enabled && self.registers_enabled
+    }
+
+    fn latch_accelerometer(&mut self) {
+        let reading = |tilt: f32| (ACCELEROMETER_CENTER + tilt * ACCELEROMETER_PER_G) as u16;
+        // tilting right lowers the X reading
+        self.latched_x = reading(-self.tilt.0);
+        self.latched_y = reading(self.tilt.1);
+    }
+}
+
+impl Mapper for Mbc7 {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_rom_bank(&self.rom, self.rom_bank(address) as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value,
+            0x4000..=0x5FFF => self.registers_enabled = value == 0x40,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.accessible() || address >= 0xB000 {
+            return 0xFF;
+        }
+        match (address >> 4) & 0x0F {
+            0x2 => self.latched_x as u8,
+            0x3 => (self.latched_x >> 8) as u8,
+            0x4 => self.latched_y as u8,
+            0x5 => (self.latched_y >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read_pins(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.accessible() || address >= 0xB000 {
+            return;
+        }
+        match (address >> 4) & 0x0F {
+            0x0 if value == 0x55 => {
+                self.latched_x = ACCELEROMETER_ERASED;
+                self.latched_y = ACCELEROMETER_ERASED;
+                self.latch_erased = true;
+            }
+            0x1 if value == 0xAA && self.latch_erased => {
+                self.latch_accelerometer();
+                self.latch_erased = false;
+            }
+            0x8 => self.eeprom.write_pins(value),
+            _ => {}
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> u16 {
+        if address < 0x4000 {
+            0
+        } else {
+            self.rom_bank as u16
+        }
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        self.eeprom.save_state(&mut writer);
+        writer.bool(self.ram_enabled);
+        writer.bool(self.registers_enabled);
+        writer.u8(self.rom_bank);
+        writer.u16(self.latched_x);
+        writer.u16(self.latched_y);
+        writer.bool(self.latch_erased);
+        writer.finish()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+        let mut reader = StateReader::new(state);
+        self.eeprom.load_state(&mut reader)?;
+        self.ram_enabled = reader.bool()?;
+        self.registers_enabled = reader.bool()?;
+        self.rom_bank = reader.u8()?;
+        self.latched_x = reader.u16()?;
+        self.latched_y = reader.u16()?;
+        self.latch_erased = reader.bool()?;
+        reader.finish()
+    }
+
+    fn battery_data(&self) -> Vec<u8> {
+        self.eeprom.to_bytes()
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        self.eeprom.load_bytes(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled_mbc7() -> Mbc7 {
+        let mut mbc7 = Mbc7::new(vec![0; 0x8000]);
+        mbc7.write_rom(0x0000, 0x0A);
+        mbc7.write_rom(0x4000, 0x40);
+        mbc7
+    }
+
+    fn read_axes(mbc7: &Mbc7) -> (u16, u16) {
+        let word =
+            |low: u16, high: u16| (mbc7.read_ram(high) as u16) << 8 | mbc7.read_ram(low) as u16;
+        (word(0xA020, 0xA030), word(0xA040, 0xA050))
+    }
+
+    #[test]
+    fn accelerometer_latch() {
+        let mut mbc7 = enabled_mbc7();
+        mbc7.set_tilt(0.0, 1.0);
+        // latching requires erasing first
+        mbc7.write_ram(0xA010, 0xAA);
+        assert_eq!(read_axes(&mbc7), (0x8000, 0x8000));
+        mbc7.write_ram(0xA000, 0x55);
+        mbc7.write_ram(0xA010, 0xAA);
+        assert_eq!(read_axes(&mbc7), (0x81D0, 0x8240));
+        mbc7.set_tilt(1.0, 0.0);
+        assert_eq!(read_axes(&mbc7), (0x81D0, 0x8240));
+        mbc7.write_ram(0xA000, 0x55);
+        assert_eq!(read_axes(&mbc7), (0x8000, 0x8000));
+    }
+
+    #[test]
+    fn registers_require_both_enables() {
+        let mut mbc7 = Mbc7::new(vec![0; 0x8000]);
+        mbc7.write_rom(0x0000, 0x0A);
+        assert_eq!(mbc7.read_ram(0xA060), 0xFF);
+        mbc7.write_rom(0x4000, 0x40);
+        assert_eq!(mbc7.read_ram(0xA060), 0x00);
+        assert_eq!(mbc7.read_ram(0xA160), 0x00);
+        assert_eq!(mbc7.read_ram(0xB060), 0xFF);
+    }
+}
@@ -0,0 +1,232 @@
+use super::{
+    header::{LOGO_START, NINTENDO_LOGO},
+    load_ram, ram_offset, read_rom_bank, rom_bank_count,
+    state::{StateReader, StateWriter},
+    Mapper,
+};
+use crate::errors::EmulatorError;
+
+/// Size of the menu at the end of the ROM, which is mapped at power on.
+const MENU_SIZE: usize = 0x8000;
+const CARTRIDGE_TYPE_ADDRESS: usize = 0x0147;
+
+/// The MMM01 mapper of multicarts, which behaves like an MBC1 restricted to one game once mapped.
+///
+/// At power on the last 32 KiB of the ROM, containing the menu, are mapped to 0x0000-0x7FFF. The
+/// menu then selects the game by writing the upper ROM and RAM bank bits and a mask for the lower
+/// ROM bank bits the game may not change, and finally sets bit 6 at 0x0000-0x1FFF to map the game.
+/// After that, only the bits a regular MBC1 has can be written.
+pub struct Mmm01 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mapped: bool,
+    ram_enabled: bool,
+    /// 5 bits, the ones set in `rom_bank_mask` are fixed by the menu
+    rom_bank_low: u8,
+    /// ROM bank bits 5-6 and 7-8, fixed by the menu
+    rom_bank_mid: u8,
+    rom_bank_high: u8,
+    rom_bank_mask: u8,
+    /// RAM bank bits 0-1 and 2-3, the latter fixed by the menu
+    ram_bank_low: u8,
+    ram_bank_high: u8,
+    /// MBC1 banking mode 1, which applies the lower RAM bank bits
+    advanced_banking: bool,
+    /// whether the menu disabled switching the banking mode
+    banking_mode_locked: bool,
+}
+
+impl Mmm01 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        Mmm01 {
+            rom,
+            ram: vec![0; ram_size],
+            mapped: false,
+            ram_enabled: false,
+            rom_bank_low: 0,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            rom_bank_mask: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            advanced_banking: false,
+            banking_mode_locked: false,
+        }
+    }
+}
+
+impl Mapper for Mmm01 {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_rom_bank(&self.rom, self.rom_bank(address) as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+                if !self.mapped {
+                    self.mapped = value & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3FFF if self.mapped => {
+                let fixed = self.rom_bank_mask;
+                self.rom_bank_low = (self.rom_bank_low & fixed) | (value & 0x1F & !fixed);
+            }
+            0x2000..=0x3FFF => {
+                self.rom_bank_low = value & 0x1F;
+                self.rom_bank_mid = (value >> 5) & 0x03;
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank_low = value & 0x03;
+                if !self.mapped {
+                    self.ram_bank_high = (value >> 2) & 0x03;
+                    self.rom_bank_high = (value >> 4) & 0x03;
+                    self.banking_mode_locked = value & 0x40 != 0;
+                }
+            }
+            _ => {
+                if !self.banking_mode_locked {
+                    self.advanced_banking = value & 1 != 0;
+                }
+                if !self.mapped {
+                    // the mask covers ROM bank bits 1-4
+                    self.rom_bank_mask = (value & 0x3C) >> 1;
+                }
+            }
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[ram_offset(&self.ram, self.ram_bank() as usize, address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled && !self.ram.is_empty() {
+            let offset = ram_offset(&self.ram, self.ram_bank() as usize, address);
+            self.ram[offset] = value;
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> u16 {
+        if !self.mapped {
+            let menu_bank = rom_bank_count(&self.rom).saturating_sub(2);
+            return (menu_bank + address as usize / 0x4000) as u16;
+        }
+        let outer = (self.rom_bank_high as u16) << 7 | (self.rom_bank_mid as u16) << 5;
+        if address < 0x4000 {
+            return outer | (self.rom_bank_low & self.rom_bank_mask) as u16;
+        }
+        // like on the MBC1, bank 0 of the game is replaced by bank 1
+        let low = if self.rom_bank_low & !self.rom_bank_mask == 0 {
+            self.rom_bank_low | 1
+        } else {
+            self.rom_bank_low
+        };
+        outer | low as u16
+    }
+
+    fn ram_bank(&self) -> u16 {
+        let low = if self.advanced_banking {
+            self.ram_bank_low
+        } else {
+            0
+        };
+        (self.ram_bank_high << 2 | low) as u16
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        writer.bytes(&self.ram);
+        writer.bool(self.mapped);
+        writer.bool(self.ram_enabled);
+        writer.u8(self.rom_bank_low);
+        writer.u8(self.rom_bank_mid);
+        writer.u8(self.rom_bank_high);
+        writer.u8(self.rom_bank_mask);
+        writer.u8(self.ram_bank_low);
+        writer.u8(self.ram_bank_high);
+        writer.bool(self.advanced_banking);
+        writer.bool(self.banking_mode_locked);
+        writer.finish()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+        let mut reader = StateReader::new(state);
+        reader.bytes(&mut self.ram)?;
+        self.mapped = reader.bool()?;
+        self.ram_enabled = reader.bool()?;
+        self.rom_bank_low = reader.u8()?;
+        self.rom_bank_mid = reader.u8()?;
+        self.rom_bank_high = reader.u8()?;
+        self.rom_bank_mask = reader.u8()?;
+        self.ram_bank_low = reader.u8()?;
+        self.ram_bank_high = reader.u8()?;
+        self.advanced_banking = reader.bool()?;
+        self.banking_mode_locked = reader.bool()?;
+        reader.finish()
+    }
+
+    fn battery_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+/// Returns the menu at the end of an MMM01 multicart, which has the header declaring the MMM01
+/// while the header at the start belongs to the first game.
+pub fn menu(rom: &[u8]) -> Option<&[u8]> {
+    if rom.len() < 2 * MENU_SIZE || !rom.len().is_multiple_of(MENU_SIZE) {
+        return None;
+    }
+    let menu = &rom[rom.len() - MENU_SIZE..];
+    let is_mmm01 = (0x0B..=0x0D).contains(&menu[CARTRIDGE_TYPE_ADDRESS]);
+    let has_logo = menu[LOGO_START..LOGO_START + NINTENDO_LOGO.len()] == NINTENDO_LOGO;
+    (is_mmm01 && has_logo).then_some(menu)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a ROM of `size` bytes whose banks start with their bank number.
+    fn numbered_rom(size: usize) -> Vec<u8> {
+        let mut rom = vec![0; size];
+        for (bank, bank_data) in rom.chunks_mut(0x4000).enumerate() {
+            bank_data[0] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn menu_then_game() {
+        let mut rom = numbered_rom(0x80000);
+        let menu_start = rom.len() - MENU_SIZE;
+        rom[menu_start + CARTRIDGE_TYPE_ADDRESS] = 0x0B;
+        rom[menu_start + LOGO_START..menu_start + LOGO_START + NINTENDO_LOGO.len()]
+            .copy_from_slice(&NINTENDO_LOGO);
+        assert!(menu(&rom).is_some());
+
+        let mut mmm01 = Mmm01::new(rom, 0);
+        assert_eq!(mmm01.read_rom(0x0000), 0x1E);
+        assert_eq!(mmm01.read_rom(0x4000), 0x1F);
+
+        // select the game starting at bank 0x08 with 8 banks
+        mmm01.write_rom(0x2000, 0x08);
+        mmm01.write_rom(0x6000, 0x30);
+        mmm01.write_rom(0x0000, 0x40);
+        assert_eq!(mmm01.read_rom(0x0000), 0x08);
+        assert_eq!(mmm01.read_rom(0x4000), 0x09);
+        mmm01.write_rom(0x2000, 0x1F);
+        assert_eq!(mmm01.read_rom(0x4000), 0x0F);
+        // the mask can not be changed anymore
+        mmm01.write_rom(0x6000, 0x00);
+        mmm01.write_rom(0x2000, 0x10);
+        assert_eq!(mmm01.read_rom(0x4000), 0x09);
+    }
+}
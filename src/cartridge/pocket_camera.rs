@@ -0,0 +1,490 @@
+use super::{
+    load_ram, ram_offset, read_rom_bank,
+    state::{StateReader, StateWriter},
+    Mapper,
+};
+use crate::{errors::EmulatorError, pnm::Pnm};
+
+/// Size of the picture captured by the sensor.
+pub const SENSOR_WIDTH: usize = 128;
+pub const SENSOR_HEIGHT: usize = 112;
+
+const RAM_SIZE: usize = 0x20000;
+/// Offset of the captured picture in RAM bank 0, as 16x14 tiles in the 2bpp tile format.
+const IMAGE_OFFSET: usize = 0x100;
+const REGISTER_COUNT: usize = 0x36;
+/// Start of the 4x4 dithering matrix with three thresholds per pixel.
+const DITHER_MATRIX: usize = 0x06;
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+/// Gain of the output amplifier in dB for each value of register 1 bits 0-4, bit 4 adding about
+/// 6 dB to the lower gains.
+const GAIN_DB: [f32; 32] = [
+    14.0, 15.5, 17.0, 18.5, 20.0, 21.5, 23.0, 24.5, 26.0, 29.0, 32.0, 35.0, 38.0, 41.0, 45.5, 51.5,
+    20.0, 21.5, 23.0, 24.5, 26.0, 27.5, 29.0, 30.5, 32.0, 35.0, 38.0, 41.0, 44.0, 47.0, 51.5, 57.5,
+];
+/// Voltage of one step of the converter reading the sensor output, which covers 0-4 V.
+const VOLTS_PER_STEP: f32 = 4.0 / 256.0;
+
+/// A grayscale picture in front of the camera, one byte per pixel from black (0) to white (255).
+#[derive(Clone)]
+pub struct CameraImage {
+    pixels: Vec<u8>,
+}
+
+impl CameraImage {
+    /// A diagonal gradient with a checkerboard in the center, exercising the whole gray range and
+    /// the edge enhancement.
+    pub fn test_pattern() -> Self {
+        let mut pixels = Vec::with_capacity(SENSOR_WIDTH * SENSOR_HEIGHT);
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let checkerboard = (32..96).contains(&x) && (24..88).contains(&y);
+                let pixel = if checkerboard {
+                    if (x / 8 + y / 8) % 2 == 0 {
+                        0x20
+                    } else {
+                        0xE0
+                    }
+                } else {
+                    ((x + y) * 255 / (SENSOR_WIDTH + SENSOR_HEIGHT - 2)) as u8
+                };
+                pixels.push(pixel);
+            }
+        }
+        CameraImage { pixels }
+    }
+
+    /// Parses a binary PGM (P5) picture with 8-bit samples and scales it to the sensor size.
+    pub fn from_pgm(data: &[u8]) -> Result<Self, EmulatorError> {
+        let Pnm {
+            width,
+            height,
+            max_value,
+            samples,
+        } = Pnm::parse(data, "P5")?;
+
+        let mut pixels = Vec::with_capacity(SENSOR_WIDTH * SENSOR_HEIGHT);
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let sample = samples[y * height / SENSOR_HEIGHT * width + x * width / SENSOR_WIDTH];
+                pixels.push((sample as usize * 255 / max_value).min(255) as u8);
+            }
+        }
+        Ok(CameraImage { pixels })
+    }
+
+    /// Returns the pixel at the given position, clamped to the picture edges.
+    fn pixel(&self, x: isize, y: isize) -> u8 {
+        let x = x.clamp(0, SENSOR_WIDTH as isize - 1) as usize;
+        let y = y.clamp(0, SENSOR_HEIGHT as isize - 1) as usize;
+        self.pixels[y * SENSOR_WIDTH + x]
+    }
+}
+
+/// The Pocket Camera (Game Boy Camera) mapper with 1 MiB of ROM, 128 KiB of RAM and the
+/// Mitsubishi M64282FP image sensor.
+///
+/// Setting bit 4 of the RAM bank register maps the sensor registers instead of the RAM. Writing 1
+/// to bit 0 of register 0 starts a capture, which keeps the bit set until the processed picture
+/// has been written to RAM bank 0. The sensor output is the light scaled by the exposure time
+/// (registers 2-3), optionally inverted (register 4 bit 3), run through the edge operation selected
+/// by register 1 bits 5-6 and register 4 bits 4-7, amplified by the gain (register 1 bits 0-4) and
+/// raised by the reference voltage (register 4 bits 0-2) and the offset voltage (register 5 bits
+/// 0-5). The mapper converts it to 8 bits and reduces the picture to 4 shades with the 4x4
+/// threshold matrix in registers 6-0x35. The zero point calibration (register 5 bits 6-7) only
+/// removes the dark current, which the emulated sensor doesn't have.
+pub struct PocketCamera {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    image: CameraImage,
+    ram_enabled: bool,
+    rom_bank: u8,
+    /// bank 0x00-0x0F, or 0x10 set for the sensor registers
+    ram_bank: u8,
+    registers: [u8; REGISTER_COUNT],
+    /// M-cycles until the running capture completes
+    capture_cycles: u32,
+}
+
+impl PocketCamera {
+    pub fn new(rom: Vec<u8>) -> Self {
+        PocketCamera {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            image: CameraImage::test_pattern(),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            registers: [0; REGISTER_COUNT],
+            capture_cycles: 0,
+        }
+    }
+
+    fn registers_mapped(&self) -> bool {
+        self.ram_bank & 0x10 != 0
+    }
+
+    fn exposure(&self) -> u32 {
+        u16::from_be_bytes([self.registers[2], self.registers[3]]) as u32
+    }
+
+    /// Capture time in M-cycles, which is shorter with the N bit set.
+    fn capture_time(&self) -> u32 {
+        let n_bit = self.registers[1] & 0x80 != 0;
+        32446 + if n_bit { 0 } else { 512 } + 16 * self.exposure()
+    }
+
+    /// Returns the charge of the given pixel after the exposure, in steps of the converter at the
+    /// lowest gain.
+    fn sensor_output(&self, x: isize, y: isize) -> f32 {
+        let mut pixel = self.image.pixel(x, y) as f32;
+        if self.registers[4] & 0x08 != 0 {
+            pixel = 255.0 - pixel;
+        }
+        pixel * self.exposure() as f32 / 0x1000 as f32
+    }
+
+    /// Applies the edge operation: VH (register 1 bits 5-6) selects none, horizontal, vertical or
+    /// both directions, E3 (register 4 bit 7) extracts the edges instead of adding them to the
+    /// picture, and E (register 4 bits 4-6) is the ratio they are weighted with.
+    fn edge_output(&self, x: isize, y: isize) -> f32 {
+        let value = self.sensor_output(x, y);
+        let horizontal = 2.0 * value - self.sensor_output(x - 1, y) - self.sensor_output(x + 1, y);
+        let vertical = 2.0 * value - self.sensor_output(x, y - 1) - self.sensor_output(x, y + 1);
+        let edge = match (self.registers[1] >> 5) & 0x03 {
+            0 => return value,
+            1 => horizontal,
+            2 => vertical,
+            _ => horizontal + vertical,
+        };
+        let ratio = EDGE_RATIOS[((self.registers[4] >> 4) & 0x07) as usize];
+        if self.registers[4] & 0x80 != 0 {
+            edge * ratio
+        } else {
+            value + edge * ratio
+        }
+    }
+
+    /// Returns the sensor output voltage converted to 8 bits, unclamped.
+    fn converted_output(&self, x: isize, y: isize) -> f32 {
+        let gain = GAIN_DB[(self.registers[1] & 0x1F) as usize] - GAIN_DB[0];
+        let signal = self.edge_output(x, y) * 10f32.powf(gain / 20.0);
+        let reference = (self.registers[4] & 0x07) as f32 * 0.5;
+        let offset = (self.registers[5] & 0x1F) as f32 * 0.032;
+        let offset = if self.registers[5] & 0x20 != 0 {
+            offset
+        } else {
+            -offset
+        };
+        signal + (reference + offset) / VOLTS_PER_STEP
+    }
+
+    fn processed_pixel(&self, x: usize, y: usize) -> u8 {
+        let (x, y) = (x as isize, y as isize);
+        let value = self.converted_output(x, y).clamp(0.0, 255.0);
+
+        let matrix_index = DITHER_MATRIX + ((x & 3) + (y & 3) * 4) as usize * 3;
+        let thresholds = &self.registers[matrix_index..matrix_index + 3];
+        match thresholds
+            .iter()
+            .position(|&threshold| value < threshold as f32)
+        {
+            Some(0) => 3,
+            Some(1) => 2,
+            Some(_) => 1,
+            None => 0,
+        }
+    }
+
+    /// Writes the processed picture to RAM bank 0 in the 2bpp tile format.
+    fn store_capture(&mut self) {
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let shade = self.processed_pixel(x, y);
+                let tile = y / 8 * (SENSOR_WIDTH / 8) + x / 8;
+                let offset = IMAGE_OFFSET + tile * 16 + (y % 8) * 2;
+                let mask = 0x80 >> (x % 8);
+                for (plane, byte) in self.ram[offset..offset + 2].iter_mut().enumerate() {
+                    if shade >> plane & 1 != 0 {
+                        *byte |= mask;
+                    } else {
+                        *byte &= !mask;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Mapper for PocketCamera {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_rom_bank(&self.rom, self.rom_bank(address) as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x1F,
+            _ => {}
+        }
+    }
+
+    /// Only register 0 of the sensor can be read, the RAM is readable even while disabled.
+    fn read_ram(&self, address: u16) -> u8 {
+        if self.registers_mapped() {
+            return match address & 0x7F {
+                0x00 => self.registers[0],
+                _ => 0x00,
+            };
+        }
+        self.ram[ram_offset(&self.ram, self.ram_bank as usize, address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.registers_mapped() {
+            let register = (address & 0x7F) as usize;
+            match register {
+                0x00 => {
+                    self.registers[0] = value & 0x07;
+                    if value & 1 != 0 && self.capture_cycles == 0 {
+                        self.capture_cycles = self.capture_time();
+                    }
+                }
+                0x01..REGISTER_COUNT => self.registers[register] = value,
+                _ => {}
+            }
+        } else if self.ram_enabled {
+            let offset = ram_offset(&self.ram, self.ram_bank as usize, address);
+            self.ram[offset] = value;
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> u16 {
+        if address < 0x4000 {
+            0
+        } else {
+            self.rom_bank as u16
+        }
+    }
+
+    fn ram_bank(&self) -> u16 {
+        self.ram_bank as u16
+    }
+
+    fn tick(&mut self) {
+        if self.capture_cycles > 0 {
+            self.capture_cycles -= 1;
+            if self.capture_cycles == 0 {
+                self.store_capture();
+                self.registers[0] &= !1;
+            }
+        }
+    }
+
+    fn set_camera_image(&mut self, image: CameraImage) {
+        self.image = image;
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        writer.bytes(&self.ram);
+        writer.bool(self.ram_enabled);
+        writer.u8(self.rom_bank);
+        writer.u8(self.ram_bank);
+        writer.bytes(&self.registers);
+        writer.u32(self.capture_cycles);
+        writer.finish()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+        let mut reader = StateReader::new(state);
+        reader.bytes(&mut self.ram)?;
+        self.ram_enabled = reader.bool()?;
+        self.rom_bank = reader.u8()?;
+        self.ram_bank = reader.u8()?;
+        reader.bytes(&mut self.registers)?;
+        self.capture_cycles = reader.u32()?;
+        reader.finish()
+    }
+
+    fn battery_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera_with_image(pixel: u8) -> PocketCamera {
+        let mut camera = PocketCamera::new(vec![0; 0x8000]);
+        camera.set_camera_image(CameraImage {
+            pixels: vec![pixel; SENSOR_WIDTH * SENSOR_HEIGHT],
+        });
+        camera.write_rom(0x4000, 0x10);
+        // exposure 0x1000 passes the picture through unchanged
+        camera.write_ram(0xA002, 0x10);
+        camera.write_ram(0xA003, 0x00);
+        for (index, address) in (0xA006..0xA036).enumerate() {
+            camera.write_ram(address, [0x40, 0x80, 0xC0][index % 3]);
+        }
+        camera
+    }
+
+    fn capture(camera: &mut PocketCamera) {
+        camera.write_ram(0xA000, 0x01);
+        assert_eq!(camera.read_ram(0xA000) & 1, 1);
+        while camera.read_ram(0xA000) & 1 != 0 {
+            camera.tick();
+        }
+        camera.write_rom(0x4000, 0x00);
+    }
+
+    #[test]
+    fn capture_applies_thresholds() {
+        let mut camera = camera_with_image(0x90);
+        capture(&mut camera);
+        // 0x90 is between the second and third threshold, shade 1
+        assert_eq!(camera.read_ram(0xA100), 0xFF);
+        assert_eq!(camera.read_ram(0xA101), 0x00);
+        assert_eq!(camera.read_ram(0xAEFF), 0x00);
+
+        let mut camera = camera_with_image(0x10);
+        capture(&mut camera);
+        assert_eq!(camera.read_ram(0xA100), 0xFF);
+        assert_eq!(camera.read_ram(0xA101), 0xFF);
+    }
+
+    /// A camera seeing `background` with a vertical line of `line` at x = 64, or a horizontal one
+    /// at y = 56 if `vertical` is false.
+    fn camera_with_line(background: u8, line: u8, vertical: bool) -> PocketCamera {
+        let mut camera = camera_with_image(background);
+        let mut pixels = vec![background; SENSOR_WIDTH * SENSOR_HEIGHT];
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                if (vertical && x == 64) || (!vertical && y == 56) {
+                    pixels[y * SENSOR_WIDTH + x] = line;
+                }
+            }
+        }
+        camera.set_camera_image(CameraImage { pixels });
+        camera
+    }
+
+    fn assert_output(camera: &PocketCamera, x: isize, y: isize, expected: f32) {
+        let output = camera.converted_output(x, y);
+        assert!(
+            (output - expected).abs() < 0.1,
+            "output {output} instead of {expected}"
+        );
+    }
+
+    #[test]
+    fn gain() {
+        let mut camera = camera_with_image(0x40);
+        // (register 1, output): 14 dB is the lowest gain, every 6 dB doubles the output
+        for (gain, output) in [
+            (0x00, 64.0),
+            (0x04, 127.7),
+            (0x10, 127.7),
+            (0x08, 254.8),
+            (0x14, 254.8),
+            (0x1F, 64.0 * 10f32.powf(43.5 / 20.0)),
+        ] {
+            camera.write_ram(0xA001, gain);
+            assert_output(&camera, 0, 0, output);
+        }
+    }
+
+    #[test]
+    fn output_voltages() {
+        let mut camera = camera_with_image(0x40);
+        // (register 4, register 5, output): 0.5 V reference steps, 32 mV offset steps
+        for (reference, offset, output) in [
+            (0x00, 0x00, 64.0),
+            (0x02, 0x00, 128.0),
+            (0x07, 0x00, 288.0),
+            (0x02, 0x21, 130.048),
+            (0x02, 0x01, 125.952),
+            (0x00, 0x3F, 127.488),
+            (0x00, 0x1F, 0.512),
+            (0x00, 0xC0, 64.0),
+        ] {
+            camera.write_ram(0xA004, reference);
+            camera.write_ram(0xA005, offset);
+            assert_output(&camera, 0, 0, output);
+        }
+
+        // the converter clamps the output to 8 bits
+        camera.write_ram(0xA004, 0x07);
+        capture(&mut camera);
+        assert_eq!(camera.read_ram(0xA100), 0x00);
+        assert_eq!(camera.read_ram(0xA101), 0x00);
+    }
+
+    #[test]
+    fn edge_modes() {
+        // (register 1, register 4, output on the line, output next to it)
+        let cases = [
+            // no edge operation
+            (0x00, 0x20, 128.0, 64.0),
+            (0x00, 0xA0, 128.0, 64.0),
+            // horizontal enhancement at every ratio
+            (0x20, 0x00, 192.0, 32.0),
+            (0x20, 0x10, 224.0, 16.0),
+            (0x20, 0x20, 256.0, 0.0),
+            (0x20, 0x30, 288.0, -16.0),
+            (0x20, 0x40, 384.0, -64.0),
+            (0x20, 0x50, 512.0, -128.0),
+            (0x20, 0x60, 640.0, -192.0),
+            (0x20, 0x70, 768.0, -256.0),
+            // vertical enhancement doesn't see a vertical line
+            (0x40, 0x20, 128.0, 64.0),
+            // two-dimensional enhancement, also with the N bit the Camera software sets
+            (0x60, 0x20, 256.0, 0.0),
+            (0xE0, 0x20, 256.0, 0.0),
+            // extraction
+            (0x20, 0xA0, 128.0, -64.0),
+            (0x20, 0xF0, 640.0, -320.0),
+            (0x40, 0xA0, 0.0, 0.0),
+            (0x60, 0xC0, 256.0, -128.0),
+        ];
+        let mut camera = camera_with_line(0x40, 0x80, true);
+        for (mode, ratio, line, next) in cases {
+            camera.write_ram(0xA001, mode);
+            camera.write_ram(0xA004, ratio);
+            assert_output(&camera, 64, 50, line);
+            assert_output(&camera, 65, 50, next);
+        }
+
+        // the same with a horizontal line, swapping the horizontal and vertical modes
+        let mut camera = camera_with_line(0x40, 0x80, false);
+        for (mode, ratio, line, next) in cases {
+            let mode = match mode & 0x60 {
+                0x20 => mode ^ 0x60,
+                0x40 => mode ^ 0x60,
+                _ => mode,
+            };
+            camera.write_ram(0xA001, mode);
+            camera.write_ram(0xA004, ratio);
+            assert_output(&camera, 50, 56, line);
+            assert_output(&camera, 50, 57, next);
+        }
+    }
+
+    #[test]
+    fn parse_pgm() {
+        let mut data = b"P5\n# comment\n2 1\n255\n".to_vec();
+        data.extend([0x00, 0xFF]);
+        let image = CameraImage::from_pgm(&data).unwrap();
+        assert_eq!(image.pixel(0, 0), 0x00);
+        assert_eq!(image.pixel(127, 111), 0xFF);
+        assert!(CameraImage::from_pgm(b"P2\n2 1\n255\n0 255").is_err());
+        assert!(CameraImage::from_pgm(b"P5\n2 1\n255\n\x00").is_err());
+    }
+}
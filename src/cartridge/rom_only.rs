@@ -0,0 +1,69 @@
+use super::{
+    load_ram, ram_offset, read_rom_bank,
+    state::{StateReader, StateWriter},
+    Mapper,
+};
+use crate::errors::EmulatorError;
+
+/// A cartridge without a mapper, which has its first 32 KiB of ROM and up to 8 KiB of RAM mapped
+/// directly.
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl RomOnly {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        RomOnly {
+            rom,
+            ram: vec![0; ram_size.min(0x2000)],
+        }
+    }
+}
+
+impl Mapper for RomOnly {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_rom_bank(&self.rom, self.rom_bank(address) as usize, address)
+    }
+
+    fn write_rom(&mut self, _address: u16, _value: u8) {}
+
+    /// Without RAM nothing drives the data bus, which reads as 0xFF.
+    fn read_ram(&self, address: u16) -> u8 {
+        if self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[ram_offset(&self.ram, 0, address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram.is_empty() {
+            let offset = ram_offset(&self.ram, 0, address);
+            self.ram[offset] = value;
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> u16 {
+        address / 0x4000
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        writer.bytes(&self.ram);
+        writer.finish()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+        let mut reader = StateReader::new(state);
+        reader.bytes(&mut self.ram)?;
+        reader.finish()
+    }
+
+    fn battery_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
@@ -0,0 +1,263 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::state::{StateReader, StateWriter};
+use crate::{errors::EmulatorError, gameboy::CYCLES_PER_SECOND};
+
+/// Length of the RTC state appended to the save RAM: the live and the latched registers as 32-bit
+/// words followed by a 64-bit UNIX timestamp, all little endian. This is the layout most emulators
+/// use, some of them with a 32-bit timestamp.
+pub const RTC_SAVE_SIZE: usize = 48;
+const RTC_SAVE_SIZE_SHORT_TIMESTAMP: usize = 44;
+
+const DAY_HIGH_BIT: u8 = 0b0000_0001;
+const HALT_BIT: u8 = 0b0100_0000;
+const DAY_CARRY_BIT: u8 = 0b1000_0000;
+/// Bits implemented by each counter register.
+const REGISTER_MASKS: [u8; 5] = [0x3F, 0x3F, 0x1F, 0xFF, 0b1100_0001];
+
+/// The real-time clock of the MBC3, counting seconds, minutes, hours and 512 days.
+///
+/// The CPU only sees a latched copy of the counters, which is updated by writing 0x00 and then
+/// 0x01 to the latch register. Each counter only wraps around at its regular limit, a counter set
+/// beyond it counts up until it overflows its bits without carrying into the next counter.
+#[derive(Default)]
+pub struct Rtc {
+    /// seconds, minutes, hours, lower 8 bits of the day counter, day high bit/halt/day carry
+    registers: [u8; 5],
+    latched: [u8; 5],
+    /// M-cycles into the current second
+    subsecond_cycles: u64,
+    /// whether the last latch write was 0x00
+    latch_armed: bool,
+}
+
+impl Rtc {
+    /// Advances the clock by one M-cycle.
+    pub fn tick(&mut self) {
+        if self.halted() {
+            return;
+        }
+        self.subsecond_cycles += 1;
+        if self.subsecond_cycles == CYCLES_PER_SECOND {
+            self.subsecond_cycles = 0;
+            self.tick_second();
+        }
+    }
+
+    /// Advances the clock by `seconds` while the emulator was not running.
+    pub fn advance(&mut self, mut seconds: u64) {
+        if self.halted() {
+            return;
+        }
+        // counters beyond their limits need to overflow one step at a time
+        while seconds > 0 && !self.in_range() {
+            self.tick_second();
+            seconds -= 1;
+        }
+        let [s, m, h, ..] = self.registers;
+        let total = seconds + s as u64 + 60 * (m as u64 + 60 * (h as u64 + 24 * self.days()));
+        let days = total / 86_400;
+        self.registers[0] = (total % 60) as u8;
+        self.registers[1] = (total / 60 % 60) as u8;
+        self.registers[2] = (total / 3600 % 24) as u8;
+        self.set_days(days);
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 0x01 {
+            self.latched = self.registers;
+        }
+        self.latch_armed = value == 0x00;
+    }
+
+    /// Reads the latched counter selected by `register`, being 0x08-0x0C.
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched[(register - 0x08) as usize]
+    }
+
+    /// Writes the counter selected by `register`, being 0x08-0x0C. Writing the seconds restarts
+    /// the current second.
+    pub fn write(&mut self, register: u8, value: u8) {
+        let index = (register - 0x08) as usize;
+        self.registers[index] = value & REGISTER_MASKS[index];
+        if index == 0 {
+            self.subsecond_cycles = 0;
+        }
+    }
+
+    /// Serializes the clock together with the current host time, see `RTC_SAVE_SIZE`.
+    pub fn save(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(RTC_SAVE_SIZE);
+        for register in self.registers.iter().chain(&self.latched) {
+            data.extend_from_slice(&(*register as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&unix_time().to_le_bytes());
+        data
+    }
+
+    /// Restores a clock saved by `save` and catches up with the host time since then. Returns
+    /// false and leaves the clock untouched if `data` has the wrong length.
+    pub fn load(&mut self, data: &[u8]) -> bool {
+        if data.len() != RTC_SAVE_SIZE && data.len() != RTC_SAVE_SIZE_SHORT_TIMESTAMP {
+            return false;
+        }
+        let (counters, timestamp) = data.split_at(40);
+        for (index, word) in counters.chunks(4).enumerate() {
+            let value = word[0];
+            if index < 5 {
+                self.registers[index] = value;
+            } else {
+                self.latched[index - 5] = value;
+            }
+        }
+        let mut timestamp_bytes = [0; 8];
+        timestamp_bytes[..timestamp.len()].copy_from_slice(timestamp);
+        let saved_at = u64::from_le_bytes(timestamp_bytes);
+        self.advance(unix_time().saturating_sub(saved_at));
+        true
+    }
+
+    /// Appends the exact clock state to a mapper state. Unlike `save`, the host time is not
+    /// recorded, so the clock continues where it was when the state is loaded.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer
+            .bytes(&self.registers)
+            .bytes(&self.latched)
+            .u64(self.subsecond_cycles)
+            .bool(self.latch_armed);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        reader.bytes(&mut self.registers)?;
+        reader.bytes(&mut self.latched)?;
+        self.subsecond_cycles = reader.u64()?;
+        self.latch_armed = reader.bool()?;
+        Ok(())
+    }
+
+    fn halted(&self) -> bool {
+        self.registers[4] & HALT_BIT != 0
+    }
+
+    fn days(&self) -> u64 {
+        ((self.registers[4] & DAY_HIGH_BIT) as u64) << 8 | self.registers[3] as u64
+    }
+
+    /// Sets the 9-bit day counter, setting the day carry if `days` does not fit.
+    fn set_days(&mut self, days: u64) {
+        self.registers[3] = days as u8;
+        let flags = self.registers[4] & !DAY_HIGH_BIT;
+        let high_bit = ((days >> 8) & 1) as u8;
+        let carry = if days >= 512 { DAY_CARRY_BIT } else { 0 };
+        self.registers[4] = flags | high_bit | carry;
+    }
+
+    fn in_range(&self) -> bool {
+        let [s, m, h, ..] = self.registers;
+        s < 60 && m < 60 && h < 24
+    }
+
+    fn tick_second(&mut self) {
+        let [s, m, h, ..] = &mut self.registers;
+        if !increment_counter(s, 60, 0x3F) {
+            return;
+        }
+        if !increment_counter(m, 60, 0x3F) {
+            return;
+        }
+        if !increment_counter(h, 24, 0x1F) {
+            return;
+        }
+        self.set_days(self.days() + 1);
+    }
+}
+
+/// Increments a clock counter, returning whether it wrapped around at `limit` and carries into
+/// the next counter. Values beyond the limit wrap around at `mask` without a carry.
+fn increment_counter(counter: &mut u8, limit: u8, mask: u8) -> bool {
+    *counter = (*counter + 1) & mask;
+    if *counter == limit {
+        *counter = 0;
+        return true;
+    }
+    false
+}
+
+/// Returns the host time in seconds since the UNIX epoch, for catching up clocks between sessions.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latched_time(rtc: &mut Rtc) -> [u8; 5] {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        [0x08, 0x09, 0x0A, 0x0B, 0x0C].map(|register| rtc.read(register))
+    }
+
+    #[test]
+    fn counts_with_emulated_time() {
+        let mut rtc = Rtc::default();
+        rtc.write(0x08, 59);
+        rtc.write(0x09, 59);
+        rtc.write(0x0A, 23);
+        rtc.write(0x0B, 0xFF);
+        rtc.write(0x0C, DAY_HIGH_BIT);
+        assert_eq!(latched_time(&mut rtc), [59, 59, 23, 0xFF, DAY_HIGH_BIT]);
+        for _ in 0..CYCLES_PER_SECOND {
+            rtc.tick();
+        }
+        assert_eq!(latched_time(&mut rtc), [0, 0, 0, 0, DAY_CARRY_BIT]);
+    }
+
+    #[test]
+    fn latch_requires_zero_then_one() {
+        let mut rtc = Rtc::default();
+        rtc.write(0x08, 10);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 10);
+    }
+
+    #[test]
+    fn invalid_values_overflow_without_carry() {
+        let mut rtc = Rtc::default();
+        rtc.write(0x08, 63);
+        rtc.advance(1);
+        assert_eq!(latched_time(&mut rtc), [0, 0, 0, 0, 0]);
+        rtc.write(0x08, 62);
+        rtc.advance(3 + 3600);
+        assert_eq!(latched_time(&mut rtc), [1, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn halt_stops_the_clock() {
+        let mut rtc = Rtc::default();
+        rtc.write(0x0C, HALT_BIT);
+        rtc.advance(100);
+        for _ in 0..CYCLES_PER_SECOND {
+            rtc.tick();
+        }
+        assert_eq!(latched_time(&mut rtc), [0, 0, 0, 0, HALT_BIT]);
+    }
+
+    #[test]
+    fn catches_up_with_host_time() {
+        let mut rtc = Rtc::default();
+        let mut data = rtc.save();
+        assert_eq!(data.len(), RTC_SAVE_SIZE);
+        let saved_at = unix_time() - 2 * 86_400 - 61;
+        data[40..].copy_from_slice(&saved_at.to_le_bytes());
+        assert!(rtc.load(&data));
+        let time = latched_time(&mut rtc);
+        // the host clock may pass a second boundary during the test
+        assert!(time == [1, 1, 0, 2, 0] || time == [2, 1, 0, 2, 0]);
+    }
+}
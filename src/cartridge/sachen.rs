@@ -0,0 +1,229 @@
+use super::{
+    header::{LOGO_START, NINTENDO_LOGO},
+    read_rom_bank,
+    state::{StateReader, StateWriter},
+    Mapper,
+};
+use crate::errors::EmulatorError;
+
+/// Rising edges of A15 until the MMC1 unlocks, also the second stage of the MMC2.
+const UNLOCK_EDGES: u8 = 0x31;
+/// Rising edges of A15 until the MMC2 leaves its first stage, for the DMG boot ROM.
+const MMC2_FIRST_STAGE_EDGES: u8 = 0x30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lock {
+    /// first stage of the MMC2
+    LockedDmg,
+    Locked,
+    Unlocked,
+}
+
+/// The unlicensed Sachen MMC1 and MMC2 mappers, which scramble the header while locked.
+///
+/// The boot ROM sees the Nintendo logo with address lines A0/A6 and A1/A4 swapped in 0x0100-0x01FF
+/// and A7 forced high in 0x0000-0x00FF, so the cartridge itself does not need to contain the logo.
+/// The mapper unlocks after a number of rising edges of A15 during the boot sequence, the MMC2
+/// stays locked for longer to pass the additional logo check of the CGB boot ROM.
+///
+/// The ROM bank at 0x4000-0x7FFF combines the base bank with the ROM bank register, the mask
+/// selecting which bits come from the base bank. 0x0000-0x3FFF maps the masked base bank. Base
+/// and mask can only be written while the upper ROM bank bits are set.
+pub struct Sachen {
+    rom: Vec<u8>,
+    lock: Lock,
+    a15_high: bool,
+    a15_edges: u8,
+    base_bank: u8,
+    /// never zero
+    rom_bank: u8,
+    mask: u8,
+}
+
+impl Sachen {
+    pub fn new(rom: Vec<u8>, mmc2: bool) -> Self {
+        Sachen {
+            rom,
+            lock: if mmc2 { Lock::LockedDmg } else { Lock::Locked },
+            a15_high: false,
+            a15_edges: 0,
+            base_bank: 0,
+            rom_bank: 1,
+            mask: 0,
+        }
+    }
+
+    /// Applies the address scrambling of the locked mapper.
+    fn locked_address(address: u16) -> u16 {
+        match address {
+            0x0000..=0x00FF => address | 0x80,
+            0x0100..=0x01FF => scramble(address),
+            _ => address,
+        }
+    }
+}
+
+impl Mapper for Sachen {
+    fn read_rom(&self, address: u16) -> u8 {
+        let address = match self.lock {
+            Lock::Unlocked => address,
+            _ => Self::locked_address(address),
+        };
+        read_rom_bank(&self.rom, self.rom_bank(address) as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        let upper_bits_set = self.rom_bank & 0x30 == 0x30;
+        match address {
+            0x0000..=0x1FFF if upper_bits_set => self.base_bank = value,
+            0x2000..=0x3FFF => self.rom_bank = value.max(1),
+            0x4000..=0x5FFF if upper_bits_set => self.mask = value,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, _address: u16) -> u8 {
+        0xFF
+    }
+
+    fn write_ram(&mut self, _address: u16, _value: u8) {}
+
+    fn rom_bank(&self, address: u16) -> u16 {
+        let base = self.base_bank & self.mask;
+        if address < 0x4000 {
+            base as u16
+        } else {
+            (base | (self.rom_bank & !self.mask)) as u16
+        }
+    }
+
+    fn observes_accesses(&self) -> bool {
+        self.lock != Lock::Unlocked
+    }
+
+    fn observe_access(&mut self, address: u16) {
+        let a15_high = address & 0x8000 != 0;
+        if a15_high && !self.a15_high {
+            self.a15_edges += 1;
+            match self.lock {
+                Lock::LockedDmg if self.a15_edges == MMC2_FIRST_STAGE_EDGES => {
+                    self.lock = Lock::Locked;
+                    self.a15_edges = 0;
+                }
+                Lock::Locked if self.a15_edges == UNLOCK_EDGES => self.lock = Lock::Unlocked,
+                _ => {}
+            }
+        }
+        self.a15_high = a15_high;
+    }
+
+    fn skip_boot(&mut self) {
+        self.lock = Lock::Unlocked;
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        writer.u8(self.lock as u8);
+        writer.bool(self.a15_high);
+        writer.u8(self.a15_edges);
+        writer.u8(self.base_bank);
+        writer.u8(self.rom_bank);
+        writer.u8(self.mask);
+        writer.finish()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+        let mut reader = StateReader::new(state);
+        self.lock = match reader.u8()? {
+            0 => Lock::LockedDmg,
+            1 => Lock::Locked,
+            _ => Lock::Unlocked,
+        };
+        self.a15_high = reader.bool()?;
+        self.a15_edges = reader.u8()?;
+        self.base_bank = reader.u8()?;
+        self.rom_bank = reader.u8()?;
+        self.mask = reader.u8()?;
+        reader.finish()
+    }
+
+    fn battery_data(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_battery_data(&mut self, _data: &[u8]) {}
+}
+
+/// Swaps the address lines A0 with A6 and A1 with A4.
+fn scramble(address: u16) -> u16 {
+    let bit = |line: u16| (address >> line) & 1;
+    address & !0b0101_0011 | bit(0) << 6 | bit(6) | bit(1) << 4 | bit(4) << 1
+}
+
+/// Sachen cartridges contain the Nintendo logo only in its scrambled form.
+pub fn has_scrambled_logo(rom: &[u8]) -> bool {
+    if rom.len() < 0x200 {
+        return false;
+    }
+    let scrambled_logo_matches = NINTENDO_LOGO
+        .iter()
+        .enumerate()
+        .all(|(index, &byte)| rom[scramble((LOGO_START + index) as u16) as usize] == byte);
+    scrambled_logo_matches && rom[LOGO_START..LOGO_START + NINTENDO_LOGO.len()] != NINTENDO_LOGO
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrambled_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x40000];
+        for (index, &byte) in NINTENDO_LOGO.iter().enumerate() {
+            rom[scramble((LOGO_START + index) as u16) as usize] = byte;
+        }
+        rom
+    }
+
+    #[test]
+    fn unlocks_after_a15_edges() {
+        let rom = scrambled_rom();
+        assert!(has_scrambled_logo(&rom));
+        let mut sachen = Sachen::new(rom, false);
+        assert_eq!(sachen.read_rom(LOGO_START as u16), NINTENDO_LOGO[0]);
+        for _ in 0..UNLOCK_EDGES {
+            sachen.observe_access(0x0104);
+            sachen.observe_access(0x8000);
+            // staying high is not an edge
+            sachen.observe_access(0xFF80);
+        }
+        assert!(!sachen.observes_accesses());
+        assert_eq!(sachen.read_rom(LOGO_START as u16 + 1), 0);
+    }
+
+    #[test]
+    fn mmc2_has_two_stages() {
+        let mut sachen = Sachen::new(scrambled_rom(), true);
+        for _ in 0..MMC2_FIRST_STAGE_EDGES + UNLOCK_EDGES - 1 {
+            sachen.observe_access(0x0000);
+            sachen.observe_access(0x8000);
+        }
+        assert!(sachen.observes_accesses());
+        sachen.observe_access(0x0000);
+        sachen.observe_access(0x8000);
+        assert!(!sachen.observes_accesses());
+    }
+
+    #[test]
+    fn base_and_mask() {
+        let mut sachen = Sachen::new(scrambled_rom(), false);
+        sachen.skip_boot();
+        sachen.write_rom(0x0000, 0x08);
+        assert_eq!(sachen.rom_bank(0x0000), 0);
+        sachen.write_rom(0x2000, 0x30);
+        sachen.write_rom(0x0000, 0x08);
+        sachen.write_rom(0x4000, 0x0C);
+        sachen.write_rom(0x2000, 0x03);
+        assert_eq!(sachen.rom_bank(0x0000), 0x08);
+        assert_eq!(sachen.rom_bank(0x4000), 0x0B);
+    }
+}
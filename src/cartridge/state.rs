@@ -0,0 +1,141 @@
+use crate::errors::EmulatorError;
+
+/// Builds the data returned by `Mapper::save_state`, storing values in little endian.
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn u8(&mut self, value: u8) -> &mut Self {
+        self.data.push(value);
+        self
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.u8(value as u8)
+    }
+
+    pub fn u16(&mut self, value: u16) -> &mut Self {
+        self.data.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.data.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.data.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    /// Stores `bytes` preceded by their length.
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.data)
+    }
+}
+
+/// Reads the data written by a `StateWriter` in the same order, for `Mapper::load_state`.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data }
+    }
+
+    pub fn u8(&mut self) -> Result<u8, EmulatorError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, EmulatorError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, EmulatorError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, EmulatorError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, EmulatorError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    /// Reads bytes stored by `StateWriter::bytes` into `bytes`, which must have the same length.
+    pub fn bytes(&mut self, bytes: &mut [u8]) -> Result<(), EmulatorError> {
+        let len = self.u32()? as usize;
+        if len != bytes.len() || len > self.data.len() {
+            return Err(invalid_state());
+        }
+        let (stored, rest) = self.data.split_at(len);
+        bytes.copy_from_slice(stored);
+        self.data = rest;
+        Ok(())
+    }
+
+    /// Fails if the state contains more data than has been read.
+    pub fn finish(&self) -> Result<(), EmulatorError> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid_state())
+        }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], EmulatorError> {
+        let Some((bytes, rest)) = self.data.split_first_chunk::<N>() else {
+            return Err(invalid_state());
+        };
+        self.data = rest;
+        Ok(*bytes)
+    }
+}
+
+fn invalid_state() -> EmulatorError {
+    EmulatorError::StateError("The mapper state does not match the cartridge.".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let state = StateWriter::default()
+            .u8(1)
+            .bool(true)
+            .u16(0x1234)
+            .bytes(&[5, 6])
+            .u64(7)
+            .finish();
+        let mut reader = StateReader::new(&state);
+        assert_eq!(reader.u8().unwrap(), 1);
+        assert!(reader.bool().unwrap());
+        assert_eq!(reader.u16().unwrap(), 0x1234);
+        let mut bytes = [0; 2];
+        reader.bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, [5, 6]);
+        assert!(reader.finish().is_err());
+        assert_eq!(reader.u64().unwrap(), 7);
+        assert!(reader.finish().is_ok());
+        assert!(reader.u8().is_err());
+    }
+
+    #[test]
+    fn length_mismatch() {
+        let state = StateWriter::default().bytes(&[1, 2, 3]).finish();
+        assert!(StateReader::new(&state).bytes(&mut [0; 2]).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use super::{
+    read_rom_bank,
+    state::{StateReader, StateWriter},
+    CartridgeHeader, Mapper, MapperKind,
+};
+use crate::errors::EmulatorError;
+
+/// The unlicensed Wisdom Tree mapper, which switches the whole 0x0000-0x7FFF area in 32 KiB banks.
+///
+/// The bank number is taken from the lower byte of the address written to in 0x0000-0x3FFF, the
+/// written value is ignored. There is no RAM.
+pub struct WisdomTree {
+    rom: Vec<u8>,
+    bank: u8,
+}
+
+impl WisdomTree {
+    pub fn new(rom: Vec<u8>) -> Self {
+        WisdomTree { rom, bank: 0 }
+    }
+}
+
+impl Mapper for WisdomTree {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_rom_bank(&self.rom, self.rom_bank(address) as usize, address)
+    }
+
+    fn write_rom(&mut self, address: u16, _value: u8) {
+        if address < 0x4000 {
+            self.bank = address as u8;
+        }
+    }
+
+    fn read_ram(&self, _address: u16) -> u8 {
+        0xFF
+    }
+
+    fn write_ram(&mut self, _address: u16, _value: u8) {}
+
+    /// Returns the 16 KiB bank, for consistency with the other mappers.
+    fn rom_bank(&self, address: u16) -> u16 {
+        self.bank as u16 * 2 + address / 0x4000
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        writer.u8(self.bank);
+        writer.finish()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), EmulatorError> {
+        let mut reader = StateReader::new(state);
+        self.bank = reader.u8()?;
+        reader.finish()
+    }
+
+    fn battery_data(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_battery_data(&mut self, _data: &[u8]) {}
+}
+
+/// Wisdom Tree games declare no mapper or the invalid cartridge type 0xC0, but are larger than the
+/// 32 KiB addressable without a mapper. Most of them also contain the company name.
+pub fn is_wisdom_tree(rom: &[u8], header: &CartridgeHeader) -> bool {
+    let mapper = header.cartridge_type.mapper;
+    let first_bank = &rom[..rom.len().min(0x4000)];
+    let has_name = [&b"WISDOM TREE"[..], &b"WISDOM\0TREE"[..]]
+        .iter()
+        .any(|name| first_bank.windows(name.len()).any(|window| window == *name));
+    match mapper {
+        MapperKind::None => rom.len() > 0x8000 || has_name,
+        MapperKind::Unknown(0xC0) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches_32_kib_banks() {
+        let mut rom = vec![0; 0x20000];
+        rom[0x18000] = 0x33;
+        rom[0x1C000] = 0x34;
+        let mut wisdom_tree = WisdomTree::new(rom);
+        wisdom_tree.write_rom(0x0103, 0xFF);
+        assert_eq!(wisdom_tree.read_rom(0x0000), 0x33);
+        assert_eq!(wisdom_tree.read_rom(0x4000), 0x34);
+        // writes to 0x4000-0x7FFF do not switch banks
+        wisdom_tree.write_rom(0x4000, 0x00);
+        assert_eq!(wisdom_tree.rom_bank(0x4000), 7);
+    }
+}
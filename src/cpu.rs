@@ -0,0 +1,861 @@
+use std::convert::Infallible;
+
+use crate::{
+    boot::Model,
+    bus::Bus,
+    decode_cache::DecodeCache,
+    instructions::{
+        CondOperand, Instruction, R16MemOperand, R16Operand, R16StkOperand, R8Operand, U3Operand,
+    },
+    parser::decode_instruction,
+    registers::{FlagKind, R16Kind, R8Kind, Registers},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Running,
+    /// waiting for an interrupt after halt
+    Halted,
+    /// waiting for an interrupt after stop
+    Stopped,
+    /// hard-locked after executing an illegal opcode, or stuck in the boot ROM
+    Locked,
+}
+
+#[derive(Debug)]
+pub struct Cpu {
+    registers: Registers,
+    /// program counter
+    pc: u16,
+    /// interrupt master enable flag
+    ime: bool,
+    /// set by ei, IME is only enabled after the following instruction
+    ime_scheduled: bool,
+    state: State,
+    /// set when halt is executed with IME disabled while an interrupt is pending, which causes the
+    /// next byte to be read twice
+    halt_bug: bool,
+    decode_cache: Option<DecodeCache>,
+}
+
+impl Cpu {
+    /// Creates a CPU in the state the DMG boot ROM leaves it in when handing over to the cartridge.
+    pub fn new() -> Self {
+        let mut registers = Registers::new();
+        *registers.get_mut_r16(R16Kind::AF) = 0x01B0;
+        *registers.get_mut_r16(R16Kind::BC) = 0x0013;
+        *registers.get_mut_r16(R16Kind::DE) = 0x00D8;
+        *registers.get_mut_r16(R16Kind::HL) = 0x014D;
+        *registers.get_mut_r16(R16Kind::SP) = 0xFFFE;
+        Cpu {
+            registers,
+            pc: 0x0100,
+            ime: false,
+            ime_scheduled: false,
+            state: State::Running,
+            halt_bug: false,
+            decode_cache: Some(DecodeCache::new()),
+        }
+    }
+
+    /// Creates a CPU in the state the boot ROM of `model` leaves it in, in CGB mode if `cgb_mode` is
+    /// set. Some flags depend on the header checksum of the cartridge.
+    pub fn post_boot(model: Model, cgb_mode: bool, header_checksum: u8) -> Self {
+        let mut cpu = Cpu::new();
+        let registers = &mut cpu.registers;
+        let checksum_flags = if header_checksum == 0 { 0x80 } else { 0xB0 };
+        let (af, bc, de, hl) = match model {
+            Model::Dmg => (0x0100 | checksum_flags, 0x0013, 0x00D8, 0x014D),
+            Model::Mgb => (0xFF00 | checksum_flags, 0x0013, 0x00D8, 0x014D),
+            Model::Sgb => (0x0100, 0x0014, 0x0000, 0xC060),
+            Model::Cgb if cgb_mode => (0x1180, 0x0000, 0xFF56, 0x000D),
+            Model::Cgb => (0x1180, 0x0000, 0x0008, 0x007C),
+        };
+        *registers.get_mut_r16(R16Kind::AF) = af;
+        *registers.get_mut_r16(R16Kind::BC) = bc;
+        *registers.get_mut_r16(R16Kind::DE) = de;
+        *registers.get_mut_r16(R16Kind::HL) = hl;
+        cpu
+    }
+
+    /// Creates a CPU as it starts executing the boot ROM.
+    pub fn power_on() -> Self {
+        Cpu {
+            registers: Registers::new(),
+            pc: 0x0000,
+            ..Cpu::new()
+        }
+    }
+
+    /// Stops execution for good, like the boot ROM does when the cartridge fails its checks.
+    pub fn lock(&mut self) {
+        self.state = State::Locked;
+    }
+
+    /// Enables or disables caching of decoded instructions. Execution is identical either way.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        if enabled != self.decode_cache.is_some() {
+            self.decode_cache = enabled.then(DecodeCache::new);
+        }
+    }
+
+    /// Drops all cached instructions, so that the next fetches go through the bus again.
+    pub fn clear_decode_cache(&mut self) {
+        if let Some(decode_cache) = &mut self.decode_cache {
+            *decode_cache = DecodeCache::new();
+        }
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    /// Executes one instruction, dispatches one interrupt or idles for one M-cycle if the CPU is
+    /// halted or paused by a DMA. Returns the executed instruction, if any.
+    pub fn step(&mut self, bus: &mut impl Bus) -> Option<Instruction> {
+        if bus.stalled() {
+            bus.tick();
+            return None;
+        }
+        match self.state {
+            State::Running => {}
+            State::Halted | State::Stopped => {
+                if bus.pending_interrupts() == 0 {
+                    bus.tick();
+                    return None;
+                }
+                self.state = State::Running;
+            }
+            State::Locked => {
+                bus.tick();
+                return None;
+            }
+        }
+
+        if self.ime && bus.pending_interrupts() != 0 {
+            self.dispatch_interrupt(bus);
+            return None;
+        }
+        if self.ime_scheduled {
+            self.ime_scheduled = false;
+            self.ime = true;
+        }
+
+        let instruction = self.fetch_instruction(bus);
+        self.execute(&instruction, bus);
+        Some(instruction)
+    }
+
+    fn dispatch_interrupt(&mut self, bus: &mut impl Bus) {
+        self.ime = false;
+        bus.tick();
+        bus.tick();
+        let [low, high] = self.pc.to_le_bytes();
+        self.sp_dec();
+        self.write_cycle(bus, self.sp(), high);
+        // pushing the high byte can overwrite IE, in which case the dispatch is cancelled and
+        // execution continues at 0x0000
+        let pending = bus.pending_interrupts();
+        self.sp_dec();
+        self.write_cycle(bus, self.sp(), low);
+        self.pc = if pending == 0 {
+            0x0000
+        } else {
+            let bit = pending.trailing_zeros() as u16;
+            bus.acknowledge_interrupts(1 << bit);
+            0x0040 + bit * 8
+        };
+        bus.tick();
+    }
+
+    fn fetch_instruction(&mut self, bus: &mut impl Bus) -> Instruction {
+        if let Some(decode_cache) = self.decode_cache.as_mut().filter(|_| !self.halt_bug) {
+            if let Some((instruction, len)) = decode_cache.fetch(bus, self.pc) {
+                // the bytes are not read again, but fetching them still takes time
+                for _ in 0..len {
+                    bus.tick();
+                }
+                self.pc = self.pc.wrapping_add(len as u16);
+                return instruction;
+            }
+        }
+
+        let opcode = self.fetch_byte(bus);
+        let Ok(instruction) =
+            decode_instruction(opcode, || Ok::<u8, Infallible>(self.fetch_byte(bus)));
+        instruction
+    }
+
+    fn fetch_byte(&mut self, bus: &mut impl Bus) -> u8 {
+        let byte = self.read_cycle(bus, self.pc);
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.pc = self.pc.wrapping_add(1);
+        }
+        byte
+    }
+
+    fn read_cycle(&mut self, bus: &mut impl Bus, address: u16) -> u8 {
+        bus.tick();
+        bus.read(address)
+    }
+
+    fn write_cycle(&mut self, bus: &mut impl Bus, address: u16, value: u8) {
+        bus.tick();
+        bus.write(address, value);
+        if let Some(decode_cache) = &mut self.decode_cache {
+            decode_cache.invalidate(address);
+        }
+    }
+
+    fn execute(&mut self, instruction: &Instruction, bus: &mut impl Bus) {
+        match *instruction {
+            Instruction::Nop => {}
+            Instruction::LoadImm16 { dst, imm } => self.set_r16(dst, imm),
+            Instruction::StoreARegToMem { dst } => {
+                let address = self.r16mem_address(dst);
+                let a = self.a();
+                self.write_cycle(bus, address, a);
+            }
+            Instruction::LoadMemToAReg { src } => {
+                let address = self.r16mem_address(src);
+                let value = self.read_cycle(bus, address);
+                self.set_a(value);
+            }
+            Instruction::StoreSPToImmMem { dst } => {
+                let [low, high] = self.sp().to_le_bytes();
+                self.write_cycle(bus, dst, low);
+                self.write_cycle(bus, dst.wrapping_add(1), high);
+            }
+            Instruction::IncR16 { reg } => {
+                self.set_r16(reg, self.r16(reg).wrapping_add(1));
+                bus.tick();
+            }
+            Instruction::DecR16 { reg } => {
+                self.set_r16(reg, self.r16(reg).wrapping_sub(1));
+                bus.tick();
+            }
+            Instruction::AddToHLReg { reg } => {
+                let hl = self.registers.get_r16(R16Kind::HL);
+                let value = self.r16(reg);
+                let (result, carry) = hl.overflowing_add(value);
+                self.set_flag(FlagKind::N, false);
+                self.set_flag(FlagKind::H, (hl & 0x0FFF) + (value & 0x0FFF) > 0x0FFF);
+                self.set_flag(FlagKind::C, carry);
+                *self.registers.get_mut_r16(R16Kind::HL) = result;
+                bus.tick();
+            }
+            Instruction::IncR8 { reg } => {
+                let value = self.r8(bus, reg);
+                let result = value.wrapping_add(1);
+                self.set_flag(FlagKind::Z, result == 0);
+                self.set_flag(FlagKind::N, false);
+                self.set_flag(FlagKind::H, value & 0x0F == 0x0F);
+                self.set_r8(bus, reg, result);
+            }
+            Instruction::DecR8 { reg } => {
+                let value = self.r8(bus, reg);
+                let result = value.wrapping_sub(1);
+                self.set_flag(FlagKind::Z, result == 0);
+                self.set_flag(FlagKind::N, true);
+                self.set_flag(FlagKind::H, value & 0x0F == 0);
+                self.set_r8(bus, reg, result);
+            }
+            Instruction::LoadImm8 { dst, imm } => self.set_r8(bus, dst, imm),
+            Instruction::RotARegLeftSetC => {
+                let result = self.rotate_left(self.a(), false);
+                self.set_a(result);
+                self.set_flag(FlagKind::Z, false);
+            }
+            Instruction::RotARegRightSetC => {
+                let result = self.rotate_right(self.a(), false);
+                self.set_a(result);
+                self.set_flag(FlagKind::Z, false);
+            }
+            Instruction::RotARegLeftThroughC => {
+                let result = self.rotate_left(self.a(), true);
+                self.set_a(result);
+                self.set_flag(FlagKind::Z, false);
+            }
+            Instruction::RotARegRightThroughC => {
+                let result = self.rotate_right(self.a(), true);
+                self.set_a(result);
+                self.set_flag(FlagKind::Z, false);
+            }
+            Instruction::DecAdjAccum => self.decimal_adjust_accumulator(),
+            Instruction::InvA => {
+                self.set_a(!self.a());
+                self.set_flag(FlagKind::N, true);
+                self.set_flag(FlagKind::H, true);
+            }
+            Instruction::SetC => {
+                self.set_flag(FlagKind::N, false);
+                self.set_flag(FlagKind::H, false);
+                self.set_flag(FlagKind::C, true);
+            }
+            Instruction::InvC => {
+                self.set_flag(FlagKind::N, false);
+                self.set_flag(FlagKind::H, false);
+                self.set_flag(FlagKind::C, !self.flag(FlagKind::C));
+            }
+            Instruction::JumpRelativeImm { imm } => {
+                self.pc = self.pc.wrapping_add_signed(imm as i16);
+                bus.tick();
+            }
+            Instruction::JumpRelativeImmUnderCond { cond, imm } => {
+                if self.condition(cond) {
+                    self.pc = self.pc.wrapping_add_signed(imm as i16);
+                    bus.tick();
+                }
+            }
+            Instruction::Stop => {
+                if !bus.switch_speed() {
+                    self.state = State::Stopped;
+                }
+            }
+            Instruction::LoadR8ToR8 { dst, src } => {
+                let value = self.r8(bus, src);
+                self.set_r8(bus, dst, value);
+            }
+            Instruction::Halt => {
+                if self.ime || bus.pending_interrupts() == 0 {
+                    self.state = State::Halted;
+                } else {
+                    self.halt_bug = true;
+                }
+            }
+            Instruction::AddRegToAReg { reg } => {
+                let value = self.r8(bus, reg);
+                self.add(value, false);
+            }
+            Instruction::AddRegCToAReg { reg } => {
+                let value = self.r8(bus, reg);
+                self.add(value, true);
+            }
+            Instruction::SubRegFromAReg { reg } => {
+                let value = self.r8(bus, reg);
+                let result = self.sub(value, false);
+                self.set_a(result);
+            }
+            Instruction::SubRegCFromAReg { reg } => {
+                let value = self.r8(bus, reg);
+                let result = self.sub(value, true);
+                self.set_a(result);
+            }
+            Instruction::AndRegToAReg { reg } => {
+                let value = self.r8(bus, reg);
+                self.and(value);
+            }
+            Instruction::XorRegToAReg { reg } => {
+                let value = self.r8(bus, reg);
+                self.xor(value);
+            }
+            Instruction::OrRegToAReg { reg } => {
+                let value = self.r8(bus, reg);
+                self.or(value);
+            }
+            Instruction::CmpRegToAReg { reg } => {
+                let value = self.r8(bus, reg);
+                self.sub(value, false);
+            }
+            Instruction::AddImmToAReg { imm } => self.add(imm, false),
+            Instruction::AddImmCToAReg { imm } => self.add(imm, true),
+            Instruction::SubImmFromAReg { imm } => {
+                let result = self.sub(imm, false);
+                self.set_a(result);
+            }
+            Instruction::SubImmCFromAReg { imm } => {
+                let result = self.sub(imm, true);
+                self.set_a(result);
+            }
+            Instruction::AndImmToAReg { imm } => self.and(imm),
+            Instruction::XorImmToAReg { imm } => self.xor(imm),
+            Instruction::OrImmToAReg { imm } => self.or(imm),
+            Instruction::CmpImmToAReg { imm } => {
+                self.sub(imm, false);
+            }
+            Instruction::RetUnderCond { cond } => {
+                bus.tick();
+                if self.condition(cond) {
+                    self.pc = self.pop(bus);
+                    bus.tick();
+                }
+            }
+            Instruction::Ret => {
+                self.pc = self.pop(bus);
+                bus.tick();
+            }
+            Instruction::RetInterrupts => {
+                self.pc = self.pop(bus);
+                self.ime = true;
+                bus.tick();
+            }
+            Instruction::JumpImmUnderCond { cond, imm } => {
+                if self.condition(cond) {
+                    self.pc = imm;
+                    bus.tick();
+                }
+            }
+            Instruction::JumpImm { imm } => {
+                self.pc = imm;
+                bus.tick();
+            }
+            Instruction::JumpHL => self.pc = self.registers.get_r16(R16Kind::HL),
+            Instruction::CallImmUnderCond { cond, imm } => {
+                if self.condition(cond) {
+                    self.call(bus, imm);
+                }
+            }
+            Instruction::CallImm { imm } => self.call(bus, imm),
+            Instruction::CallRst { target } => self.call(bus, target as u16 * 8),
+            Instruction::Pop { reg } => {
+                let value = self.pop(bus);
+                match reg {
+                    R16StkOperand::BCReg => *self.registers.get_mut_r16(R16Kind::BC) = value,
+                    R16StkOperand::DEReg => *self.registers.get_mut_r16(R16Kind::DE) = value,
+                    R16StkOperand::HLReg => *self.registers.get_mut_r16(R16Kind::HL) = value,
+                    // the lower 4 bits of the F register are always zero
+                    R16StkOperand::AFReg => {
+                        *self.registers.get_mut_r16(R16Kind::AF) = value & 0xFFF0
+                    }
+                }
+            }
+            Instruction::Push { reg } => {
+                let value = match reg {
+                    R16StkOperand::BCReg => self.registers.get_r16(R16Kind::BC),
+                    R16StkOperand::DEReg => self.registers.get_r16(R16Kind::DE),
+                    R16StkOperand::HLReg => self.registers.get_r16(R16Kind::HL),
+                    R16StkOperand::AFReg => self.registers.get_r16(R16Kind::AF),
+                };
+                bus.tick();
+                self.push(bus, value);
+            }
+            Instruction::StoreARegToCMem => {
+                let address = 0xFF00 | self.registers.get_r8(R8Kind::C) as u16;
+                let a = self.a();
+                self.write_cycle(bus, address, a);
+            }
+            Instruction::StoreARegToImm8Mem { imm } => {
+                let a = self.a();
+                self.write_cycle(bus, 0xFF00 | imm as u16, a);
+            }
+            Instruction::StoreARegToImm16Mem { imm } => {
+                let a = self.a();
+                self.write_cycle(bus, imm, a);
+            }
+            Instruction::LoadCMemToAReg => {
+                let address = 0xFF00 | self.registers.get_r8(R8Kind::C) as u16;
+                let value = self.read_cycle(bus, address);
+                self.set_a(value);
+            }
+            Instruction::LoadImm8MemToAReg { imm } => {
+                let value = self.read_cycle(bus, 0xFF00 | imm as u16);
+                self.set_a(value);
+            }
+            Instruction::LoadImm16MemToAReg { imm } => {
+                let value = self.read_cycle(bus, imm);
+                self.set_a(value);
+            }
+            Instruction::AddImmToSP { imm } => {
+                let result = self.add_sp_offset(imm);
+                *self.registers.get_mut_r16(R16Kind::SP) = result;
+                bus.tick();
+                bus.tick();
+            }
+            Instruction::LoadSPWithImmToHLReg { imm } => {
+                let result = self.add_sp_offset(imm);
+                *self.registers.get_mut_r16(R16Kind::HL) = result;
+                bus.tick();
+            }
+            Instruction::LoadHLRegToSP => {
+                *self.registers.get_mut_r16(R16Kind::SP) = self.registers.get_r16(R16Kind::HL);
+                bus.tick();
+            }
+            Instruction::DisableInterrupts => {
+                self.ime = false;
+                self.ime_scheduled = false;
+            }
+            Instruction::EnableInterrupts => self.ime_scheduled = true,
+            Instruction::RotR8LeftSetC { reg } => {
+                self.modify_r8(bus, reg, |cpu, value| cpu.rotate_left(value, false))
+            }
+            Instruction::RotR8RightSetC { reg } => {
+                self.modify_r8(bus, reg, |cpu, value| cpu.rotate_right(value, false))
+            }
+            Instruction::RotR8LeftThroughC { reg } => {
+                self.modify_r8(bus, reg, |cpu, value| cpu.rotate_left(value, true))
+            }
+            Instruction::RotR8RightThroughC { reg } => {
+                self.modify_r8(bus, reg, |cpu, value| cpu.rotate_right(value, true))
+            }
+            Instruction::ShiftLeftArith { reg } => self.modify_r8(bus, reg, |cpu, value| {
+                cpu.set_shift_flags(value << 1, value & 0x80 != 0)
+            }),
+            Instruction::ShiftRightArith { reg } => self.modify_r8(bus, reg, |cpu, value| {
+                cpu.set_shift_flags((value >> 1) | (value & 0x80), value & 0x01 != 0)
+            }),
+            Instruction::SwapHighLowR8 { reg } => self.modify_r8(bus, reg, |cpu, value| {
+                cpu.set_shift_flags(value.rotate_left(4), false)
+            }),
+            Instruction::ShiftRightLogic { reg } => self.modify_r8(bus, reg, |cpu, value| {
+                cpu.set_shift_flags(value >> 1, value & 0x01 != 0)
+            }),
+            Instruction::TestBit { bit_num, reg } => {
+                let value = self.r8(bus, reg);
+                self.set_flag(FlagKind::Z, value & bit_mask(bit_num) == 0);
+                self.set_flag(FlagKind::N, false);
+                self.set_flag(FlagKind::H, true);
+            }
+            Instruction::SetBitZero { bit_num, reg } => {
+                self.modify_r8(bus, reg, |_, value| value & !bit_mask(bit_num))
+            }
+            Instruction::SetBitOne { bit_num, reg } => {
+                self.modify_r8(bus, reg, |_, value| value | bit_mask(bit_num))
+            }
+            Instruction::Illegal { .. } => self.state = State::Locked,
+        }
+    }
+
+    fn a(&self) -> u8 {
+        self.registers.get_r8(R8Kind::A)
+    }
+
+    fn set_a(&mut self, value: u8) {
+        *self.registers.get_mut_r8(R8Kind::A) = value;
+    }
+
+    fn sp(&self) -> u16 {
+        self.registers.get_r16(R16Kind::SP)
+    }
+
+    fn sp_dec(&mut self) {
+        let sp = self.registers.get_mut_r16(R16Kind::SP);
+        *sp = sp.wrapping_sub(1);
+    }
+
+    fn flag(&self, flag_kind: FlagKind) -> bool {
+        self.registers.get_flag(flag_kind)
+    }
+
+    fn set_flag(&mut self, flag_kind: FlagKind, value: bool) {
+        self.registers.set_flag(flag_kind, value);
+    }
+
+    fn condition(&self, cond: CondOperand) -> bool {
+        match cond {
+            CondOperand::NZ => !self.flag(FlagKind::Z),
+            CondOperand::Z => self.flag(FlagKind::Z),
+            CondOperand::NC => !self.flag(FlagKind::C),
+            CondOperand::C => self.flag(FlagKind::C),
+        }
+    }
+
+    fn r8(&mut self, bus: &mut impl Bus, reg: R8Operand) -> u8 {
+        match r8_kind(reg) {
+            Some(kind) => self.registers.get_r8(kind),
+            None => {
+                let address = self.registers.get_r16(R16Kind::HL);
+                self.read_cycle(bus, address)
+            }
+        }
+    }
+
+    fn set_r8(&mut self, bus: &mut impl Bus, reg: R8Operand, value: u8) {
+        match r8_kind(reg) {
+            Some(kind) => *self.registers.get_mut_r8(kind) = value,
+            None => {
+                let address = self.registers.get_r16(R16Kind::HL);
+                self.write_cycle(bus, address, value);
+            }
+        }
+    }
+
+    fn modify_r8(
+        &mut self,
+        bus: &mut impl Bus,
+        reg: R8Operand,
+        operation: impl FnOnce(&mut Self, u8) -> u8,
+    ) {
+        let value = self.r8(bus, reg);
+        let result = operation(self, value);
+        self.set_r8(bus, reg, result);
+    }
+
+    fn r16(&self, reg: R16Operand) -> u16 {
+        self.registers.get_r16(r16_kind(reg))
+    }
+
+    fn set_r16(&mut self, reg: R16Operand, value: u16) {
+        *self.registers.get_mut_r16(r16_kind(reg)) = value;
+    }
+
+    /// Returns the address of a [r16mem] operand and applies its post-increment or -decrement.
+    fn r16mem_address(&mut self, reg: R16MemOperand) -> u16 {
+        match reg {
+            R16MemOperand::BCReg => self.registers.get_r16(R16Kind::BC),
+            R16MemOperand::DEReg => self.registers.get_r16(R16Kind::DE),
+            R16MemOperand::HLRegAndInc => {
+                let hl = self.registers.get_mut_r16(R16Kind::HL);
+                let address = *hl;
+                *hl = address.wrapping_add(1);
+                address
+            }
+            R16MemOperand::HLRegAndDec => {
+                let hl = self.registers.get_mut_r16(R16Kind::HL);
+                let address = *hl;
+                *hl = address.wrapping_sub(1);
+                address
+            }
+        }
+    }
+
+    fn push(&mut self, bus: &mut impl Bus, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.sp_dec();
+        self.write_cycle(bus, self.sp(), high);
+        self.sp_dec();
+        self.write_cycle(bus, self.sp(), low);
+    }
+
+    fn pop(&mut self, bus: &mut impl Bus) -> u16 {
+        let sp = self.sp();
+        let low = self.read_cycle(bus, sp);
+        let high = self.read_cycle(bus, sp.wrapping_add(1));
+        *self.registers.get_mut_r16(R16Kind::SP) = sp.wrapping_add(2);
+        u16::from_le_bytes([low, high])
+    }
+
+    fn call(&mut self, bus: &mut impl Bus, address: u16) {
+        bus.tick();
+        self.push(bus, self.pc);
+        self.pc = address;
+    }
+
+    fn add(&mut self, value: u8, with_carry: bool) {
+        let a = self.a();
+        let carry = (with_carry && self.flag(FlagKind::C)) as u8;
+        let result = a.wrapping_add(value).wrapping_add(carry);
+        self.set_flag(FlagKind::Z, result == 0);
+        self.set_flag(FlagKind::N, false);
+        self.set_flag(FlagKind::H, (a & 0x0F) + (value & 0x0F) + carry > 0x0F);
+        self.set_flag(FlagKind::C, a as u16 + value as u16 + carry as u16 > 0xFF);
+        self.set_a(result);
+    }
+
+    /// Subtracts `value` from the A register and sets the flags, but only returns the result so
+    /// that cp can share this.
+    fn sub(&mut self, value: u8, with_carry: bool) -> u8 {
+        let a = self.a();
+        let carry = (with_carry && self.flag(FlagKind::C)) as u8;
+        let result = a.wrapping_sub(value).wrapping_sub(carry);
+        self.set_flag(FlagKind::Z, result == 0);
+        self.set_flag(FlagKind::N, true);
+        self.set_flag(FlagKind::H, (a & 0x0F) < (value & 0x0F) + carry);
+        self.set_flag(FlagKind::C, (a as u16) < value as u16 + carry as u16);
+        result
+    }
+
+    fn and(&mut self, value: u8) {
+        let result = self.a() & value;
+        self.set_a(result);
+        self.set_flag(FlagKind::Z, result == 0);
+        self.set_flag(FlagKind::N, false);
+        self.set_flag(FlagKind::H, true);
+        self.set_flag(FlagKind::C, false);
+    }
+
+    fn xor(&mut self, value: u8) {
+        let result = self.a() ^ value;
+        self.set_a(result);
+        self.set_shift_flags(result, false);
+    }
+
+    fn or(&mut self, value: u8) {
+        let result = self.a() | value;
+        self.set_a(result);
+        self.set_shift_flags(result, false);
+    }
+
+    /// Sets Z according to `result`, C to `carry` and clears N and H. Returns `result`.
+    fn set_shift_flags(&mut self, result: u8, carry: bool) -> u8 {
+        self.set_flag(FlagKind::Z, result == 0);
+        self.set_flag(FlagKind::N, false);
+        self.set_flag(FlagKind::H, false);
+        self.set_flag(FlagKind::C, carry);
+        result
+    }
+
+    fn rotate_left(&mut self, value: u8, through_carry: bool) -> u8 {
+        let low_bit = if through_carry {
+            self.flag(FlagKind::C) as u8
+        } else {
+            value >> 7
+        };
+        self.set_shift_flags((value << 1) | low_bit, value & 0x80 != 0)
+    }
+
+    fn rotate_right(&mut self, value: u8, through_carry: bool) -> u8 {
+        let high_bit = if through_carry {
+            self.flag(FlagKind::C) as u8
+        } else {
+            value & 0x01
+        };
+        self.set_shift_flags((value >> 1) | (high_bit << 7), value & 0x01 != 0)
+    }
+
+    fn decimal_adjust_accumulator(&mut self) {
+        let mut a = self.a();
+        let mut carry = self.flag(FlagKind::C);
+        if self.flag(FlagKind::N) {
+            if self.flag(FlagKind::H) {
+                a = a.wrapping_sub(0x06);
+            }
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+        } else {
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if self.flag(FlagKind::H) || a & 0x0F > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
+        }
+        self.set_a(a);
+        self.set_flag(FlagKind::Z, a == 0);
+        self.set_flag(FlagKind::H, false);
+        self.set_flag(FlagKind::C, carry);
+    }
+
+    /// Computes SP plus a signed offset, with H and C set from the unsigned addition of the low
+    /// bytes as the hardware does.
+    fn add_sp_offset(&mut self, offset: i8) -> u16 {
+        let sp = self.sp();
+        let value = offset as u8 as u16;
+        self.set_flag(FlagKind::Z, false);
+        self.set_flag(FlagKind::N, false);
+        self.set_flag(FlagKind::H, (sp & 0x0F) + (value & 0x0F) > 0x0F);
+        self.set_flag(FlagKind::C, (sp & 0xFF) + value > 0xFF);
+        sp.wrapping_add_signed(offset as i16)
+    }
+}
+
+fn r8_kind(reg: R8Operand) -> Option<R8Kind> {
+    match reg {
+        R8Operand::AReg => Some(R8Kind::A),
+        R8Operand::BReg => Some(R8Kind::B),
+        R8Operand::CReg => Some(R8Kind::C),
+        R8Operand::DReg => Some(R8Kind::D),
+        R8Operand::EReg => Some(R8Kind::E),
+        R8Operand::HReg => Some(R8Kind::H),
+        R8Operand::LReg => Some(R8Kind::L),
+        R8Operand::HLAddr => None,
+    }
+}
+
+fn r16_kind(reg: R16Operand) -> R16Kind {
+    match reg {
+        R16Operand::BCReg => R16Kind::BC,
+        R16Operand::DEReg => R16Kind::DE,
+        R16Operand::HLReg => R16Kind::HL,
+        R16Operand::SP => R16Kind::SP,
+    }
+}
+
+fn bit_mask(bit_num: U3Operand) -> u8 {
+    1 << bit_num as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bus::FlatBus;
+
+    use super::*;
+
+    /// Runs `program` from `start` until the CPU halts.
+    fn run_program_at(program: &[u8], start: u16, decode_cache: bool) -> Cpu {
+        let mut bus = FlatBus::with_rom(&[]);
+        for (offset, byte) in program.iter().enumerate() {
+            bus.write(start + offset as u16, *byte);
+        }
+        let mut cpu = Cpu::new();
+        cpu.set_decode_cache(decode_cache);
+        cpu.pc = start;
+        while cpu.step(&mut bus) != Some(Instruction::Halt) {}
+        cpu
+    }
+
+    /// Runs `program` from 0xC000 until the CPU halts.
+    fn run_program(program: &[u8]) -> Cpu {
+        run_program_at(program, 0xC000, true)
+    }
+
+    #[test]
+    fn arithmetic_flags() {
+        // ld a, 0x0F; add a, 0x01; halt
+        let cpu = run_program(&[0x3E, 0x0F, 0xC6, 0x01, 0x76]);
+        assert_eq!(cpu.registers.get_r8(R8Kind::A), 0x10);
+        assert!(!cpu.registers.get_flag(FlagKind::Z));
+        assert!(cpu.registers.get_flag(FlagKind::H));
+        assert!(!cpu.registers.get_flag(FlagKind::C));
+
+        // ld a, 0x10; sub a, 0x20; halt
+        let cpu = run_program(&[0x3E, 0x10, 0xD6, 0x20, 0x76]);
+        assert_eq!(cpu.registers.get_r8(R8Kind::A), 0xF0);
+        assert!(cpu.registers.get_flag(FlagKind::N));
+        assert!(!cpu.registers.get_flag(FlagKind::H));
+        assert!(cpu.registers.get_flag(FlagKind::C));
+    }
+
+    #[test]
+    fn decimal_adjust() {
+        // ld a, 0x19; add a, 0x28; daa; halt
+        let cpu = run_program(&[0x3E, 0x19, 0xC6, 0x28, 0x27, 0x76]);
+        assert_eq!(cpu.registers.get_r8(R8Kind::A), 0x47);
+    }
+
+    #[test]
+    fn call_and_return() {
+        // ld sp, 0xD000; call 0xC008; halt; nop; ld b, 0x42; ret
+        let cpu = run_program(&[
+            0x31, 0x00, 0xD0, 0xCD, 0x08, 0xC0, 0x76, 0x00, 0x06, 0x42, 0xC9,
+        ]);
+        assert_eq!(cpu.registers.get_r8(R8Kind::B), 0x42);
+        assert_eq!(cpu.registers.get_r16(R16Kind::SP), 0xD000);
+        assert_eq!(cpu.pc, 0xC007);
+    }
+
+    #[test]
+    fn pop_af_masks_flags() {
+        // ld bc, 0x12FF; push bc; pop af; halt
+        let cpu = run_program(&[0x01, 0xFF, 0x12, 0xC5, 0xF1, 0x76]);
+        assert_eq!(cpu.registers.get_r16(R16Kind::AF), 0x12F0);
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        // ld hl, start + 6; ld b, 3; loop: ld a, 0x00; inc [hl]; dec b; jr nz, loop; halt
+        // increments the immediate of its own ld a, imm8 after every execution, also through echo
+        // RAM
+        for (start, target) in [(0xC000, 0xC006), (0xFF80, 0xFF86), (0xC000, 0xE006)] {
+            let [low, high] = u16::to_le_bytes(target);
+            let program = [
+                0x21, low, high, 0x06, 0x03, 0x3E, 0x00, 0x34, 0x05, 0x20, 0xFA, 0x76,
+            ];
+            let uncached = run_program_at(&program, start, false);
+            let cached = run_program_at(&program, start, true);
+            assert_eq!(uncached.registers.get_r8(R8Kind::A), 2);
+            assert_eq!(
+                cached.registers.get_r16(R16Kind::AF),
+                uncached.registers.get_r16(R16Kind::AF)
+            );
+            assert_eq!(cached.pc, uncached.pc);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::rc::Rc;
+
+use crate::{bus::Bus, instructions::Instruction, parser::decode_instruction};
+
+/// Upper bound on the number of instructions in a single block.
+const MAX_BLOCK_LEN: usize = 64;
+
+/// A run of decoded instructions that ends after the first instruction that can change the
+/// control flow, or where the mapped memory changes.
+#[derive(Debug)]
+struct Block {
+    /// instructions together with their length in bytes
+    instructions: Vec<(Instruction, u8)>,
+}
+
+/// Position inside a block, so that straight-line code does not need a lookup per instruction.
+#[derive(Debug)]
+struct Cursor {
+    block: Rc<Block>,
+    bank: u16,
+    /// index of the next instruction in `block`
+    index: usize,
+    /// address of the next instruction in `block`
+    address: u16,
+}
+
+/// Marks an instruction whose bytes are not all in the same cacheable memory region.
+struct Uncacheable;
+
+/// Caches decoded instructions per address and mapped bank, see `Bus::mapped_bank`. The cache is
+/// direct-mapped: every address holds the block of at most one bank, which gets replaced when
+/// code from another bank runs at the same address.
+#[derive(Debug)]
+pub struct DecodeCache {
+    /// blocks by start address, together with the bank they were decoded from
+    blocks: Vec<Option<(u16, Rc<Block>)>>,
+    /// start addresses of the cached blocks overlapping each 256-byte page of writable memory
+    pages: Vec<Vec<u16>>,
+    cursor: Option<Cursor>,
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        DecodeCache {
+            blocks: vec![None; 0x10000],
+            pages: vec![Vec::new(); 0x100],
+            cursor: None,
+        }
+    }
+
+    /// Returns the instruction at `address` together with its length in bytes, decoding and
+    /// caching the block starting there if necessary. Returns `None` if the memory at `address`
+    /// can not be cached. Reading the instruction bytes is left to the caller, no time passes.
+    pub fn fetch(&mut self, bus: &mut impl Bus, address: u16) -> Option<(Instruction, u8)> {
+        let bank = bus.mapped_bank(address)?;
+
+        if let Some(cursor) = &mut self.cursor {
+            if cursor.address == address && cursor.bank == bank {
+                if let Some((instruction, len)) = cursor.block.instructions.get(cursor.index) {
+                    cursor.index += 1;
+                    cursor.address = address.wrapping_add(*len as u16);
+                    return Some((instruction.clone(), *len));
+                }
+            }
+        }
+
+        let block = match &self.blocks[address as usize] {
+            Some((block_bank, block)) if *block_bank == bank => Rc::clone(block),
+            _ => self.decode_block(bus, bank, address)?,
+        };
+        let (instruction, len) = block.instructions[0].clone();
+        self.cursor = Some(Cursor {
+            block,
+            bank,
+            index: 1,
+            address: address.wrapping_add(len as u16),
+        });
+        Some((instruction, len))
+    }
+
+    /// Drops all cached instructions that overlap `address`. Has to be called for every write.
+    pub fn invalidate(&mut self, address: u16) {
+        // ROM can not be written, writes only go to the mapper registers
+        if address < 0x8000 {
+            return;
+        }
+        // echo RAM writes go to WRAM, where the code was cached
+        let address = match address {
+            0xE000..=0xFDFF => address - 0x2000,
+            _ => address,
+        };
+        let page = &mut self.pages[(address >> 8) as usize];
+        if page.is_empty() {
+            return;
+        }
+        for start in page.drain(..) {
+            self.blocks[start as usize] = None;
+        }
+        self.cursor = None;
+    }
+
+    fn decode_block(&mut self, bus: &mut impl Bus, bank: u16, start: u16) -> Option<Rc<Block>> {
+        let mut instructions = Vec::new();
+        let mut address = start;
+        while instructions.len() < MAX_BLOCK_LEN {
+            let Some((instruction, len)) = decode_at(bus, bank, address) else {
+                break;
+            };
+            let ends_block = ends_block(&instruction);
+            instructions.push((instruction, len));
+            address = address.wrapping_add(len as u16);
+            if ends_block || address < start {
+                break;
+            }
+        }
+        if instructions.is_empty() {
+            return None;
+        }
+
+        if start >= 0x8000 {
+            let last_page = address.wrapping_sub(1) >> 8;
+            for page in start >> 8..=last_page {
+                let page = &mut self.pages[page as usize];
+                if !page.contains(&start) {
+                    page.push(start);
+                }
+            }
+        }
+        let block = Rc::new(Block { instructions });
+        self.blocks[start as usize] = Some((bank, Rc::clone(&block)));
+        Some(block)
+    }
+}
+
+/// Decodes the instruction at `address` if all of its bytes are mapped to `bank`.
+fn decode_at(bus: &mut impl Bus, bank: u16, address: u16) -> Option<(Instruction, u8)> {
+    let mut len: u8 = 0;
+    let mut next_byte = || {
+        let byte_address = address.wrapping_add(len as u16);
+        if bus.mapped_bank(byte_address) != Some(bank) || (len > 0 && byte_address == 0) {
+            return Err(Uncacheable);
+        }
+        len += 1;
+        Ok(bus.read(byte_address))
+    };
+    let opcode = next_byte().ok()?;
+    let instruction = decode_instruction(opcode, next_byte).ok()?;
+    Some((instruction, len))
+}
+
+fn ends_block(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::JumpRelativeImm { .. }
+            | Instruction::JumpRelativeImmUnderCond { .. }
+            | Instruction::Stop
+            | Instruction::Halt
+            | Instruction::RetUnderCond { .. }
+            | Instruction::Ret
+            | Instruction::RetInterrupts
+            | Instruction::JumpImmUnderCond { .. }
+            | Instruction::JumpImm { .. }
+            | Instruction::JumpHL
+            | Instruction::CallImmUnderCond { .. }
+            | Instruction::CallImm { .. }
+            | Instruction::CallRst { .. }
+            | Instruction::Illegal { .. }
+    )
+}
@@ -0,0 +1,84 @@
+/// Number of bytes an OAM DMA transfer copies, one per M-cycle.
+pub const OAM_DMA_LENGTH: u16 = 0xA0;
+
+/// The OAM DMA started by writing the upper source address byte to DMA (0xFF46), which copies
+/// 0xA0 bytes from there to OAM.
+///
+/// The transfer starts after a setup M-cycle. Writing DMA again while a transfer runs restarts it,
+/// the old transfer keeps going during the setup cycle of the new one.
+#[derive(Debug, Default)]
+pub struct OamDma {
+    /// source address of the transfer set up in the current M-cycle
+    pending: Option<u16>,
+    /// source address of the running transfer
+    source: Option<u16>,
+    /// number of bytes already copied by the running transfer
+    copied: u16,
+}
+
+impl OamDma {
+    pub fn start(&mut self, value: u8) {
+        self.pending = Some((value as u16) << 8);
+    }
+
+    /// Whether a transfer occupies the bus, which blocks the CPU from anything but HRAM.
+    #[inline]
+    pub fn is_active(&self) -> bool {
+        self.source.is_some()
+    }
+
+    /// Returns the address of the byte being copied in the current M-cycle, if any.
+    pub fn source_address(&self) -> Option<u16> {
+        self.source.map(|source| source + self.copied)
+    }
+
+    /// Advances the DMA by one M-cycle. Returns the source address and the OAM offset of the byte
+    /// to copy in this cycle.
+    pub fn tick(&mut self) -> Option<(u16, usize)> {
+        let transfer = self
+            .source_address()
+            .map(|address| (address, self.copied as usize));
+        if transfer.is_some() {
+            self.copied += 1;
+            if self.copied == OAM_DMA_LENGTH {
+                self.source = None;
+            }
+        }
+        if let Some(source) = self.pending.take() {
+            self.source = Some(source);
+            self.copied = 0;
+        }
+        transfer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn setup_cycle_and_length() {
+        let mut dma = OamDma::default();
+        dma.start(0xC1);
+        assert!(!dma.is_active());
+        assert_eq!(dma.tick(), None);
+        assert!(dma.is_active());
+        assert_eq!(dma.tick(), Some((0xC100, 0)));
+        for offset in 1..OAM_DMA_LENGTH as usize {
+            assert_eq!(dma.tick(), Some((0xC100 + offset as u16, offset)));
+        }
+        assert!(!dma.is_active());
+        assert_eq!(dma.tick(), None);
+    }
+
+    #[test]
+    fn restart_continues_during_setup() {
+        let mut dma = OamDma::default();
+        dma.start(0xC1);
+        dma.tick();
+        dma.tick();
+        dma.start(0xC2);
+        assert_eq!(dma.tick(), Some((0xC101, 1)));
+        assert_eq!(dma.tick(), Some((0xC200, 0)));
+    }
+}
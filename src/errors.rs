@@ -2,10 +2,19 @@ use std::io;
 
 use thiserror::Error;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum EmulatorError {
     #[error("IOError: {0}")]
     IoError(#[from] io::Error),
     #[error("PlatformError: {0}")]
     PlatformError(String),
+    #[error("CartridgeError: {0}")]
+    CartridgeError(String),
+    #[error("ImageError: {0}")]
+    ImageError(String),
+    #[error("BootRomError: {0}")]
+    BootRomError(String),
+    #[error("StateError: {0}")]
+    StateError(String),
 }
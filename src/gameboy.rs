@@ -0,0 +1,144 @@
+use crate::{
+    boot::{BootRom, Model},
+    cartridge::Cartridge,
+    cpu::Cpu,
+    instructions::Instruction,
+    memory::MemoryBus,
+    ppu::{ColorCorrection, Renderer},
+    watchpoint::{WatchHit, Watchpoint},
+};
+
+/// Number of M-cycles the DMG runs per second.
+pub const CYCLES_PER_SECOND: u64 = 1 << 20;
+
+/// The whole console, a CPU connected to the memory bus with a cartridge inserted.
+pub struct GameBoy {
+    cpu: Cpu,
+    bus: MemoryBus,
+    watch_hit: Option<WatchHit>,
+}
+
+impl GameBoy {
+    /// Starts the game directly in the state the boot ROM of `model` leaves behind. The checks of
+    /// the boot ROM are still done, a cartridge failing them locks up the console.
+    pub fn new(cartridge: Cartridge, model: Model) -> Self {
+        // the logo has to be read before the mapper is moved past the boot sequence
+        let logo_matches = cartridge.logo_matches(model.checked_logo_len());
+        let header_checksum = cartridge.header().header_checksum;
+        let bus = MemoryBus::new(cartridge, model);
+        let mut cpu = Cpu::post_boot(model, bus.cgb_mode(), header_checksum);
+        if !logo_matches {
+            cpu.lock();
+        }
+        GameBoy {
+            cpu,
+            bus,
+            watch_hit: None,
+        }
+    }
+
+    /// Starts the console from power on, running `boot_rom` before the game.
+    pub fn with_boot_rom(cartridge: Cartridge, boot_rom: BootRom) -> Self {
+        GameBoy {
+            cpu: Cpu::power_on(),
+            bus: MemoryBus::with_boot_rom(cartridge, boot_rom),
+            watch_hit: None,
+        }
+    }
+
+    pub(crate) fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        self.bus.cartridge()
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        self.bus.cartridge_mut()
+    }
+
+    /// Number of M-cycles since power on.
+    pub fn cycles(&self) -> u64 {
+        self.bus.cycles()
+    }
+
+    /// Emulated time since power on in M-cycles of normal speed, `CYCLES_PER_SECOND` per second.
+    /// Unlike `cycles`, it does not run twice as fast in double speed.
+    pub fn time(&self) -> u64 {
+        self.bus.time()
+    }
+
+    /// The last frame the PPU completed, `SCREEN_WIDTH` × `SCREEN_HEIGHT` RGBA pixels row by row.
+    pub fn frame(&self) -> &[u8] {
+        self.bus.ppu().frame()
+    }
+
+    /// Number of frames completed since power on, for frontends to notice a new frame.
+    pub fn frames(&self) -> u64 {
+        self.bus.ppu().frames()
+    }
+
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.cpu.set_decode_cache(enabled);
+    }
+
+    /// Selects how the PPU draws, which should be done before running.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.bus.ppu_mut().set_renderer(renderer);
+    }
+
+    /// Selects how the colours of CGB games are converted for the frame.
+    pub fn set_color_correction(&mut self, color_correction: ColorCorrection) {
+        self.bus.ppu_mut().set_color_correction(color_correction);
+    }
+
+    /// Adds a watchpoint, which is reported by `take_watch_hit` once it triggers.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.bus.add_watchpoint(watchpoint);
+        // cached instructions are fetched without accessing the bus
+        self.cpu.clear_decode_cache();
+    }
+
+    /// Returns the first watchpoint hit since the last call. Callers should stop executing once
+    /// this returns a hit.
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    /// Executes one instruction, see `Cpu::step`.
+    pub fn step(&mut self) -> Option<Instruction> {
+        let pc = self.cpu.pc();
+        let instruction = self.cpu.step(&mut self.bus);
+        if let Some(access) = self.bus.take_watch_access() {
+            self.watch_hit.get_or_insert(WatchHit {
+                access,
+                pc,
+                instruction: instruction.clone(),
+            });
+        }
+        instruction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registers::R16Kind;
+
+    /// Returns DE and HL after the CGB boot ROM for a game with the CGB flag `cgb_flag`.
+    fn cgb_post_boot_registers(cgb_flag: u8) -> [u16; 2] {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0143] = cgb_flag;
+        let gameboy = GameBoy::new(Cartridge::new(rom).unwrap(), Model::Cgb);
+        [R16Kind::DE, R16Kind::HL].map(|kind| gameboy.cpu().registers().get_r16(kind))
+    }
+
+    #[test]
+    fn cgb_post_boot_registers_per_mode() {
+        assert_eq!(cgb_post_boot_registers(0x80), [0xFF56, 0x000D]);
+        assert_eq!(cgb_post_boot_registers(0xC0), [0xFF56, 0x000D]);
+        // compatibility mode
+        assert_eq!(cgb_post_boot_registers(0x00), [0x0008, 0x007C]);
+    }
+}
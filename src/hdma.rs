@@ -0,0 +1,165 @@
+/// Number of bytes copied per HBlank, also the unit of the transfer length.
+const BLOCK_SIZE: u8 = 0x10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// copies all blocks at once
+    General,
+    /// copies one block per HBlank
+    HBlank,
+}
+
+/// The CGB VRAM DMA controlled by HDMA1-HDMA5 (0xFF51-0xFF55), which copies blocks of 16 bytes
+/// from ROM or RAM to VRAM.
+///
+/// A general-purpose transfer copies everything at once, an HBlank transfer one block at the start
+/// of every HBlank. The CPU is paused while a block is copied, which takes 8 M-cycles in normal
+/// speed and 16 in double speed, the DMA running at the same rate in both.
+#[derive(Debug)]
+pub struct Hdma {
+    /// HDMA1 and HDMA2, advanced by the transfer
+    source: u16,
+    /// HDMA3 and HDMA4 as an offset into VRAM, advanced by the transfer
+    destination: u16,
+    /// number of blocks left minus one, as read from HDMA5
+    remaining: u8,
+    mode: Option<Mode>,
+    /// bytes left of the block being copied
+    block_bytes: u8,
+}
+
+impl Default for Hdma {
+    fn default() -> Self {
+        Hdma {
+            source: 0,
+            destination: 0,
+            remaining: 0x7F,
+            mode: None,
+            block_bytes: 0,
+        }
+    }
+}
+
+impl Hdma {
+    pub fn write_source_high(&mut self, value: u8) {
+        self.source = (value as u16) << 8 | (self.source & 0x00FF);
+    }
+
+    pub fn write_source_low(&mut self, value: u8) {
+        self.source = (self.source & 0xFF00) | (value & 0xF0) as u16;
+    }
+
+    pub fn write_destination_high(&mut self, value: u8) {
+        self.destination = ((value & 0x1F) as u16) << 8 | (self.destination & 0x00FF);
+    }
+
+    pub fn write_destination_low(&mut self, value: u8) {
+        self.destination = (self.destination & 0x1F00) | (value & 0xF0) as u16;
+    }
+
+    /// Reads HDMA5: bit 7 is clear while an HBlank transfer is active, the lower bits hold the
+    /// remaining length. It reads 0xFF once a transfer has completed.
+    pub fn read_control(&self) -> u8 {
+        match self.mode {
+            Some(Mode::HBlank) => self.remaining,
+            _ => 0x80 | self.remaining,
+        }
+    }
+
+    /// Writes HDMA5, which starts a transfer of `(value & 0x7F) + 1` blocks, an HBlank transfer if
+    /// bit 7 is set. Writing bit 7 clear during an HBlank transfer cancels it instead.
+    pub fn write_control(&mut self, value: u8) {
+        if self.mode == Some(Mode::HBlank) && value & 0x80 == 0 {
+            self.mode = None;
+            return;
+        }
+        self.remaining = value & 0x7F;
+        if value & 0x80 != 0 {
+            self.mode = Some(Mode::HBlank);
+        } else {
+            self.mode = Some(Mode::General);
+            self.block_bytes = BLOCK_SIZE;
+        }
+    }
+
+    /// Starts copying the next block of an HBlank transfer, called when the PPU enters HBlank.
+    pub fn hblank(&mut self) {
+        if self.mode == Some(Mode::HBlank) && self.block_bytes == 0 {
+            self.block_bytes = BLOCK_SIZE;
+        }
+    }
+
+    /// Whether a block is being copied, during which the CPU is paused.
+    #[inline]
+    pub fn is_copying(&self) -> bool {
+        self.block_bytes != 0
+    }
+
+    /// Advances the DMA by one M-cycle. Returns the source address, the VRAM offset and the number
+    /// of bytes to copy in this cycle, two in normal speed and one in double speed.
+    pub fn tick(&mut self, double_speed: bool) -> Option<(u16, u16, u16)> {
+        if self.block_bytes == 0 {
+            return None;
+        }
+        let count = if double_speed { 1 } else { 2 };
+        let transfer = (self.source, self.destination, count);
+        self.source = self.source.wrapping_add(count);
+        self.destination = (self.destination + count) & 0x1FFF;
+        self.block_bytes -= count as u8;
+        if self.block_bytes == 0 {
+            let last_block = self.remaining == 0;
+            self.remaining = self.remaining.wrapping_sub(1) & 0x7F;
+            if last_block {
+                self.mode = None;
+            } else if self.mode == Some(Mode::General) {
+                self.block_bytes = BLOCK_SIZE;
+            }
+        }
+        Some(transfer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(hdma: &mut Hdma, double_speed: bool) -> usize {
+        let mut cycles = 0;
+        while hdma.tick(double_speed).is_some() {
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn general_purpose_transfer() {
+        let mut hdma = Hdma::default();
+        hdma.write_source_high(0xC1);
+        hdma.write_source_low(0x2F);
+        hdma.write_destination_high(0xE0);
+        hdma.write_destination_low(0x10);
+        hdma.write_control(0x01);
+        assert_eq!(hdma.tick(false), Some((0xC120, 0x0010, 2)));
+        assert_eq!(run(&mut hdma, false), 15);
+        assert_eq!(hdma.read_control(), 0xFF);
+
+        hdma.write_control(0x00);
+        assert_eq!(hdma.tick(true), Some((0xC140, 0x0030, 1)));
+        assert_eq!(run(&mut hdma, true), 15);
+    }
+
+    #[test]
+    fn hblank_transfer_and_cancel() {
+        let mut hdma = Hdma::default();
+        hdma.write_control(0x82);
+        assert_eq!(hdma.read_control(), 0x02);
+        assert!(!hdma.is_copying());
+        hdma.hblank();
+        assert_eq!(run(&mut hdma, false), 8);
+        assert_eq!(hdma.read_control(), 0x01);
+        hdma.write_control(0x00);
+        assert_eq!(hdma.read_control(), 0x81);
+        hdma.hblank();
+        assert!(!hdma.is_copying());
+    }
+}
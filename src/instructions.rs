@@ -18,6 +18,7 @@ pub enum R16Operand {
     SP,
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum R16StkOperand {
     BCReg,
@@ -63,7 +64,7 @@ pub enum Instruction {
     /// ld [r16mem], a - store 8-bit value from A register into byte pointed to by 16-bit register
     StoreARegToMem { dst: R16MemOperand },
     /// ld a, [r16mem] - load 8-bit value from byte pointed to by 16-bit register into A register
-    LoadMemToAReg { dst: R16MemOperand },
+    LoadMemToAReg { src: R16MemOperand },
     /// ld [imm16], sp - store 16-bit stack pointer into the two bytes pointed to by immediate
     StoreSPToImmMem { dst: u16 },
     /// inc r16 - increment 16-bit register
@@ -80,7 +81,7 @@ pub enum Instruction {
     LoadImm8 { dst: R8Operand, imm: u8 },
     /// rlca - rotate A register left
     RotARegLeftSetC,
-    /// rrca - rotate A register left
+    /// rrca - rotate A register right
     RotARegRightSetC,
     /// rla - rotate A register left through the carry floag
     RotARegLeftThroughC,
@@ -94,10 +95,10 @@ pub enum Instruction {
     SetC,
     /// ccf - invert carry flag
     InvC,
-    /// jr imm16 - jump to address with signed 16-bit immediate offset
+    /// jr imm8 - jump to address with signed 8-bit immediate offset
     JumpRelativeImm { imm: i8 },
-    /// jr cond, imm16 - jump to address with signed 16-bit immediate offset if condition is met
-    JumpRelativeImmUnderCond { imm: i8 },
+    /// jr cond, imm8 - jump to address with signed 8-bit immediate offset if condition is met
+    JumpRelativeImmUnderCond { cond: CondOperand, imm: i8 },
     /// stop - do nothing but is (often) considered a two-byte instruction
     Stop,
     /// ld r8dst, r8src - load value from 8-bit register into another 8-bit register
@@ -125,9 +126,9 @@ pub enum Instruction {
     /// adc a, imm8 - add 8-bit immediate plus the carry flag to the A register
     AddImmCToAReg { imm: u8 },
     /// sub a, imm8 - subtract 8-bit immediate from the A register
-    SubImmFromAReg { reg: R8Operand },
+    SubImmFromAReg { imm: u8 },
     /// sbc a, imm8 - subtract 8-bit immediate and the carry flag from the A register
-    SubImmCFromAReg { reg: R8Operand },
+    SubImmCFromAReg { imm: u8 },
     /// and a, imm8 - bitwise and between 8-bit register and the A register
     AndImmToAReg { imm: u8 },
     /// xor a, imm8 - bitwise xor between 8-bit register and the A register
@@ -137,15 +138,15 @@ pub enum Instruction {
     /// cp a, imm8 - compare 8-bit register and 8-bit immediate by substracting and setting flags
     CmpImmToAReg { imm: u8 },
     /// ret cond - return from subroutine if condition is met
-    RetUnderCond,
+    RetUnderCond { cond: CondOperand },
     /// ret - return from subroutine (Pop PC)
     Ret,
     /// reti - return from subroutine and enable interrupts
     RetInterrupts,
     /// jp cond, imm16 - jump to 16-bit immediate address if condition is met
-    JumpImmUnderCond,
+    JumpImmUnderCond { cond: CondOperand, imm: u16 },
     /// jp imm16 - jump to 16-bit immediate address
-    JumpImm,
+    JumpImm { imm: u16 },
     /// jp hl - jump to 16-bit address stored in HL register
     JumpHL,
     /// call cond, imm16 - call 16-bit immediate if condition is met
@@ -155,9 +156,9 @@ pub enum Instruction {
     /// rst tgt3 - call address tgt3 * 8
     CallRst { target: U3Operand },
     /// pop r16stk - pop 16-bit register from the stack
-    Pop { reg: R16Operand },
+    Pop { reg: R16StkOperand },
     /// push r16stk - push 16-bit register to the stack
-    Push { reg: R16Operand },
+    Push { reg: R16StkOperand },
     /// ldh [c], a - store 8-bit value from A register to memory at 0xFF00 + C
     StoreARegToCMem,
     /// ldh [imm8], a - store 8-bit value from A register to memory at 0xFF00 + 8-bit immediate
@@ -181,13 +182,13 @@ pub enum Instruction {
     /// ei - enable interrupts by setting the IME flag
     EnableInterrupts,
     /// rlc r8 - rotate 8-bit register left
-    RotR8LeftSetC,
+    RotR8LeftSetC { reg: R8Operand },
     /// rrc r8 - rotate 8-bit register right
-    RotR8RightSetC,
+    RotR8RightSetC { reg: R8Operand },
     /// rl r8 - rotate 8-bit register left through the carry flag
-    RotR8LeftThroughC,
+    RotR8LeftThroughC { reg: R8Operand },
     /// rr r8 - rotate 8-bit register right through the carry flag
-    RotR8RightThroughC,
+    RotR8RightThroughC { reg: R8Operand },
     /// sla r8 - arithmetically shift left 8-bit register
     ShiftLeftArith { reg: R8Operand },
     /// sra r8 - arithmetically shift right 8-bit register
@@ -195,11 +196,13 @@ pub enum Instruction {
     /// swap r8 - swap the upper 4 bits in 8-bit register with the lower 4 bits
     SwapHighLowR8 { reg: R8Operand },
     /// srl r8 - logically shift right 8-bit register
-    ShiftLeftLogic { reg: R8Operand },
+    ShiftRightLogic { reg: R8Operand },
     /// bit b3, r8 - test b3-th bit in 8-bit register
     TestBit { bit_num: U3Operand, reg: R8Operand },
     /// res b3, r8 - set b3-th bit in 8-bit register to zero
     SetBitZero { bit_num: U3Operand, reg: R8Operand },
     /// set b3, r8 - set b3-th bit in 8-bit register to one
     SetBitOne { bit_num: U3Operand, reg: R8Operand },
+    /// one of the eleven unused opcodes - hard-locks the CPU
+    Illegal { opcode: u8 },
 }
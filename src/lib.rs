@@ -1,22 +1,37 @@
-extern crate proc_macro;
-use proc_macro::TokenStream;
+//! The emulator core, which the command line frontend in `main.rs` is built on. Other crates can
+//! use it to embed the emulator or to add cartridge hardware through `cartridge::MapperRegistry`.
 
-mod bitstring_matching;
+pub mod boot;
+pub(crate) mod bus;
+pub mod cartridge;
+pub(crate) mod cpu;
+pub(crate) mod decode_cache;
+pub(crate) mod dma;
+pub mod errors;
+pub mod gameboy;
+pub(crate) mod hdma;
+pub(crate) mod instructions;
+pub(crate) mod memory;
+pub(crate) mod mooneye;
+pub(crate) mod parser;
+pub(crate) mod pnm;
+pub mod ppu;
+pub(crate) mod reference;
+pub(crate) mod registers;
+pub mod save;
+pub(crate) mod timer;
+pub mod watchpoint;
 
-/// This macros expands 8-bit patterns to all the possible u8 values that should be matched.
-/// Example:
-/// ```
-/// # #[macro_use] extern crate gameboy_emulator;
-/// # for byte in 0..=u8::MAX {
-/// match byte /* u8 */ {
-///     bits!(00001111) => assert_eq!(byte, 15),
-///     bits!(_____101) => assert_eq!(byte % 8, 5),
-///     bits!(00__1011) => assert_eq!(byte & 0b11001111, 0b00001011),
-///     _ => {},
-/// }
-/// # }
-///
-#[proc_macro]
-pub fn bits(token_stream: TokenStream) -> TokenStream {
-    bitstring_matching::generate_all_bitstrings(token_stream)
+pub(crate) use gameboy_emulator_macros::bits;
+
+/// The test ROM harnesses and file formats of the command line frontend, not part of the library
+/// API.
+#[doc(hidden)]
+pub mod frontend {
+    pub use crate::{
+        mooneye::{run_test_directory, run_test_rom, TestOutcome},
+        parser::parse_instructions,
+        pnm::encode_ppm,
+        reference::run_reference_test,
+    };
 }
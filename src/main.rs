@@ -1,33 +1,116 @@
-use std::{fs::File, io::Read, path::PathBuf, process::ExitCode, time::Instant};
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
-mod errors;
-mod instructions;
-mod parser;
-mod registers;
-
-use errors::EmulatorError;
-use parser::parse_instructions;
+use gameboy_emulator::{
+    boot::{BootRom, Model},
+    cartridge::{CameraImage, Cartridge, CartridgeHeader, MapperKind},
+    errors::EmulatorError,
+    frontend::{
+        encode_ppm, parse_instructions, run_reference_test, run_test_directory, run_test_rom,
+        TestOutcome,
+    },
+    gameboy::{GameBoy, CYCLES_PER_SECOND},
+    ppu::{ColorCorrection, Renderer, SCREEN_HEIGHT, SCREEN_WIDTH},
+    save::{save_path, SaveFile},
+    watchpoint::Watchpoint,
+};
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Cli {
-    game_file: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[arg(required = true)]
+    game_file: Option<PathBuf>,
     #[arg(short, long)]
     debug: bool,
+    /// Run the game file, or every .gb file in the given directory, as a Mooneye test ROM
+    #[arg(long)]
+    mooneye: bool,
+    /// Decode every instruction from memory instead of caching decoded instructions
+    #[arg(long)]
+    no_decode_cache: bool,
+    /// Stop after emulating this many M-cycles instead of running until interrupted
+    #[arg(long)]
+    cycles: Option<u64>,
+    /// Tilt the cartridge by X,Y g for games with an accelerometer, positive being right and down
+    #[arg(
+        long,
+        value_names = ["X", "Y"],
+        value_delimiter = ',',
+        num_args = 2,
+        allow_negative_numbers = true
+    )]
+    tilt: Option<Vec<f32>>,
+    /// Binary PGM picture the Game Boy Camera captures, instead of a test pattern
+    #[arg(long, value_name = "FILE")]
+    camera_image: Option<PathBuf>,
+    /// Use this mapper instead of detecting it, e.g. mbc1, wisdom-tree or sachen-mmc1
+    #[arg(long)]
+    mapper: Option<MapperKind>,
+    /// Run this DMG, MGB, SGB or CGB boot ROM before the game instead of starting it directly
+    #[arg(long, value_name = "FILE")]
+    boot_rom: Option<PathBuf>,
+    /// Console model to emulate: dmg, mgb, sgb or cgb. Defaults to the model of the boot ROM,
+    /// or DMG
+    #[arg(long)]
+    model: Option<Model>,
+    /// Stop when the CPU accesses memory matching [r:|w:|rw:]START[-END][=VALUE][@BANK], in hex,
+    /// e.g. w:C100=FF to find the code writing 0xFF to 0xC100. Can be given multiple times
+    #[arg(long, value_name = "WATCHPOINT")]
+    watch: Vec<Watchpoint>,
+    /// How accurately the PPU draws: scanline draws whole lines at once, fifo draws pixel by pixel
+    /// like the hardware, which is slower but needed by games and demos that change registers in
+    /// the middle of a line
+    #[arg(long, default_value_t)]
+    renderer: Renderer,
+    /// How the colours of CGB games are shown: raw scales them as stored, as cgb-acid2 expects,
+    /// matrix approximates the colours of the CGB screen and lcd follows its response curve
+    #[arg(long, default_value_t)]
+    color_correction: ColorCorrection,
+    /// Run the game file as a test ROM drawing a picture, like dmg-acid2, and compare the screen
+    /// once it executes ld b, b with this binary PPM
+    #[arg(long, value_name = "FILE")]
+    reference: Option<PathBuf>,
+    /// Write the last frame to this binary PPM file when the emulation stops
+    #[arg(long, value_name = "FILE")]
+    screenshot: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the cartridge header of the game file and check its checksums
+    Info { game_file: PathBuf },
 }
 
 fn main() -> ExitCode {
-    let result = run();
-    if let Err(error) = result {
-        println!("{}", error);
-        return ExitCode::FAILURE;
+    match run() {
+        Ok(exit_code) => exit_code,
+        Err(error) => {
+            println!("{}", error);
+            ExitCode::FAILURE
+        }
     }
-    ExitCode::SUCCESS
 }
 
-fn run() -> Result<(), EmulatorError> {
+fn run() -> Result<ExitCode, EmulatorError> {
     if cfg!(target_endian = "big") {
         return Err(EmulatorError::PlatformError(
             "This emulator only supports little endianness.".to_string(),
@@ -37,39 +120,227 @@ fn run() -> Result<(), EmulatorError> {
     println!("Hello, gameboys!");
 
     let cli = Cli::parse();
+    if let Some(Command::Info { game_file }) = &cli.command {
+        print_cartridge_info(game_file)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let game_file = cli
+        .game_file
+        .expect("Game file should be required without a subcommand");
     println!(
         "Game file: {}",
-        cli.game_file
+        game_file
             .to_str()
             .expect("Game file path should be valid string")
     );
     println!("Debug mode: {}", cli.debug);
 
-    if !cli.game_file.is_file() {
+    if cli.mooneye {
+        return run_mooneye(&game_file, !cli.no_decode_cache);
+    }
+
+    if !game_file.is_file() {
         println!("Provided path is not a file");
-        return Ok(());
+        return Ok(ExitCode::SUCCESS);
     }
 
-    let file_size = cli.game_file.metadata()?.len();
+    let rom = fs::read(&game_file)?;
     if cli.debug {
-        println!("Total size of file: {} bytes", file_size);
-    }
+        println!("Total size of file: {} bytes", rom.len());
 
-    let file = File::open(cli.game_file)?;
+        let time_start = Instant::now();
+        let instructions = parse_instructions(rom.as_slice().bytes(), rom.len(), cli.debug)?;
+        println!("Time taken to parse the file: {:?}", time_start.elapsed());
+        println!("Parsed {} instructions.", instructions.len());
+    }
 
-    let mut time_start: Option<Instant> = None;
+    let mut cartridge = Cartridge::with_mapper(rom, cli.mapper)?;
+    println!("Title: {}", cartridge.header().title);
+    let mut save_file = SaveFile::new(save_path(&game_file));
+    save_file.load(&mut cartridge)?;
+    let mut gameboy = match &cli.boot_rom {
+        Some(path) => {
+            let data = fs::read(path)?;
+            let model = cli.model.unwrap_or_else(|| Model::from_boot_rom(&data));
+            GameBoy::with_boot_rom(cartridge, BootRom::new(data, model)?)
+        }
+        None => GameBoy::new(cartridge, cli.model.unwrap_or(Model::Dmg)),
+    };
+    gameboy.set_decode_cache(!cli.no_decode_cache);
+    gameboy.set_renderer(cli.renderer);
+    gameboy.set_color_correction(cli.color_correction);
+    if let Some(tilt) = &cli.tilt {
+        gameboy.cartridge_mut().set_tilt(tilt[0], tilt[1]);
+    }
+    if let Some(camera_image) = &cli.camera_image {
+        let image = CameraImage::from_pgm(&fs::read(camera_image)?)?;
+        gameboy.cartridge_mut().set_camera_image(image);
+    }
+    for watchpoint in &cli.watch {
+        gameboy.add_watchpoint(watchpoint.clone());
+    }
     if cli.debug {
-        time_start = Some(Instant::now());
+        gameboy
+            .cartridge_mut()
+            .subscribe(Box::new(|event| println!("Cartridge event: {event:?}")));
     }
 
-    let instructions = parse_instructions(file.bytes(), file_size as usize, cli.debug)?;
+    if let Some(reference) = &cli.reference {
+        let outcome = run_reference_test(&mut gameboy, &fs::read(reference)?)?;
+        println!("{outcome} {}", game_file.display());
+        if let Some(path) = &cli.screenshot {
+            write_screenshot(path, gameboy.frame())?;
+        }
+        return Ok(if outcome == TestOutcome::Passed {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        });
+    }
 
-    if cli.debug {
-        if let Some(start) = time_start {
-            println!("Time taken to parse the file: {:?}", start.elapsed());
-            println!("Parsed {} instructions.", instructions.len());
+    // Ctrl-C stops emulation like running out of cycles, so that the save file is written
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler_interrupted = Arc::clone(&interrupted);
+    ctrlc::set_handler(move || handler_interrupted.store(true, Ordering::Relaxed))
+        .map_err(|error| EmulatorError::PlatformError(error.to_string()))?;
+
+    let time_start = Instant::now();
+    while cli.cycles.is_none_or(|cycles| gameboy.cycles() < cycles) {
+        if interrupted.load(Ordering::Relaxed) {
+            break;
+        }
+        gameboy.step();
+        if let Some(hit) = gameboy.take_watch_hit() {
+            println!("Watchpoint hit: {hit}");
+            break;
         }
+        let flushed = save_file.flush(gameboy.cartridge(), gameboy.time());
+        report_save_error(&save_file, flushed);
+    }
+    let stored = save_file.store(gameboy.cartridge());
+    report_save_error(&save_file, stored);
+    if let Some(path) = &cli.screenshot {
+        write_screenshot(path, gameboy.frame())?;
+    }
+
+    if cli.debug {
+        let elapsed = time_start.elapsed();
+        let emulated_seconds = gameboy.time() as f64 / CYCLES_PER_SECOND as f64;
+        println!(
+            "Emulated {} M-cycles and {} frames in {:?} ({:.2}x real-time speed)",
+            gameboy.cycles(),
+            gameboy.frames(),
+            elapsed,
+            emulated_seconds / elapsed.as_secs_f64()
+        );
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Failing to write the save file does not stop the game, the next attempt may succeed.
+fn report_save_error(save_file: &SaveFile, result: Result<(), EmulatorError>) {
+    if let Err(error) = result {
+        println!(
+            "Could not write the save file {}: {error}",
+            save_file.path().display()
+        );
     }
+}
+
+fn write_screenshot(path: &Path, frame: &[u8]) -> Result<(), EmulatorError> {
+    fs::write(path, encode_ppm(SCREEN_WIDTH, SCREEN_HEIGHT, frame))?;
+    Ok(())
+}
 
+fn print_cartridge_info(game_file: &Path) -> Result<(), EmulatorError> {
+    let rom = fs::read(game_file)?;
+    let header = CartridgeHeader::parse(&rom)?;
+
+    let format_size = |size: Option<usize>| match size {
+        Some(0) => "none".to_string(),
+        Some(size) if size >= 0x100000 => format!("{} MiB", size as f64 / 0x100000 as f64),
+        Some(size) => format!("{} KiB", size / 0x400),
+        None => "unknown".to_string(),
+    };
+    let format_checksum = |valid: bool, computed: String| {
+        if valid {
+            "OK".to_string()
+        } else {
+            format!("MISMATCH, computed {computed}")
+        }
+    };
+
+    println!("Title:             {}", header.title);
+    if let Some(manufacturer_code) = &header.manufacturer_code {
+        println!("Manufacturer code: {manufacturer_code}");
+    }
+    println!("CGB support:       {:?}", header.cgb_support);
+    println!("SGB support:       {}", header.sgb_support);
+    println!("Cartridge type:    {}", header.cartridge_type);
+    println!("ROM size:          {}", format_size(header.rom_size));
+    println!("RAM size:          {}", format_size(header.ram_size));
+    println!("Destination:       {:?}", header.destination);
+    match &header.new_licensee_code {
+        Some(code) => println!("Licensee code:     {code} (new)"),
+        None => println!("Licensee code:     0x{:02X}", header.old_licensee_code),
+    }
+    println!("Version:           {}", header.version);
+    println!(
+        "Header checksum:   0x{:02X} ({})",
+        header.header_checksum,
+        format_checksum(
+            header.header_checksum_valid(),
+            format!("0x{:02X}", header.computed_header_checksum())
+        )
+    );
+    println!(
+        "Global checksum:   0x{:04X} ({})",
+        header.global_checksum,
+        format_checksum(
+            header.global_checksum_valid(),
+            format!("0x{:04X}", header.computed_global_checksum())
+        )
+    );
+    if rom.len() != header.rom_size.unwrap_or(rom.len()) {
+        println!(
+            "Warning: the file is {} bytes long, but the header specifies {} bytes.",
+            rom.len(),
+            header.rom_size.unwrap_or_default()
+        );
+    }
     Ok(())
 }
+
+/// Runs the Mooneye test ROMs at `path`, failing unless all of them pass.
+fn run_mooneye(path: &Path, decode_cache: bool) -> Result<ExitCode, EmulatorError> {
+    let results = if path.is_dir() {
+        run_test_directory(path, decode_cache)?
+    } else {
+        let rom = fs::read(path)?;
+        vec![(path.to_path_buf(), run_test_rom(&rom, decode_cache))]
+    };
+
+    for (rom_path, outcome) in &results {
+        let name = rom_path.strip_prefix(path).unwrap_or(rom_path);
+        match outcome {
+            Ok(outcome) => println!("{outcome:7} {}", name.display()),
+            Err(error) => println!("ERROR   {}: {error}", name.display()),
+        }
+    }
+
+    let passed_count = results
+        .iter()
+        .filter(|(_, outcome)| matches!(outcome, Ok(TestOutcome::Passed)))
+        .count();
+    println!(
+        "Passed {passed_count} of {} Mooneye test ROMs.",
+        results.len()
+    );
+    if passed_count == results.len() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
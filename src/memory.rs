@@ -0,0 +1,810 @@
+use crate::{
+    boot::{BootRom, Model},
+    bus::{Bus, Interrupt, INTERRUPT_ENABLE_ADDRESS, INTERRUPT_FLAG_ADDRESS},
+    cartridge::{header::CgbSupport, Cartridge},
+    dma::OamDma,
+    hdma::Hdma,
+    ppu::Ppu,
+    timer::Timer,
+    watchpoint::{Access, WatchAccess, Watchpoint},
+};
+
+/// Bits of the IO registers (0xFF00-0xFF7F) that are not connected and always read as 1. Addresses
+/// without a register on the DMG read as 0xFF entirely.
+#[rustfmt::skip]
+const IO_UNUSED_BITS: [u8; 0x80] = [
+    // P1    SB    SC          DIV   TIMA  TMA   TAC                                         IF
+    0xC0, 0x00, 0x7E, 0xFF, 0x00, 0x00, 0x00, 0xF8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE0,
+    // NR10  NR11  NR12  NR13  NR14        NR21  NR22  NR23  NR24  NR30  NR31  NR32  NR33  NR34
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, 0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
+    // NR41  NR42  NR43  NR44  NR50  NR51  NR52
+    0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // wave RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // LCDC  STAT  SCY   SCX   LY    LYC   DMA   BGP   OBP0  OBP1  WY    WX
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+/// Values of the IO registers after the DMG and MGB boot ROMs have finished.
+const DMG_POST_BOOT_IO: [(u16, u8); 24] = [
+    (0xFF00, 0xCF),
+    (0xFF10, 0x80),
+    (0xFF11, 0xBF),
+    (0xFF12, 0xF3),
+    (0xFF13, 0xFF),
+    (0xFF14, 0xBF),
+    (0xFF16, 0x3F),
+    (0xFF18, 0xFF),
+    (0xFF19, 0xBF),
+    (0xFF1A, 0x7F),
+    (0xFF1B, 0xFF),
+    (0xFF1C, 0x9F),
+    (0xFF1D, 0xFF),
+    (0xFF1E, 0xBF),
+    (0xFF20, 0xFF),
+    (0xFF23, 0xBF),
+    (0xFF24, 0x77),
+    (0xFF25, 0xF3),
+    (0xFF26, 0xF1),
+    (0xFF40, 0x91),
+    (0xFF41, 0x85),
+    (0xFF46, 0xFF),
+    (0xFF47, 0xFC),
+    // last, the write of STAT with the LCD on requests a STAT interrupt through the DMG STAT
+    // write bug
+    (0xFF0F, 0xE1),
+];
+
+/// NR52 after the SGB boot ROM has finished, which leaves the other IO registers like the DMG boot
+/// ROM.
+const SGB_POST_BOOT_NR52: u8 = 0xF0;
+
+/// Values of the IO registers after the CGB boot ROM has finished, in CGB and in compatibility
+/// mode, which leaves SC with its clock speed bit set and DMA at 0.
+const CGB_POST_BOOT_IO: [(u16, u8); 25] = [
+    (0xFF00, 0xCF),
+    (0xFF02, 0x7F),
+    (0xFF10, 0x80),
+    (0xFF11, 0xBF),
+    (0xFF12, 0xF3),
+    (0xFF13, 0xFF),
+    (0xFF14, 0xBF),
+    (0xFF16, 0x3F),
+    (0xFF18, 0xFF),
+    (0xFF19, 0xBF),
+    (0xFF1A, 0x7F),
+    (0xFF1B, 0xFF),
+    (0xFF1C, 0x9F),
+    (0xFF1D, 0xFF),
+    (0xFF1E, 0xBF),
+    (0xFF20, 0xFF),
+    (0xFF23, 0xBF),
+    (0xFF24, 0x77),
+    (0xFF25, 0xF3),
+    (0xFF26, 0xF1),
+    (0xFF40, 0x91),
+    (0xFF41, 0x85),
+    (0xFF46, 0x00),
+    (0xFF47, 0xFC),
+    // last, so that no interrupt requested by the writes before is left pending
+    (0xFF0F, 0xE1),
+];
+
+// System counter values after the boot ROMs have finished, DIV being the upper byte. They depend on
+// how long the boot ROM runs: the SGB boot ROM sends the header to the SNES, and the CGB boot ROM
+// takes longer in compatibility mode, where it picks the palettes for the game.
+const DMG_POST_BOOT_SYSTEM_COUNTER: u16 = 0xABCC;
+const SGB_POST_BOOT_SYSTEM_COUNTER: u16 = 0xD85C;
+const CGB_POST_BOOT_SYSTEM_COUNTER: u16 = 0x1EA0;
+const CGB_COMPATIBILITY_POST_BOOT_SYSTEM_COUNTER: u16 = 0x267C;
+
+const P1_ADDRESS: u16 = 0xFF00;
+const DIV_ADDRESS: u16 = 0xFF04;
+const TIMA_ADDRESS: u16 = 0xFF05;
+const TMA_ADDRESS: u16 = 0xFF06;
+const TAC_ADDRESS: u16 = 0xFF07;
+const NR52_ADDRESS: u16 = 0xFF26;
+const LCDC_ADDRESS: u16 = 0xFF40;
+const WX_ADDRESS: u16 = 0xFF4B;
+const DMA_ADDRESS: u16 = 0xFF46;
+const KEY1_ADDRESS: u16 = 0xFF4D;
+const VBK_ADDRESS: u16 = 0xFF4F;
+const HDMA1_ADDRESS: u16 = 0xFF51;
+const HDMA2_ADDRESS: u16 = 0xFF52;
+const HDMA3_ADDRESS: u16 = 0xFF53;
+const HDMA4_ADDRESS: u16 = 0xFF54;
+const HDMA5_ADDRESS: u16 = 0xFF55;
+const BCPS_ADDRESS: u16 = 0xFF68;
+const OCPD_ADDRESS: u16 = 0xFF6B;
+const SVBK_ADDRESS: u16 = 0xFF70;
+
+/// M-cycles the CPU is paused for while switching between normal and double speed.
+const SPEED_SWITCH_CYCLES: u16 = 2050;
+const BOOT_ROM_DISABLE_ADDRESS: u16 = 0xFF50;
+
+/// The DMG memory map:
+///
+/// | Range         | Contents                                  |
+/// |---------------|-------------------------------------------|
+/// | 0x0000-0x3FFF | cartridge ROM, usually fixed to bank 0    |
+/// |               | (boot ROM while mapped)                   |
+/// | 0x4000-0x7FFF | cartridge ROM, switchable bank            |
+/// | 0x8000-0x9FFF | VRAM, switchable bank on the CGB          |
+/// | 0xA000-0xBFFF | external (cartridge) RAM                  |
+/// | 0xC000-0xCFFF | WRAM bank 0                               |
+/// | 0xD000-0xDFFF | WRAM bank 1, switchable 1-7 on the CGB    |
+/// | 0xE000-0xFDFF | echo RAM, mirror of 0xC000-0xDDFF         |
+/// | 0xFE00-0xFE9F | OAM                                       |
+/// | 0xFEA0-0xFEFF | unusable, reads 0x00 and ignores writes   |
+/// | 0xFF00-0xFF7F | IO registers                              |
+/// | 0xFF80-0xFFFE | HRAM                                      |
+/// | 0xFFFF        | IE register                               |
+pub struct MemoryBus {
+    cartridge: Cartridge,
+    /// mapped over the cartridge ROM until 0xFF50 is written
+    boot_rom: Option<BootRom>,
+    /// a CGB running a game with CGB support, enabling the CGB registers
+    cgb_mode: bool,
+    /// owns VRAM and OAM
+    ppu: Ppu,
+    /// eight banks on the CGB
+    wram: Box<[u8; 0x8000]>,
+    /// VBK, the VRAM bank at 0x8000-0x9FFF
+    vram_bank: u8,
+    /// SVBK, the WRAM bank at 0xD000-0xDFFF, never zero
+    wram_bank: u8,
+    /// the CPU runs at twice the speed, and with it the timer and the OAM DMA which are ticked
+    /// per M-cycle, while the PPU, the VRAM DMA and cartridge clocks keep their speed
+    double_speed: bool,
+    /// KEY1 bit 0, a stop instruction switches the speed when set
+    speed_switch_armed: bool,
+    /// M-cycles left until the CPU runs again after switching speed
+    speed_switch_cycles: u16,
+    /// IO registers without dedicated emulation, read through `IO_UNUSED_BITS`
+    io: [u8; 0x80],
+    hram: [u8; 0x7F],
+    interrupt_flag: u8,
+    interrupt_enable: u8,
+    timer: Timer,
+    dma: OamDma,
+    hdma: Hdma,
+    watchpoints: Vec<Watchpoint>,
+    /// the first access that matched a watchpoint
+    watch_access: Option<WatchAccess>,
+    /// number of M-cycles since power on
+    cycles: u64,
+    /// emulated time in M-cycles of normal speed, which pass every other M-cycle in double speed
+    time: u64,
+}
+
+impl MemoryBus {
+    /// Creates a bus in the state the boot ROM of `model` leaves it in.
+    pub fn new(cartridge: Cartridge, model: Model) -> Self {
+        let mut bus = Self::power_on(cartridge, model, None);
+        let (io, system_counter) = match model {
+            Model::Dmg | Model::Mgb => (&DMG_POST_BOOT_IO[..], DMG_POST_BOOT_SYSTEM_COUNTER),
+            Model::Sgb => (&DMG_POST_BOOT_IO[..], SGB_POST_BOOT_SYSTEM_COUNTER),
+            Model::Cgb if bus.cgb_mode => (&CGB_POST_BOOT_IO[..], CGB_POST_BOOT_SYSTEM_COUNTER),
+            Model::Cgb => (
+                &CGB_POST_BOOT_IO[..],
+                CGB_COMPATIBILITY_POST_BOOT_SYSTEM_COUNTER,
+            ),
+        };
+        bus.timer = Timer::with_counter(system_counter);
+        bus.cartridge.skip_boot();
+        for &(address, value) in io {
+            bus.write(address, value);
+        }
+        if model == Model::Sgb {
+            bus.write(NR52_ADDRESS, SGB_POST_BOOT_NR52);
+        }
+        // only the value is left behind, no transfer
+        bus.dma = OamDma::default();
+        bus
+    }
+
+    /// Creates a bus as it is at power on, with `boot_rom` mapped.
+    pub fn with_boot_rom(cartridge: Cartridge, boot_rom: BootRom) -> Self {
+        Self::power_on(cartridge, boot_rom.model(), Some(boot_rom))
+    }
+
+    fn power_on(cartridge: Cartridge, model: Model, boot_rom: Option<BootRom>) -> Self {
+        // the CGB boot ROM switches to compatibility mode for games without CGB support
+        let cgb_mode = model == Model::Cgb && cartridge.header().cgb_support != CgbSupport::None;
+        MemoryBus {
+            cartridge,
+            boot_rom,
+            cgb_mode,
+            ppu: Ppu::new(model, cgb_mode),
+            wram: Box::new([0; 0x8000]),
+            vram_bank: 0,
+            wram_bank: 1,
+            double_speed: false,
+            speed_switch_armed: false,
+            speed_switch_cycles: 0,
+            io: [0; 0x80],
+            hram: [0; 0x7F],
+            interrupt_flag: 0,
+            interrupt_enable: 0,
+            timer: Timer::with_counter(0),
+            dma: OamDma::default(),
+            hdma: Hdma::default(),
+            watchpoints: Vec::new(),
+            watch_access: None,
+            cycles: 0,
+            time: 0,
+        }
+    }
+
+    /// Whether the game runs in CGB mode, as opposed to a DMG or the compatibility mode of the CGB.
+    pub fn cgb_mode(&self) -> bool {
+        self.cgb_mode
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag |= interrupt.mask();
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    /// Returns the VRAM index of `address` in 0x8000-0x9FFF.
+    #[inline]
+    fn vram_index(&self, address: u16) -> usize {
+        self.vram_bank as usize * 0x2000 + (address & 0x1FFF) as usize
+    }
+
+    /// Returns the index into `wram` of `address` in 0xC000-0xFDFF, including echo RAM.
+    #[inline]
+    fn wram_index(&self, address: u16) -> usize {
+        let offset = (address & 0x0FFF) as usize;
+        if address & 0x1000 == 0 {
+            offset
+        } else {
+            self.wram_bank as usize * 0x1000 + offset
+        }
+    }
+
+    /// Breaks on watchpoints matching the accesses of the CPU, see `take_watch_access`.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Returns the first access since the last call that matched a watchpoint.
+    pub fn take_watch_access(&mut self) -> Option<WatchAccess> {
+        self.watch_access.take()
+    }
+
+    fn watch(&mut self, access: Access, address: u16, value: u8) {
+        if self.watch_access.is_some() {
+            return;
+        }
+        let access = WatchAccess {
+            access,
+            address,
+            value,
+            bank: self.bank(address),
+        };
+        if self
+            .watchpoints
+            .iter()
+            .any(|watchpoint| watchpoint.matches(&access))
+        {
+            self.watch_access = Some(access);
+        }
+    }
+
+    /// Returns the bank mapped at `address` for watchpoints.
+    fn bank(&self, address: u16) -> u16 {
+        match address {
+            0x0000..=0x7FFF => self.cartridge.rom_bank(address),
+            0x8000..=0x9FFF => self.vram_bank as u16,
+            0xA000..=0xBFFF => self.cartridge.ram_bank(),
+            0xD000..=0xDFFF | 0xF000..=0xFDFF => self.wram_bank as u16,
+            _ => 0,
+        }
+    }
+
+    fn read_byte(&mut self, address: u16) -> u8 {
+        self.cartridge.observe_access(address);
+        if let Some(source) = self.dma.source_address().filter(|_| address < 0xFF00) {
+            // the CPU sees the byte the DMA puts on the bus, OAM is busy being written
+            return match address {
+                0xFE00..=0xFEFF => 0xFF,
+                _ => self.read_dma_source(source),
+            };
+        }
+        match address {
+            0x0000..=0x08FF if self.boot_rom.is_some() => {
+                let boot_rom = self
+                    .boot_rom
+                    .as_ref()
+                    .and_then(|boot_rom| boot_rom.read(address));
+                boot_rom.unwrap_or_else(|| self.cartridge.read_rom(address))
+            }
+            0x0000..=0x7FFF => self.cartridge.read_rom(address),
+            // VRAM and OAM are not accessible while the PPU uses them
+            0x8000..=0x9FFF if self.ppu.vram_accessible() => {
+                self.ppu.read_vram(self.vram_index(address))
+            }
+            0x8000..=0x9FFF => 0xFF,
+            0xA000..=0xBFFF => self.cartridge.read_ram(address),
+            0xC000..=0xFDFF => self.wram[self.wram_index(address)],
+            0xFE00..=0xFE9F if self.ppu.oam_accessible() => {
+                self.ppu.read_oam((address - 0xFE00) as usize)
+            }
+            0xFE00..=0xFE9F => 0xFF,
+            0xFEA0..=0xFEFF => 0x00,
+            0xFF00..=0xFF7F => self.read_io(address),
+            0xFF80..=0xFFFE => self.hram[(address - 0xFF80) as usize],
+            INTERRUPT_ENABLE_ADDRESS => self.interrupt_enable,
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        self.cartridge.observe_access(address);
+        if self.dma.is_active() && address < 0xFF00 {
+            return;
+        }
+        match address {
+            0x0000..=0x7FFF => self.cartridge.write_rom(address, value),
+            0x8000..=0x9FFF if self.ppu.vram_accessible() => {
+                self.ppu.write_vram(self.vram_index(address), value)
+            }
+            0x8000..=0x9FFF => {}
+            0xA000..=0xBFFF => self.cartridge.write_ram(address, value),
+            0xC000..=0xFDFF => {
+                let index = self.wram_index(address);
+                self.wram[index] = value;
+            }
+            0xFE00..=0xFE9F if self.ppu.oam_accessible() => {
+                self.ppu.write_oam((address - 0xFE00) as usize, value)
+            }
+            0xFE00..=0xFE9F => {}
+            0xFEA0..=0xFEFF => {}
+            0xFF00..=0xFF7F => self.write_io(address, value),
+            0xFF80..=0xFFFE => self.hram[(address - 0xFF80) as usize] = value,
+            INTERRUPT_ENABLE_ADDRESS => self.interrupt_enable = value,
+        }
+    }
+
+    /// Reads a byte as the OAM DMA sees it. Sources from 0xE000 up read WRAM like echo RAM.
+    fn read_dma_source(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x7FFF => self.cartridge.read_rom(address),
+            0x8000..=0x9FFF => self.ppu.read_vram(self.vram_index(address)),
+            0xA000..=0xBFFF => self.cartridge.read_ram(address),
+            _ => self.wram[self.wram_index(address)],
+        }
+    }
+
+    fn read_io(&self, address: u16) -> u8 {
+        let index = (address - 0xFF00) as usize;
+        match address {
+            // no buttons are pressed
+            P1_ADDRESS => self.io[index] | IO_UNUSED_BITS[index] | 0x0F,
+            DIV_ADDRESS => self.timer.read_div(),
+            TIMA_ADDRESS => self.timer.read_tima(),
+            TMA_ADDRESS => self.timer.read_tma(),
+            TAC_ADDRESS => self.timer.read_tac(),
+            LCDC_ADDRESS..=WX_ADDRESS if address != DMA_ADDRESS => self.ppu.read_register(address),
+            HDMA5_ADDRESS if self.cgb_mode => self.hdma.read_control(),
+            KEY1_ADDRESS if self.cgb_mode => {
+                (self.double_speed as u8) << 7 | 0x7E | self.speed_switch_armed as u8
+            }
+            VBK_ADDRESS if self.cgb_mode => 0xFE | self.vram_bank,
+            BCPS_ADDRESS..=OCPD_ADDRESS if self.cgb_mode => self.ppu.read_register(address),
+            SVBK_ADDRESS if self.cgb_mode => 0xF8 | self.wram_bank,
+            INTERRUPT_FLAG_ADDRESS => self.interrupt_flag | IO_UNUSED_BITS[index],
+            _ => self.io[index] | IO_UNUSED_BITS[index],
+        }
+    }
+
+    fn write_io(&mut self, address: u16, value: u8) {
+        let index = (address - 0xFF00) as usize;
+        match address {
+            P1_ADDRESS => self.io[index] = value & 0x30,
+            DIV_ADDRESS => self.timer.write_div(),
+            TIMA_ADDRESS => self.timer.write_tima(value),
+            TMA_ADDRESS => self.timer.write_tma(value),
+            TAC_ADDRESS => self.timer.write_tac(value),
+            INTERRUPT_FLAG_ADDRESS => self.interrupt_flag = value & 0x1F,
+            LCDC_ADDRESS..=WX_ADDRESS if address != DMA_ADDRESS => {
+                self.interrupt_flag |= self.ppu.write_register(address, value);
+            }
+            KEY1_ADDRESS if self.cgb_mode => self.speed_switch_armed = value & 1 != 0,
+            VBK_ADDRESS if self.cgb_mode => self.vram_bank = value & 1,
+            BCPS_ADDRESS..=OCPD_ADDRESS if self.cgb_mode => {
+                self.ppu.write_register(address, value);
+            }
+            SVBK_ADDRESS if self.cgb_mode => self.wram_bank = (value & 0x07).max(1),
+            HDMA1_ADDRESS if self.cgb_mode => self.hdma.write_source_high(value),
+            HDMA2_ADDRESS if self.cgb_mode => self.hdma.write_source_low(value),
+            HDMA3_ADDRESS if self.cgb_mode => self.hdma.write_destination_high(value),
+            HDMA4_ADDRESS if self.cgb_mode => self.hdma.write_destination_low(value),
+            HDMA5_ADDRESS if self.cgb_mode => self.hdma.write_control(value),
+            DMA_ADDRESS => {
+                self.io[index] = value;
+                self.dma.start(value);
+            }
+            // the boot ROM can not be mapped again
+            BOOT_ROM_DISABLE_ADDRESS if value & 1 != 0 => self.boot_rom = None,
+            BOOT_ROM_DISABLE_ADDRESS => {}
+            _ => self.io[index] = value,
+        }
+    }
+}
+
+impl Bus for MemoryBus {
+    fn read(&mut self, address: u16) -> u8 {
+        let value = self.read_byte(address);
+        if !self.watchpoints.is_empty() {
+            self.watch(Access::Read, address, value);
+        }
+        value
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if !self.watchpoints.is_empty() {
+            self.watch(Access::Write, address, value);
+        }
+        self.write_byte(address, value);
+    }
+
+    fn tick(&mut self) {
+        self.cycles += 1;
+        // cartridge hardware like clocks is not driven by the CPU clock
+        if !self.double_speed || self.cycles.is_multiple_of(2) {
+            self.time += 1;
+            self.cartridge.tick();
+        }
+        self.speed_switch_cycles = self.speed_switch_cycles.saturating_sub(1);
+        if let Some((source, offset)) = self.dma.tick() {
+            let byte = self.read_dma_source(source);
+            self.ppu.write_oam(offset, byte);
+        }
+        if let Some((source, destination, count)) = self.hdma.tick(self.double_speed) {
+            for index in 0..count {
+                let byte = self.read_dma_source(source.wrapping_add(index));
+                let vram_index = self.vram_index(0x8000 | destination.wrapping_add(index));
+                self.ppu.write_vram(vram_index, byte);
+            }
+        }
+        let events = self.ppu.tick(if self.double_speed { 2 } else { 4 });
+        self.interrupt_flag |= events.interrupts;
+        if events.hblank {
+            self.hdma.hblank();
+        }
+        if self.timer.tick() {
+            self.request_interrupt(Interrupt::Timer);
+        }
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.interrupt_flag & self.interrupt_enable & 0x1F
+    }
+
+    fn acknowledge_interrupts(&mut self, mask: u8) {
+        self.interrupt_flag &= !mask;
+    }
+
+    fn stalled(&self) -> bool {
+        self.hdma.is_copying() || self.speed_switch_cycles != 0
+    }
+
+    fn switch_speed(&mut self) -> bool {
+        if !self.speed_switch_armed {
+            return false;
+        }
+        self.speed_switch_armed = false;
+        self.double_speed = !self.double_speed;
+        self.speed_switch_cycles = SPEED_SWITCH_CYCLES;
+        // the STOP switching speed resets the system counter like a write to DIV
+        self.timer.write_div();
+        true
+    }
+
+    fn mapped_bank(&self, address: u16) -> Option<u16> {
+        match address {
+            0x0000..=0xFEFF if self.dma.is_active() => None,
+            0x0000..=0x7FFF if self.cartridge.observes_accesses() => None,
+            // instruction fetches from the cache would not be seen by watchpoints
+            _ if self
+                .watchpoints
+                .iter()
+                .any(|watchpoint| watchpoint.watches(Access::Read, address)) =>
+            {
+                None
+            }
+            // instructions from the boot ROM must not be cached for after it is unmapped
+            0x0000..=0x08FF if self.boot_rom.as_ref().is_some_and(|rom| rom.maps(address)) => None,
+            0x0000..=0x7FFF => Some(self.cartridge.rom_bank(address)),
+            0xC000..=0xCFFF | 0xFF80..=0xFFFE => Some(0),
+            0xD000..=0xDFFF => Some(self.wram_bank as u16),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bus() -> MemoryBus {
+        let mut rom = vec![0x11; 0x8000];
+        // no mapper
+        rom[0x0147] = 0x00;
+        MemoryBus::new(Cartridge::new(rom).unwrap(), Model::Dmg)
+    }
+
+    #[test]
+    fn post_boot_state() {
+        let mut bus = bus();
+        // only the VBlank request the boot ROM leaves behind is pending, not a STAT request from
+        // replaying the STAT write
+        assert_eq!(bus.read(0xFF0F), 0xE1);
+        assert_eq!(bus.read(0xFF40), 0x91);
+    }
+
+    /// Returns DIV, SC, NR52 and DMA after the boot ROM of `model` for a game with the CGB flag
+    /// `cgb_flag`.
+    fn post_boot_registers(model: Model, cgb_flag: u8) -> [u8; 4] {
+        let mut rom = vec![0x11; 0x8000];
+        rom[0x0143] = cgb_flag;
+        rom[0x0147] = 0x00;
+        let mut bus = MemoryBus::new(Cartridge::new(rom).unwrap(), model);
+        assert_eq!(bus.read(0xFF0F), 0xE1, "{model:?}");
+        [0xFF04, 0xFF02, 0xFF26, 0xFF46].map(|address| bus.read(address))
+    }
+
+    #[test]
+    fn post_boot_state_per_model() {
+        assert_eq!(post_boot_registers(Model::Dmg, 0), [0xAB, 0x7E, 0xF1, 0xFF]);
+        assert_eq!(post_boot_registers(Model::Mgb, 0), [0xAB, 0x7E, 0xF1, 0xFF]);
+        assert_eq!(post_boot_registers(Model::Sgb, 0), [0xD8, 0x7E, 0xF0, 0xFF]);
+        assert_eq!(
+            post_boot_registers(Model::Cgb, 0x80),
+            [0x1E, 0x7F, 0xF1, 0x00]
+        );
+        // the boot ROM runs longer in compatibility mode
+        assert_eq!(post_boot_registers(Model::Cgb, 0), [0x26, 0x7F, 0xF1, 0x00]);
+    }
+
+    #[test]
+    fn echo_ram_mirrors_wram() {
+        let mut bus = bus();
+        bus.write(0xC123, 0x42);
+        assert_eq!(bus.read(0xE123), 0x42);
+        bus.write(0xFDFF, 0x24);
+        assert_eq!(bus.read(0xDDFF), 0x24);
+    }
+
+    #[test]
+    fn open_bus_behaviour() {
+        let mut bus = bus();
+        bus.write(0x2000, 0x00);
+        assert_eq!(bus.read(0x2000), 0x11);
+        assert_eq!(bus.read(0xA000), 0xFF);
+        bus.write(0xFEA0, 0x42);
+        assert_eq!(bus.read(0xFEA0), 0x00);
+        bus.write(0xFF03, 0x00);
+        assert_eq!(bus.read(0xFF03), 0xFF);
+        bus.write(0xFF0F, 0x00);
+        assert_eq!(bus.read(0xFF0F), 0xE0);
+        bus.write(0xFF07, 0x00);
+        assert_eq!(bus.read(0xFF07), 0xF8);
+    }
+
+    #[test]
+    fn timer_requests_interrupt() {
+        let mut bus = bus();
+        bus.write(0xFFFF, Interrupt::Timer.mask());
+        bus.write(0xFF0F, 0x00);
+        bus.write(0xFF05, 0xFF);
+        bus.write(0xFF07, 0b101);
+        for _ in 0..8 {
+            bus.tick();
+        }
+        assert_eq!(bus.pending_interrupts(), Interrupt::Timer.mask());
+    }
+
+    #[test]
+    fn boot_rom_unmaps_on_write() {
+        let mut rom = vec![0x11; 0x8000];
+        rom[0x0147] = 0x00;
+        let boot_rom = BootRom::new(vec![0x22; 0x100], Model::Dmg).unwrap();
+        let mut bus = MemoryBus::with_boot_rom(Cartridge::new(rom).unwrap(), boot_rom);
+        assert_eq!(bus.read(0x00FF), 0x22);
+        assert_eq!(bus.mapped_bank(0x0000), None);
+        assert_eq!(bus.read(0x0100), 0x11);
+        bus.write(0xFF50, 0x00);
+        assert_eq!(bus.read(0x0000), 0x22);
+        bus.write(0xFF50, 0x01);
+        assert_eq!(bus.read(0x0000), 0x11);
+        assert_eq!(bus.mapped_bank(0x0000), Some(0));
+    }
+
+    #[test]
+    fn oam_dma_blocks_bus() {
+        let mut bus = bus();
+        // keep OAM accessible
+        bus.write(0xFF40, 0x00);
+        for offset in 0..0xA0 {
+            bus.write(0xC100 + offset, offset as u8);
+        }
+        bus.write(0xFF46, 0xC1);
+        bus.tick();
+        bus.tick();
+        // the second byte is being copied
+        assert_eq!(bus.read(0x4000), 0x01);
+        assert_eq!(bus.read(0xFE00), 0xFF);
+        bus.write(0xFF80, 0x42);
+        assert_eq!(bus.read(0xFF80), 0x42);
+        bus.write(0xC000, 0x42);
+        for _ in 0..0x9F {
+            bus.tick();
+        }
+        assert_eq!(bus.read(0xC000), 0x00);
+        assert_eq!(bus.read(0xFE9F), 0x9F);
+    }
+
+    fn cgb_bus() -> MemoryBus {
+        let mut rom = vec![0x11; 0x8000];
+        rom[0x0143] = 0x80;
+        rom[0x0147] = 0x00;
+        MemoryBus::new(Cartridge::new(rom).unwrap(), Model::Cgb)
+    }
+
+    #[test]
+    fn general_purpose_hdma_pauses_cpu() {
+        let mut cgb_bus = cgb_bus();
+        for offset in 0..0x20 {
+            cgb_bus.write(0xC000 + offset, offset as u8);
+        }
+        cgb_bus.write(0xFF51, 0xC0);
+        cgb_bus.write(0xFF52, 0x00);
+        cgb_bus.write(0xFF53, 0x81);
+        cgb_bus.write(0xFF54, 0x00);
+        cgb_bus.write(0xFF55, 0x01);
+        let mut cycles = 0;
+        while cgb_bus.stalled() {
+            cgb_bus.tick();
+            cycles += 1;
+        }
+        assert_eq!(cycles, 16);
+        assert_eq!(cgb_bus.read(0x811F), 0x1F);
+        assert_eq!(cgb_bus.read(0xFF55), 0xFF);
+
+        // the registers do not exist on the DMG
+        let mut bus = bus();
+        bus.write(0xFF55, 0x01);
+        assert!(!bus.stalled());
+    }
+
+    #[test]
+    fn cgb_banking() {
+        let mut bus = cgb_bus();
+        bus.write(0xD000, 0x01);
+        bus.write(0xFF70, 0x07);
+        bus.write(0xD000, 0x07);
+        assert_eq!(bus.mapped_bank(0xD000), Some(7));
+        assert_eq!(bus.read(0xF000), 0x07);
+        bus.write(0xFF70, 0x00);
+        assert_eq!(bus.read(0xFF70), 0xF9);
+        assert_eq!(bus.read(0xD000), 0x01);
+
+        bus.write(0x8000, 0x42);
+        bus.write(0xFF4F, 0xFF);
+        assert_eq!(bus.read(0xFF4F), 0xFF);
+        assert_eq!(bus.read(0x8000), 0x00);
+        bus.write(0xFF4F, 0x00);
+        assert_eq!(bus.read(0x8000), 0x42);
+    }
+
+    #[test]
+    fn cgb_palette_registers() {
+        let mut cgb = cgb_bus();
+        cgb.write(0xFF6A, 0x85);
+        cgb.write(0xFF6B, 0x12);
+        assert_eq!(cgb.read(0xFF6A), 0xC6);
+        // the registers do not exist on the DMG
+        let mut dmg = bus();
+        dmg.write(0xFF6A, 0x85);
+        assert_eq!(dmg.read(0xFF6A), 0xFF);
+    }
+
+    #[test]
+    fn speed_switch() {
+        let mut bus = cgb_bus();
+        assert!(!bus.switch_speed());
+        bus.write(0xFF4D, 0x01);
+        assert_eq!(bus.read(0xFF4D), 0x7F);
+        assert!(bus.switch_speed());
+        assert_eq!(bus.read(0xFF4D), 0xFE);
+        assert_eq!(bus.read(0xFF04), 0x00);
+        for _ in 0..SPEED_SWITCH_CYCLES {
+            assert!(bus.stalled());
+            bus.tick();
+        }
+        assert!(!bus.stalled());
+    }
+
+    #[test]
+    fn time_runs_at_normal_speed() {
+        let mut bus = cgb_bus();
+        bus.tick();
+        assert_eq!(bus.time(), 1);
+        bus.write(0xFF4D, 0x01);
+        bus.switch_speed();
+        let time = bus.time();
+        for _ in 0..4 {
+            bus.tick();
+        }
+        assert_eq!(bus.time(), time + 2);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut bus = bus();
+        bus.add_watchpoint("w:C000-C0FF=42".parse().unwrap());
+        bus.add_watchpoint("r:4000@1".parse().unwrap());
+        bus.write(0xC010, 0x41);
+        bus.read(0xC010);
+        assert_eq!(bus.take_watch_access(), None);
+        bus.write(0xC010, 0x42);
+        bus.read(0x4000);
+        assert_eq!(
+            bus.take_watch_access(),
+            Some(WatchAccess {
+                access: Access::Write,
+                address: 0xC010,
+                value: 0x42,
+                bank: 0,
+            })
+        );
+        assert_eq!(bus.mapped_bank(0x4000), None);
+        bus.read(0x4000);
+        assert_eq!(bus.take_watch_access().map(|access| access.bank), Some(1));
+    }
+
+    #[test]
+    fn external_ram_watchpoints() {
+        let mut rom = vec![0x11; 0x8000];
+        // MBC5 with 32 KiB of RAM
+        rom[0x0147] = 0x1A;
+        rom[0x0149] = 0x03;
+        let mut bus = MemoryBus::new(Cartridge::new(rom).unwrap(), Model::Dmg);
+        bus.add_watchpoint("w:A000-BFFF@2".parse().unwrap());
+        bus.write(0x0000, 0x0A);
+        bus.write(0x4000, 0x01);
+        bus.write(0xA000, 0x42);
+        assert_eq!(bus.take_watch_access(), None);
+        bus.write(0x4000, 0x02);
+        bus.write(0xA000, 0x42);
+        assert_eq!(bus.take_watch_access().map(|access| access.bank), Some(2));
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    boot::Model,
+    cartridge::Cartridge,
+    errors::EmulatorError,
+    gameboy::GameBoy,
+    instructions::{Instruction, R8Operand},
+    ppu::Renderer,
+    registers::{R8Kind, Registers},
+};
+
+/// Mooneye test ROMs execute ld b, b once they are done.
+pub const EXIT_INSTRUCTION: Instruction = Instruction::LoadR8ToR8 {
+    dst: R8Operand::BReg,
+    src: R8Operand::BReg,
+};
+
+/// On success, the registers B, C, D, E, H and L hold the first Fibonacci numbers starting at 3.
+const PASS_REGISTERS: [(R8Kind, u8); 6] = [
+    (R8Kind::B, 3),
+    (R8Kind::C, 5),
+    (R8Kind::D, 8),
+    (R8Kind::E, 13),
+    (R8Kind::H, 21),
+    (R8Kind::L, 34),
+];
+
+/// Number of CPU steps after which a test ROM that has not executed the exit instruction is
+/// considered to be stuck.
+pub const MAX_STEPS: usize = 20_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestOutcome {
+    Passed,
+    Failed,
+    TimedOut,
+}
+
+impl fmt::Display for TestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            TestOutcome::Passed => "PASS",
+            TestOutcome::Failed => "FAIL",
+            TestOutcome::TimedOut => "TIMEOUT",
+        };
+        f.pad(label)
+    }
+}
+
+/// Path of a test ROM together with its outcome, or the error that prevented running it.
+pub type TestResult = (PathBuf, Result<TestOutcome, EmulatorError>);
+
+/// Runs a single Mooneye test ROM until it signals completion or runs out of steps.
+pub fn run_test_rom(rom: &[u8], decode_cache: bool) -> Result<TestOutcome, EmulatorError> {
+    let mut gameboy = GameBoy::new(Cartridge::new(rom.to_vec())?, Model::Dmg);
+    gameboy.set_decode_cache(decode_cache);
+    // the PPU timing tests need the length of mode 3 to vary like on hardware
+    gameboy.set_renderer(Renderer::PixelFifo);
+    for _ in 0..MAX_STEPS {
+        if gameboy.step() == Some(EXIT_INSTRUCTION) {
+            return Ok(check_registers(gameboy.cpu().registers()));
+        }
+    }
+    Ok(TestOutcome::TimedOut)
+}
+
+/// Runs every `.gb` file in `directory` and its subdirectories as a Mooneye test ROM. The results
+/// are sorted by path, ROMs that could not be run have an error as their result.
+pub fn run_test_directory(
+    directory: &Path,
+    decode_cache: bool,
+) -> Result<Vec<TestResult>, EmulatorError> {
+    let mut rom_paths = Vec::new();
+    collect_roms(directory, &mut rom_paths)?;
+    rom_paths.sort();
+
+    let mut results = Vec::with_capacity(rom_paths.len());
+    for rom_path in rom_paths {
+        let outcome = fs::read(&rom_path)
+            .map_err(EmulatorError::from)
+            .and_then(|rom| run_test_rom(&rom, decode_cache));
+        results.push((rom_path, outcome));
+    }
+    Ok(results)
+}
+
+fn collect_roms(directory: &Path, rom_paths: &mut Vec<PathBuf>) -> Result<(), EmulatorError> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_roms(&path, rom_paths)?;
+        } else if path.extension().is_some_and(|extension| extension == "gb") {
+            rom_paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn check_registers(registers: &Registers) -> TestOutcome {
+    let passed = PASS_REGISTERS
+        .iter()
+        .all(|&(kind, expected)| registers.get_r8(kind) == expected);
+    if passed {
+        TestOutcome::Passed
+    } else {
+        TestOutcome::Failed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::header::{LOGO_START, NINTENDO_LOGO};
+
+    /// Builds a ROM that loads `values` into B, C, D, E, H and L and then executes ld b, b.
+    fn exit_rom(values: [u8; 6]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[LOGO_START..LOGO_START + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        // jp 0x0150, past the header
+        let mut program = vec![0xC3, 0x50, 0x01];
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(&program);
+        program.clear();
+        // ld r8, imm8 for B, C, D, E, H and L
+        for (opcode, value) in [0x06, 0x0E, 0x16, 0x1E, 0x26, 0x2E].into_iter().zip(values) {
+            program.extend([opcode, value]);
+        }
+        program.push(0x40);
+        rom[0x0150..0x0150 + program.len()].copy_from_slice(&program);
+        rom
+    }
+
+    #[test]
+    fn passing_rom() {
+        let rom = exit_rom([3, 5, 8, 13, 21, 34]);
+        assert_eq!(run_test_rom(&rom, true).unwrap(), TestOutcome::Passed);
+    }
+
+    #[test]
+    fn failing_rom() {
+        let rom = exit_rom([0x42; 6]);
+        assert_eq!(run_test_rom(&rom, true).unwrap(), TestOutcome::Failed);
+    }
+}
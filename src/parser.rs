@@ -1,6 +1,13 @@
-use std::io::{Bytes, Read};
+use std::io::{self, Bytes, Read};
 
-use crate::{errors::EmulatorError, instructions::Instruction};
+use crate::bits;
+
+use crate::{
+    errors::EmulatorError,
+    instructions::{
+        CondOperand, Instruction, R16MemOperand, R16Operand, R16StkOperand, R8Operand, U3Operand,
+    },
+};
 
 pub fn parse_instructions(
     bytes: Bytes<impl Read>,
@@ -30,15 +37,248 @@ pub fn parse_instructions(
             println!("Byte: '{byte:0>8b}' ('{byte:0>2x}')");
         }
 
-        let instruction = match byte {
-            0 => Instruction::Nop,
-            _ => todo!("Instruction '{byte:0>8b}' ('{byte:0>2x}')"),
-        };
+        let instruction = decode_instruction(byte, || match enumerated_bytes.next() {
+            Some((_, byte_result)) => byte_result,
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Instruction '{byte:0>2x}' is missing its operands"),
+            )),
+        })?;
         instructions.push(instruction);
     }
     Ok(instructions)
 }
 
+/// Decodes a single instruction starting with `opcode`. Operand bytes (and the second byte of
+/// CB-prefixed instructions) are requested from `next_byte` in the order they appear in memory.
+pub fn decode_instruction<E>(
+    opcode: u8,
+    mut next_byte: impl FnMut() -> Result<u8, E>,
+) -> Result<Instruction, E> {
+    let mut imm16 = || -> Result<u16, E> {
+        let low = next_byte()?;
+        let high = next_byte()?;
+        Ok(u16::from_le_bytes([low, high]))
+    };
+
+    let instruction = match opcode {
+        bits!(00000000) => Instruction::Nop,
+        bits!(00__0001) => Instruction::LoadImm16 {
+            dst: r16(opcode >> 4),
+            imm: imm16()?,
+        },
+        bits!(00__0010) => Instruction::StoreARegToMem {
+            dst: r16mem(opcode >> 4),
+        },
+        bits!(00__1010) => Instruction::LoadMemToAReg {
+            src: r16mem(opcode >> 4),
+        },
+        bits!(00001000) => Instruction::StoreSPToImmMem { dst: imm16()? },
+        bits!(00__0011) => Instruction::IncR16 {
+            reg: r16(opcode >> 4),
+        },
+        bits!(00__1011) => Instruction::DecR16 {
+            reg: r16(opcode >> 4),
+        },
+        bits!(00__1001) => Instruction::AddToHLReg {
+            reg: r16(opcode >> 4),
+        },
+        bits!(00___100) => Instruction::IncR8 {
+            reg: r8(opcode >> 3),
+        },
+        bits!(00___101) => Instruction::DecR8 {
+            reg: r8(opcode >> 3),
+        },
+        bits!(00___110) => Instruction::LoadImm8 {
+            dst: r8(opcode >> 3),
+            imm: next_byte()?,
+        },
+        bits!(00000111) => Instruction::RotARegLeftSetC,
+        bits!(00001111) => Instruction::RotARegRightSetC,
+        bits!(00010111) => Instruction::RotARegLeftThroughC,
+        bits!(00011111) => Instruction::RotARegRightThroughC,
+        bits!(00100111) => Instruction::DecAdjAccum,
+        bits!(00101111) => Instruction::InvA,
+        bits!(00110111) => Instruction::SetC,
+        bits!(00111111) => Instruction::InvC,
+        bits!(00011000) => Instruction::JumpRelativeImm {
+            imm: next_byte()? as i8,
+        },
+        bits!(001__000) => Instruction::JumpRelativeImmUnderCond {
+            cond: cond(opcode >> 3),
+            imm: next_byte()? as i8,
+        },
+        bits!(00010000) => {
+            // the byte following stop is skipped by the CPU
+            next_byte()?;
+            Instruction::Stop
+        }
+        bits!(01______) => {
+            let dst = r8(opcode >> 3);
+            let src = r8(opcode);
+            // the encoding of ld [hl], [hl] is used for halt instead
+            if dst == R8Operand::HLAddr && src == R8Operand::HLAddr {
+                Instruction::Halt
+            } else {
+                Instruction::LoadR8ToR8 { dst, src }
+            }
+        }
+        bits!(10000___) => Instruction::AddRegToAReg { reg: r8(opcode) },
+        bits!(10001___) => Instruction::AddRegCToAReg { reg: r8(opcode) },
+        bits!(10010___) => Instruction::SubRegFromAReg { reg: r8(opcode) },
+        bits!(10011___) => Instruction::SubRegCFromAReg { reg: r8(opcode) },
+        bits!(10100___) => Instruction::AndRegToAReg { reg: r8(opcode) },
+        bits!(10101___) => Instruction::XorRegToAReg { reg: r8(opcode) },
+        bits!(10110___) => Instruction::OrRegToAReg { reg: r8(opcode) },
+        bits!(10111___) => Instruction::CmpRegToAReg { reg: r8(opcode) },
+        bits!(11000110) => Instruction::AddImmToAReg { imm: next_byte()? },
+        bits!(11001110) => Instruction::AddImmCToAReg { imm: next_byte()? },
+        bits!(11010110) => Instruction::SubImmFromAReg { imm: next_byte()? },
+        bits!(11011110) => Instruction::SubImmCFromAReg { imm: next_byte()? },
+        bits!(11100110) => Instruction::AndImmToAReg { imm: next_byte()? },
+        bits!(11101110) => Instruction::XorImmToAReg { imm: next_byte()? },
+        bits!(11110110) => Instruction::OrImmToAReg { imm: next_byte()? },
+        bits!(11111110) => Instruction::CmpImmToAReg { imm: next_byte()? },
+        bits!(110__000) => Instruction::RetUnderCond {
+            cond: cond(opcode >> 3),
+        },
+        bits!(11001001) => Instruction::Ret,
+        bits!(11011001) => Instruction::RetInterrupts,
+        bits!(110__010) => Instruction::JumpImmUnderCond {
+            cond: cond(opcode >> 3),
+            imm: imm16()?,
+        },
+        bits!(11000011) => Instruction::JumpImm { imm: imm16()? },
+        bits!(11101001) => Instruction::JumpHL,
+        bits!(110__100) => Instruction::CallImmUnderCond {
+            cond: cond(opcode >> 3),
+            imm: imm16()?,
+        },
+        bits!(11001101) => Instruction::CallImm { imm: imm16()? },
+        bits!(11___111) => Instruction::CallRst {
+            target: u3(opcode >> 3),
+        },
+        bits!(11__0001) => Instruction::Pop {
+            reg: r16stk(opcode >> 4),
+        },
+        bits!(11__0101) => Instruction::Push {
+            reg: r16stk(opcode >> 4),
+        },
+        bits!(11001011) => decode_prefixed_instruction(next_byte()?),
+        bits!(11100010) => Instruction::StoreARegToCMem,
+        bits!(11100000) => Instruction::StoreARegToImm8Mem { imm: next_byte()? },
+        bits!(11101010) => Instruction::StoreARegToImm16Mem { imm: imm16()? },
+        bits!(11110010) => Instruction::LoadCMemToAReg,
+        bits!(11110000) => Instruction::LoadImm8MemToAReg { imm: next_byte()? },
+        bits!(11111010) => Instruction::LoadImm16MemToAReg { imm: imm16()? },
+        bits!(11101000) => Instruction::AddImmToSP {
+            imm: next_byte()? as i8,
+        },
+        bits!(11111000) => Instruction::LoadSPWithImmToHLReg {
+            imm: next_byte()? as i8,
+        },
+        bits!(11111001) => Instruction::LoadHLRegToSP,
+        bits!(11110011) => Instruction::DisableInterrupts,
+        bits!(11111011) => Instruction::EnableInterrupts,
+        _ => Instruction::Illegal { opcode },
+    };
+    Ok(instruction)
+}
+
+fn decode_prefixed_instruction(opcode: u8) -> Instruction {
+    let reg = r8(opcode);
+    match opcode {
+        bits!(00000___) => Instruction::RotR8LeftSetC { reg },
+        bits!(00001___) => Instruction::RotR8RightSetC { reg },
+        bits!(00010___) => Instruction::RotR8LeftThroughC { reg },
+        bits!(00011___) => Instruction::RotR8RightThroughC { reg },
+        bits!(00100___) => Instruction::ShiftLeftArith { reg },
+        bits!(00101___) => Instruction::ShiftRightArith { reg },
+        bits!(00110___) => Instruction::SwapHighLowR8 { reg },
+        bits!(00111___) => Instruction::ShiftRightLogic { reg },
+        bits!(01______) => Instruction::TestBit {
+            bit_num: u3(opcode >> 3),
+            reg,
+        },
+        bits!(10______) => Instruction::SetBitZero {
+            bit_num: u3(opcode >> 3),
+            reg,
+        },
+        bits!(11______) => Instruction::SetBitOne {
+            bit_num: u3(opcode >> 3),
+            reg,
+        },
+    }
+}
+
+/// Decodes the 3-bit register operand in the lowest bits of `bits`.
+fn r8(bits: u8) -> R8Operand {
+    match bits & 0b111 {
+        0 => R8Operand::BReg,
+        1 => R8Operand::CReg,
+        2 => R8Operand::DReg,
+        3 => R8Operand::EReg,
+        4 => R8Operand::HReg,
+        5 => R8Operand::LReg,
+        6 => R8Operand::HLAddr,
+        _ => R8Operand::AReg,
+    }
+}
+
+/// Decodes the 2-bit register operand in the lowest bits of `bits`.
+fn r16(bits: u8) -> R16Operand {
+    match bits & 0b11 {
+        0 => R16Operand::BCReg,
+        1 => R16Operand::DEReg,
+        2 => R16Operand::HLReg,
+        _ => R16Operand::SP,
+    }
+}
+
+/// Decodes the 2-bit stack register operand in the lowest bits of `bits`.
+fn r16stk(bits: u8) -> R16StkOperand {
+    match bits & 0b11 {
+        0 => R16StkOperand::BCReg,
+        1 => R16StkOperand::DEReg,
+        2 => R16StkOperand::HLReg,
+        _ => R16StkOperand::AFReg,
+    }
+}
+
+/// Decodes the 2-bit memory register operand in the lowest bits of `bits`.
+fn r16mem(bits: u8) -> R16MemOperand {
+    match bits & 0b11 {
+        0 => R16MemOperand::BCReg,
+        1 => R16MemOperand::DEReg,
+        2 => R16MemOperand::HLRegAndInc,
+        _ => R16MemOperand::HLRegAndDec,
+    }
+}
+
+/// Decodes the 2-bit condition operand in the lowest bits of `bits`.
+fn cond(bits: u8) -> CondOperand {
+    match bits & 0b11 {
+        0 => CondOperand::NZ,
+        1 => CondOperand::Z,
+        2 => CondOperand::NC,
+        _ => CondOperand::C,
+    }
+}
+
+/// Decodes the 3-bit operand in the lowest bits of `bits`.
+fn u3(bits: u8) -> U3Operand {
+    match bits & 0b111 {
+        0 => U3Operand::Zero,
+        1 => U3Operand::One,
+        2 => U3Operand::Two,
+        3 => U3Operand::Three,
+        4 => U3Operand::Four,
+        5 => U3Operand::Five,
+        6 => U3Operand::Six,
+        _ => U3Operand::Seven,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
@@ -54,4 +294,47 @@ mod tests {
         let instructions = instructions.unwrap();
         assert_eq!(instructions, vec![Instruction::Nop]);
     }
+
+    #[test]
+    fn operands() {
+        let bytes = [
+            0x01, 0x34, 0x12, 0x70, 0x76, 0x28, 0xFE, 0xCB, 0x7E, 0xF5, 0xD3,
+        ];
+        let cursor = Cursor::new(bytes);
+        let instructions = parse_instructions(cursor.bytes(), bytes.len(), false).unwrap();
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::LoadImm16 {
+                    dst: R16Operand::BCReg,
+                    imm: 0x1234
+                },
+                Instruction::LoadR8ToR8 {
+                    dst: R8Operand::HLAddr,
+                    src: R8Operand::BReg
+                },
+                Instruction::Halt,
+                Instruction::JumpRelativeImmUnderCond {
+                    cond: CondOperand::Z,
+                    imm: -2
+                },
+                Instruction::TestBit {
+                    bit_num: U3Operand::Seven,
+                    reg: R8Operand::HLAddr
+                },
+                Instruction::Push {
+                    reg: R16StkOperand::AFReg
+                },
+                Instruction::Illegal { opcode: 0xD3 },
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_operand() {
+        let bytes = [0xC3, 0x00];
+        let cursor = Cursor::new(bytes);
+        let instructions = parse_instructions(cursor.bytes(), bytes.len(), false);
+        assert!(instructions.is_err());
+    }
 }
@@ -0,0 +1,105 @@
+use crate::errors::EmulatorError;
+
+/// A binary Netpbm picture: PGM (P5) with one sample per pixel or PPM (P6) with three.
+pub struct Pnm<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub max_value: usize,
+    /// `width * height` pixels of 8-bit samples, row by row
+    pub samples: &'a [u8],
+}
+
+impl<'a> Pnm<'a> {
+    /// Parses a binary PGM (`magic` P5) or PPM (`magic` P6) picture with 8-bit samples.
+    pub fn parse(data: &'a [u8], magic: &str) -> Result<Self, EmulatorError> {
+        let format = if magic == "P6" { "PPM" } else { "PGM" };
+        let error = |message: String| EmulatorError::ImageError(message);
+
+        // the header consists of 4 whitespace separated tokens, followed by a single whitespace
+        let mut tokens = Vec::new();
+        let mut position = 0;
+        while tokens.len() < 4 {
+            while data
+                .get(position)
+                .is_some_and(|byte| byte.is_ascii_whitespace())
+            {
+                position += 1;
+            }
+            if data.get(position) == Some(&b'#') {
+                while data.get(position).is_some_and(|&byte| byte != b'\n') {
+                    position += 1;
+                }
+                continue;
+            }
+            let start = position;
+            while data
+                .get(position)
+                .is_some_and(|byte| !byte.is_ascii_whitespace())
+            {
+                position += 1;
+            }
+            if start == position {
+                return Err(error(format!("The {format} header is incomplete.")));
+            }
+            tokens.push(String::from_utf8_lossy(&data[start..position]).into_owned());
+        }
+        position += 1;
+
+        if tokens[0] != magic {
+            return Err(error(format!(
+                "Only binary {format} ({magic}) pictures are supported."
+            )));
+        }
+        let number = |token: &str| {
+            token
+                .parse::<usize>()
+                .map_err(|_| error(format!("The {format} header contains an invalid number.")))
+        };
+        let (width, height, max_value) = (
+            number(&tokens[1])?,
+            number(&tokens[2])?,
+            number(&tokens[3])?,
+        );
+        if !(1..=255).contains(&max_value) {
+            return Err(error(format!(
+                "Only {format} pictures with 8-bit samples are supported."
+            )));
+        }
+        let channels = if magic == "P6" { 3 } else { 1 };
+        let samples = data
+            .get(position..position + width * height * channels)
+            .filter(|_| width > 0 && height > 0)
+            .ok_or_else(|| error(format!("The {format} picture data is incomplete.")))?;
+        Ok(Pnm {
+            width,
+            height,
+            max_value,
+            samples,
+        })
+    }
+}
+
+/// Encodes RGBA pixels as a binary PPM, dropping the alpha channel.
+pub fn encode_ppm(width: usize, height: usize, rgba: &[u8]) -> Vec<u8> {
+    let mut ppm = format!("P6\n{width} {height}\n255\n").into_bytes();
+    for pixel in rgba.chunks_exact(4) {
+        ppm.extend_from_slice(&pixel[..3]);
+    }
+    ppm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ppm_round_trip() {
+        let rgba = [1, 2, 3, 0xFF, 4, 5, 6, 0xFF];
+        let ppm = encode_ppm(2, 1, &rgba);
+        let parsed = Pnm::parse(&ppm, "P6").unwrap();
+        assert_eq!((parsed.width, parsed.height), (2, 1));
+        assert_eq!(parsed.samples, [1, 2, 3, 4, 5, 6]);
+        assert!(Pnm::parse(&ppm, "P5").is_err());
+        assert!(Pnm::parse(&ppm[..ppm.len() - 1], "P6").is_err());
+    }
+}
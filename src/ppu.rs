@@ -0,0 +1,1290 @@
+mod fifo;
+mod scanline;
+
+use std::{fmt, str::FromStr, sync::OnceLock};
+
+use crate::{boot::Model, bus::Interrupt};
+
+use fifo::PixelFifo;
+
+/// Width of the LCD in pixels.
+pub const SCREEN_WIDTH: usize = 160;
+/// Height of the LCD in pixels.
+pub const SCREEN_HEIGHT: usize = 144;
+
+/// Dots (T-cycles in normal speed) per scanline, including HBlank.
+const LINE_DOTS: u16 = 456;
+/// Scanlines per frame, including the 10 lines of VBlank.
+const LINES: u8 = 154;
+/// Length of mode 2.
+const OAM_SCAN_DOTS: u16 = 80;
+/// Length of mode 3 without any of the delays caused by scrolling, the window or objects.
+const DRAWING_DOTS: u16 = 172;
+/// Dots into line 153 after which LY already reads 0.
+const LAST_LINE_LY_DOTS: u16 = 4;
+/// WX at which the window starts on the last pixel of a line and then covers the whole next line.
+const WX_WRAPPING: u8 = 166;
+/// Objects a scanline can show at most.
+const MAX_LINE_OBJECTS: usize = 10;
+
+const LCDC_ADDRESS: u16 = 0xFF40;
+const STAT_ADDRESS: u16 = 0xFF41;
+const SCY_ADDRESS: u16 = 0xFF42;
+const SCX_ADDRESS: u16 = 0xFF43;
+const LY_ADDRESS: u16 = 0xFF44;
+const LYC_ADDRESS: u16 = 0xFF45;
+const BGP_ADDRESS: u16 = 0xFF47;
+const OBP0_ADDRESS: u16 = 0xFF48;
+const OBP1_ADDRESS: u16 = 0xFF49;
+const WY_ADDRESS: u16 = 0xFF4A;
+const WX_ADDRESS: u16 = 0xFF4B;
+const BCPS_ADDRESS: u16 = 0xFF68;
+const BCPD_ADDRESS: u16 = 0xFF69;
+const OCPS_ADDRESS: u16 = 0xFF6A;
+const OCPD_ADDRESS: u16 = 0xFF6B;
+
+const LCDC_ENABLE: u8 = 0x80;
+const LCDC_WINDOW_MAP: u8 = 0x40;
+const LCDC_WINDOW_ENABLE: u8 = 0x20;
+const LCDC_TILE_DATA: u8 = 0x10;
+const LCDC_BG_MAP: u8 = 0x08;
+const LCDC_OBJ_SIZE: u8 = 0x04;
+const LCDC_OBJ_ENABLE: u8 = 0x02;
+const LCDC_BG_ENABLE: u8 = 0x01;
+
+const STAT_LYC_SOURCE: u8 = 0x40;
+const STAT_OAM_SCAN_SOURCE: u8 = 0x20;
+const STAT_VBLANK_SOURCE: u8 = 0x10;
+const STAT_HBLANK_SOURCE: u8 = 0x08;
+const STAT_COINCIDENCE: u8 = 0x04;
+
+// Attribute bits of objects in OAM, and of BG and window tiles in VRAM bank 1 on the CGB, except
+// for the DMG palette.
+const ATTRIBUTE_PRIORITY: u8 = 0x80;
+const ATTRIBUTE_Y_FLIP: u8 = 0x40;
+const ATTRIBUTE_X_FLIP: u8 = 0x20;
+const ATTRIBUTE_DMG_PALETTE: u8 = 0x10;
+const ATTRIBUTE_BANK: u8 = 0x08;
+const ATTRIBUTE_CGB_PALETTE: u8 = 0x07;
+
+/// Bit of BCPS and OCPS advancing the index after each write of palette data.
+const PALETTE_AUTO_INCREMENT: u8 = 0x80;
+/// Size of the BG and the object palette RAM: 8 palettes of 4 colours in RGB555.
+const PALETTE_RAM_SIZE: usize = 64;
+
+/// The four DMG shades from white to black as RGBA.
+const SHADES: [[u8; 4]; 4] = [
+    [0xFF, 0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA, 0xFF],
+    [0x55, 0x55, 0x55, 0xFF],
+    [0x00, 0x00, 0x00, 0xFF],
+];
+
+/// The PPU mode, as shown in the lower two bits of STAT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+/// How the PPU draws, trading accuracy for speed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Renderer {
+    /// draws each line at once at the start of mode 3, which always takes 172 dots
+    #[default]
+    Scanline,
+    /// shifts pixels out of the background and object FIFOs dot by dot like the hardware, so that
+    /// mode 3 takes as long as it does there and register changes in the middle of a line show
+    PixelFifo,
+}
+
+impl fmt::Display for Renderer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Renderer::Scanline => "scanline",
+            Renderer::PixelFifo => "fifo",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Parses the renderer names as displayed, ignoring case.
+impl FromStr for Renderer {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        [Renderer::Scanline, Renderer::PixelFifo]
+            .into_iter()
+            .find(|renderer| renderer.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(|| "expected one of scanline, fifo".to_string())
+    }
+}
+
+/// How CGB colours in RGB555 are converted to the RGB of the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorCorrection {
+    /// scales each 5-bit channel to 8 bits, as test ROMs like cgb-acid2 expect
+    #[default]
+    Raw,
+    /// mixes the channels and darkens bright colours like the washed out LCD of the CGB, which
+    /// games were designed for, with a linear approximation
+    Matrix,
+    /// models the response curve of the CGB LCD, mixing the channels in linear light
+    Lcd,
+}
+
+/// Gamma of the CGB LCD, turning channel values into light.
+const LCD_GAMMA: f32 = 2.2;
+/// Gamma the frame is encoded with, that of sRGB displays.
+const DISPLAY_GAMMA: f32 = 2.2;
+/// Brightness of white on the CGB LCD.
+const LCD_LUMINANCE: f32 = 0.94;
+/// How much of the light of each channel of the CGB LCD (columns) ends up in the red, green and
+/// blue of the frame (rows), each row adding up to 1 so that white stays neutral.
+const LCD_MIX: [[f32; 3]; 3] = [
+    [0.82, 0.24, -0.06],
+    [0.125, 0.665, 0.21],
+    [0.195, 0.075, 0.73],
+];
+
+/// Converts an RGB555 colour through the response curve of the CGB LCD.
+fn lcd_color(color: u16) -> [u8; 4] {
+    let light = [0, 5, 10].map(|shift| (((color >> shift) & 0x1F) as f32 / 31.0).powf(LCD_GAMMA));
+    let [red, green, blue] = LCD_MIX.map(|weights| {
+        let sum: f32 = weights
+            .iter()
+            .zip(light)
+            .map(|(weight, light)| weight * light)
+            .sum();
+        let encoded = (sum * LCD_LUMINANCE)
+            .clamp(0.0, 1.0)
+            .powf(1.0 / DISPLAY_GAMMA);
+        (encoded * 255.0).round() as u8
+    });
+    [red, green, blue, 0xFF]
+}
+
+impl ColorCorrection {
+    /// Converts a colour in RGB555 as stored in palette RAM, red in the lowest bits, to RGBA.
+    fn convert(self, color: u16) -> [u8; 4] {
+        let [red, green, blue] = [0, 5, 10].map(|shift| ((color >> shift) & 0x1F) as u32);
+        match self {
+            ColorCorrection::Raw => {
+                let scale = |channel: u32| (channel << 3 | channel >> 2) as u8;
+                [scale(red), scale(green), scale(blue), 0xFF]
+            }
+            ColorCorrection::Matrix => {
+                // each channel bleeds into the others and tops out at 240
+                let mix = |weights: [u32; 3]| {
+                    let sum = red * weights[0] + green * weights[1] + blue * weights[2];
+                    (sum.min(960) >> 2) as u8
+                };
+                [mix([26, 4, 2]), mix([0, 24, 8]), mix([6, 4, 22]), 0xFF]
+            }
+            ColorCorrection::Lcd => {
+                // the curve is too slow to evaluate for every pixel
+                static COLORS: OnceLock<Vec<[u8; 4]>> = OnceLock::new();
+                COLORS.get_or_init(|| (0..0x8000).map(lcd_color).collect())
+                    [(color & 0x7FFF) as usize]
+            }
+        }
+    }
+}
+
+impl fmt::Display for ColorCorrection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColorCorrection::Raw => "raw",
+            ColorCorrection::Matrix => "matrix",
+            ColorCorrection::Lcd => "lcd",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Parses the colour correction names as displayed, ignoring case.
+impl FromStr for ColorCorrection {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        [
+            ColorCorrection::Raw,
+            ColorCorrection::Matrix,
+            ColorCorrection::Lcd,
+        ]
+        .into_iter()
+        .find(|correction| correction.to_string().eq_ignore_ascii_case(name))
+        .ok_or_else(|| "expected one of raw, matrix, lcd".to_string())
+    }
+}
+
+/// A pixel of the background or the window, to be mixed with the object pixel on top of it.
+#[derive(Debug, Clone, Copy, Default)]
+struct BackgroundPixel {
+    /// colour index
+    color: u8,
+    /// the attributes of the tile from VRAM bank 1 in CGB mode, 0 otherwise
+    attributes: u8,
+}
+
+/// A pixel of an object, to be mixed with the background pixel below it.
+#[derive(Debug, Clone, Copy, Default)]
+struct ObjectPixel {
+    /// colour index, 0 being transparent
+    color: u8,
+    /// the attribute byte of the object in OAM
+    attributes: u8,
+    /// offset of the object in OAM
+    oam_offset: u8,
+}
+
+/// What happened during `Ppu::tick`.
+#[derive(Debug, Default)]
+pub(crate) struct PpuEvents {
+    /// interrupt requests as IF bits
+    pub interrupts: u8,
+    /// HBlank of a visible line started
+    pub hblank: bool,
+}
+
+/// The picture processing unit, which owns VRAM and OAM and draws a 160×144 frame from them.
+///
+/// Every visible line starts with the OAM scan (mode 2) selecting the objects on the line, followed
+/// by drawing (mode 3) and HBlank (mode 0). Lines 144-153 are VBlank (mode 1). How mode 3 draws
+/// the line depends on the `Renderer`.
+///
+/// The interrupt sources enabled in STAT are ORed into a single line, and the STAT interrupt is
+/// only requested when that line rises. A source becoming active while another one already holds
+/// the line high does not request it again.
+pub(crate) struct Ppu {
+    /// two banks on the CGB
+    vram: Box<[u8; 0x4000]>,
+    oam: [u8; 0xA0],
+    lcdc: u8,
+    /// the interrupt source bits of STAT, the mode and coincidence bits are computed
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    /// the LY register, which differs from `line` at the end of line 153
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    /// CGB palette RAM, written through BCPS/BCPD
+    bg_palettes: [u8; PALETTE_RAM_SIZE],
+    /// CGB palette RAM, written through OCPS/OCPD
+    obj_palettes: [u8; PALETTE_RAM_SIZE],
+    /// BCPS, the index into `bg_palettes` and the auto-increment bit
+    bcps: u8,
+    /// OCPS, the index into `obj_palettes` and the auto-increment bit
+    ocps: u8,
+    color_correction: ColorCorrection,
+    renderer: Renderer,
+    /// state of the `Renderer::PixelFifo` during mode 3
+    fifo: PixelFifo,
+    mode: Mode,
+    /// line being drawn, 0-153
+    line: u8,
+    /// dot within the current line
+    dot: u16,
+    /// the STAT interrupt line, the enabled sources ORed together
+    stat_line: bool,
+    /// writing STAT briefly enables all sources, as on the DMG
+    stat_write_bug: bool,
+    /// a CGB running a game with CGB support, which has colour palettes and tile attributes,
+    /// draws overlapping objects in OAM order only instead of the object further left winning,
+    /// and uses LCDC bit 0 as the master priority instead of the BG enable
+    cgb_mode: bool,
+    /// OAM offsets of the objects on the current line, selected during the OAM scan
+    line_objects: Vec<usize>,
+    /// the window's own line counter, which only advances on lines that draw the window
+    window_line: u8,
+    /// LY matched WY at the start of a line of this frame, which the window needs to show
+    window_y_reached: bool,
+    /// the window has been drawn on the current line
+    window_drawn: bool,
+    /// the window started on the last pixel of the previous line and covers this whole line
+    window_wraps: bool,
+    /// RGBA pixels of the last completed frame, row by row
+    framebuffer: Box<[u8; SCREEN_WIDTH * SCREEN_HEIGHT * 4]>,
+    /// RGBA pixels of the frame being drawn, swapped with `framebuffer` at VBlank
+    back_buffer: Box<[u8; SCREEN_WIDTH * SCREEN_HEIGHT * 4]>,
+    /// number of frames completed
+    frames: u64,
+}
+
+impl Ppu {
+    /// Creates a PPU with the LCD off.
+    pub fn new(model: Model, cgb_mode: bool) -> Self {
+        Ppu {
+            vram: Box::new([0; 0x4000]),
+            oam: [0; 0xA0],
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            // the CGB boot ROM leaves the background white
+            bg_palettes: [0xFF; PALETTE_RAM_SIZE],
+            obj_palettes: [0; PALETTE_RAM_SIZE],
+            bcps: 0,
+            ocps: 0,
+            color_correction: ColorCorrection::default(),
+            renderer: Renderer::default(),
+            fifo: PixelFifo::default(),
+            mode: Mode::HBlank,
+            line: 0,
+            dot: 0,
+            stat_line: false,
+            stat_write_bug: model != Model::Cgb,
+            cgb_mode,
+            line_objects: Vec::with_capacity(MAX_LINE_OBJECTS),
+            window_line: 0,
+            window_y_reached: false,
+            window_drawn: false,
+            window_wraps: false,
+            framebuffer: Box::new([0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 4]),
+            back_buffer: Box::new([0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 4]),
+            frames: 0,
+        }
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    pub fn set_color_correction(&mut self, color_correction: ColorCorrection) {
+        self.color_correction = color_correction;
+    }
+
+    /// The last completed frame as RGBA pixels, row by row, without the lines of the frame being
+    /// drawn.
+    pub fn frame(&self) -> &[u8] {
+        &self.framebuffer[..]
+    }
+
+    /// Number of frames completed since power on.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn read_vram(&self, index: usize) -> u8 {
+        self.vram[index]
+    }
+
+    pub fn write_vram(&mut self, index: usize, value: u8) {
+        self.vram[index] = value;
+    }
+
+    pub fn read_oam(&self, offset: usize) -> u8 {
+        self.oam[offset]
+    }
+
+    pub fn write_oam(&mut self, offset: usize, value: u8) {
+        self.oam[offset] = value;
+    }
+
+    /// Whether the CPU can access VRAM and CGB palette RAM, which is not the case while the PPU is
+    /// drawing.
+    pub fn vram_accessible(&self) -> bool {
+        self.mode != Mode::Drawing
+    }
+
+    /// Whether the CPU can access OAM, which is not the case during the OAM scan and drawing.
+    pub fn oam_accessible(&self) -> bool {
+        matches!(self.mode, Mode::HBlank | Mode::VBlank)
+    }
+
+    /// Reads one of the LCD registers in 0xFF40-0xFF4B, except for DMA, or one of the CGB palette
+    /// registers in 0xFF68-0xFF6B.
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            LCDC_ADDRESS => self.lcdc,
+            STAT_ADDRESS => {
+                let coincidence = if self.ly == self.lyc {
+                    STAT_COINCIDENCE
+                } else {
+                    0
+                };
+                0x80 | self.stat | coincidence | self.mode as u8
+            }
+            SCY_ADDRESS => self.scy,
+            SCX_ADDRESS => self.scx,
+            LY_ADDRESS => self.ly,
+            LYC_ADDRESS => self.lyc,
+            BGP_ADDRESS => self.bgp,
+            OBP0_ADDRESS => self.obp0,
+            OBP1_ADDRESS => self.obp1,
+            WY_ADDRESS => self.wy,
+            WX_ADDRESS => self.wx,
+            BCPS_ADDRESS => 0x40 | self.bcps,
+            BCPD_ADDRESS if self.vram_accessible() => self.bg_palettes[(self.bcps & 0x3F) as usize],
+            OCPS_ADDRESS => 0x40 | self.ocps,
+            OCPD_ADDRESS if self.vram_accessible() => {
+                self.obj_palettes[(self.ocps & 0x3F) as usize]
+            }
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes one of the LCD registers in 0xFF40-0xFF4B, except for DMA, or one of the CGB palette
+    /// registers in 0xFF68-0xFF6B. Returns interrupt requests as IF bits.
+    pub fn write_register(&mut self, address: u16, value: u8) -> u8 {
+        let mut events = PpuEvents::default();
+        match address {
+            LCDC_ADDRESS => {
+                let was_enabled = self.lcdc & LCDC_ENABLE != 0;
+                self.lcdc = value;
+                match (was_enabled, value & LCDC_ENABLE != 0) {
+                    (true, false) => {
+                        self.line = 0;
+                        self.ly = 0;
+                        self.dot = 0;
+                        self.mode = Mode::HBlank;
+                        self.stat_line = false;
+                    }
+                    (false, true) => {
+                        // the first line after enabling the LCD has no OAM scan, mode 0 is shown
+                        // instead of mode 2
+                        self.line_objects.clear();
+                        self.begin_line();
+                        self.update_stat_line(&mut events);
+                    }
+                    _ => {}
+                }
+            }
+            STAT_ADDRESS => {
+                if self.stat_write_bug && self.enabled() {
+                    // for a cycle, the write acts as if the HBlank, VBlank and LY=LYC sources
+                    // were enabled, which requests the interrupt in modes 0 and 1 and on a match
+                    self.stat = STAT_HBLANK_SOURCE | STAT_VBLANK_SOURCE | STAT_LYC_SOURCE;
+                    self.update_stat_line(&mut events);
+                }
+                // the mode and coincidence bits are read-only
+                self.stat = value & 0x78;
+                if self.enabled() {
+                    self.update_stat_line(&mut events);
+                }
+            }
+            SCY_ADDRESS => self.scy = value,
+            SCX_ADDRESS => self.scx = value,
+            LY_ADDRESS => {}
+            LYC_ADDRESS => {
+                self.lyc = value;
+                if self.enabled() {
+                    self.update_stat_line(&mut events);
+                }
+            }
+            BGP_ADDRESS => self.bgp = value,
+            OBP0_ADDRESS => self.obp0 = value,
+            OBP1_ADDRESS => self.obp1 = value,
+            WY_ADDRESS => self.wy = value,
+            WX_ADDRESS => self.wx = value,
+            BCPS_ADDRESS => self.bcps = value & 0xBF,
+            BCPD_ADDRESS => {
+                let accessible = self.vram_accessible();
+                write_palette(&mut self.bg_palettes, &mut self.bcps, value, accessible);
+            }
+            OCPS_ADDRESS => self.ocps = value & 0xBF,
+            OCPD_ADDRESS => {
+                let accessible = self.vram_accessible();
+                write_palette(&mut self.obj_palettes, &mut self.ocps, value, accessible);
+            }
+            _ => {}
+        }
+        events.interrupts
+    }
+
+    /// Advances the PPU by `dots`, 4 per M-cycle in normal speed and 2 in double speed.
+    pub fn tick(&mut self, dots: u8) -> PpuEvents {
+        let mut events = PpuEvents::default();
+        if self.enabled() {
+            for _ in 0..dots {
+                self.step(&mut events);
+            }
+        }
+        events
+    }
+
+    fn enabled(&self) -> bool {
+        self.lcdc & LCDC_ENABLE != 0
+    }
+
+    fn step(&mut self, events: &mut PpuEvents) {
+        self.dot += 1;
+        if self.dot == LINE_DOTS {
+            self.dot = 0;
+            self.line = (self.line + 1) % LINES;
+            self.ly = self.line;
+        }
+        let visible = (self.line as usize) < SCREEN_HEIGHT;
+        match self.dot {
+            0 if visible => {
+                self.begin_line();
+                self.enter_mode(Mode::OamScan);
+            }
+            0 if self.line as usize == SCREEN_HEIGHT => {
+                self.enter_mode(Mode::VBlank);
+                events.interrupts |= Interrupt::VBlank.mask();
+                std::mem::swap(&mut self.framebuffer, &mut self.back_buffer);
+                self.frames += 1;
+            }
+            // LY wraps to 0 early
+            LAST_LINE_LY_DOTS if self.line == LINES - 1 => self.ly = 0,
+            OAM_SCAN_DOTS if visible => {
+                self.enter_mode(Mode::Drawing);
+                match self.renderer {
+                    Renderer::Scanline => scanline::render_line(self),
+                    Renderer::PixelFifo => fifo::start_line(self),
+                }
+            }
+            _ => {}
+        }
+        if self.mode == Mode::Drawing && self.drawing_done() {
+            self.enter_mode(Mode::HBlank);
+            events.hblank = true;
+        }
+        self.update_stat_line(events);
+    }
+
+    /// Updates the window state for the visible line starting.
+    fn begin_line(&mut self) {
+        if self.line == 0 {
+            self.window_line = 0;
+            self.window_y_reached = false;
+            self.window_wraps = false;
+        } else {
+            self.window_wraps = self.window_drawn && self.wx == WX_WRAPPING;
+            if self.window_drawn {
+                self.window_line += 1;
+            }
+        }
+        self.window_drawn = false;
+        if self.ly == self.wy {
+            self.window_y_reached = true;
+        }
+    }
+
+    /// Returns the screen X at which the window starts on the current line and the number of its
+    /// pixels cut off at the left edge, or `None` if it is not shown. LCDC bit 5 can be toggled
+    /// between lines, leaving the window line counter where it was.
+    ///
+    /// WX below 7 cuts off the window at the left edge. The renderers cut off another SCX & 7
+    /// pixels for WX 0, which makes the window stutter as SCX changes. With WX 166 the window
+    /// starts on the last pixel and covers the whole next line.
+    fn window_start(&self) -> Option<(u8, u8)> {
+        if self.lcdc & LCDC_WINDOW_ENABLE == 0 || !self.window_y_reached {
+            return None;
+        }
+        match self.wx {
+            _ if self.window_wraps => Some((0, 0)),
+            0..=6 => Some((0, 7 - self.wx)),
+            7..=WX_WRAPPING => Some((self.wx - 7, 0)),
+            _ => None,
+        }
+    }
+
+    /// Advances mode 3 by a dot, returning whether the line is complete.
+    fn drawing_done(&mut self) -> bool {
+        match self.renderer {
+            Renderer::Scanline => self.dot == OAM_SCAN_DOTS + DRAWING_DOTS,
+            Renderer::PixelFifo => fifo::step(self),
+        }
+    }
+
+    fn enter_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        if mode == Mode::OamScan {
+            self.scan_oam();
+        }
+    }
+
+    /// Recomputes the STAT interrupt line, requesting the interrupt if it rises.
+    fn update_stat_line(&mut self, events: &mut PpuEvents) {
+        let source = match self.mode {
+            Mode::HBlank => STAT_HBLANK_SOURCE,
+            // the OAM scan source also fires as VBlank starts
+            Mode::VBlank if self.line as usize == SCREEN_HEIGHT && self.dot == 0 => {
+                STAT_VBLANK_SOURCE | STAT_OAM_SCAN_SOURCE
+            }
+            Mode::VBlank => STAT_VBLANK_SOURCE,
+            Mode::OamScan => STAT_OAM_SCAN_SOURCE,
+            Mode::Drawing => 0,
+        };
+        let coincidence = if self.ly == self.lyc {
+            STAT_LYC_SOURCE
+        } else {
+            0
+        };
+        let stat_line = self.stat & (source | coincidence) != 0;
+        if stat_line && !self.stat_line {
+            events.interrupts |= Interrupt::Stat.mask();
+        }
+        self.stat_line = stat_line;
+    }
+
+    fn object_height(&self) -> u8 {
+        if self.lcdc & LCDC_OBJ_SIZE != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Selects the first objects in OAM that overlap the current line.
+    fn scan_oam(&mut self) {
+        self.line_objects.clear();
+        let height = self.object_height();
+        // object Y coordinates are offset by 16 so that objects can be partially above the screen
+        let line = self.ly + 16;
+        for offset in (0..self.oam.len()).step_by(4) {
+            let y = self.oam[offset];
+            if line >= y && line < y.saturating_add(height) {
+                self.line_objects.push(offset);
+                if self.line_objects.len() == MAX_LINE_OBJECTS {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Returns the color index (0-3) of pixel `x` (0-7, from the left) in row `row` of the tile
+    /// whose data starts at `tile_index` in VRAM.
+    fn tile_pixel(&self, tile_index: usize, row: u8, x: u8) -> u8 {
+        let index = tile_index + row as usize * 2;
+        let bit = 7 - x;
+        let low = (self.vram[index] >> bit) & 1;
+        let high = (self.vram[index + 1] >> bit) & 1;
+        high << 1 | low
+    }
+
+    /// Returns the VRAM index of the data of BG or window tile `tile` as addressed by LCDC bit 4,
+    /// either from 0x8000 unsigned or from 0x9000 signed, in the VRAM bank given by `attributes`.
+    fn bg_tile_index(&self, tile: u8, attributes: u8) -> usize {
+        let index = if self.lcdc & LCDC_TILE_DATA != 0 {
+            tile as usize * 16
+        } else {
+            (0x1000 + tile as i8 as isize * 16) as usize
+        };
+        index + bank_offset(attributes)
+    }
+
+    /// Returns the attributes of the tile at VRAM index `map_index` of a tile map, which are at the
+    /// same index in VRAM bank 1 in CGB mode.
+    fn map_attributes(&self, map_index: usize) -> u8 {
+        if self.cgb_mode {
+            self.vram[0x2000 + map_index]
+        } else {
+            0
+        }
+    }
+
+    /// Returns the VRAM index of the BG or window tile map selected by `lcdc_bit`.
+    fn tile_map(&self, lcdc_bit: u8) -> usize {
+        if self.lcdc & lcdc_bit != 0 {
+            0x1C00
+        } else {
+            0x1800
+        }
+    }
+
+    /// Returns the colour indices of the 8 pixels of the object at `offset` in OAM on the current
+    /// line, from left to right on the screen.
+    fn object_row(&self, offset: usize) -> [u8; 8] {
+        let [y, _, mut tile, mut attributes] = self.oam[offset..offset + 4] else {
+            unreachable!()
+        };
+        if !self.cgb_mode {
+            attributes &= !ATTRIBUTE_BANK;
+        }
+        let height = self.object_height();
+        if height == 16 {
+            tile &= 0xFE;
+        }
+        // the OAM scan may have selected the object with another height or Y, the row wraps
+        // around within the current height like on the hardware
+        let mut row = (self.ly + 16).wrapping_sub(y) & (height - 1);
+        if attributes & ATTRIBUTE_Y_FLIP != 0 {
+            row = height - 1 - row;
+        }
+        // rows past the first tile of 8×16 objects come from the next tile
+        let tile_index = tile as usize * 16 + (row as usize / 8) * 16 + bank_offset(attributes);
+        std::array::from_fn(|column| {
+            let x = if attributes & ATTRIBUTE_X_FLIP != 0 {
+                7 - column as u8
+            } else {
+                column as u8
+            };
+            self.tile_pixel(tile_index, row % 8, x)
+        })
+    }
+
+    /// Returns the RGBA colour of a pixel from the background pixel and the object pixel on top
+    /// of it. Objects with the BG priority attribute, or over a tile with it in CGB mode, are only
+    /// drawn over colour 0 of the background.
+    ///
+    /// LCDC bit 0 turns the background white while cleared, except in CGB mode, where it is the
+    /// master priority instead and puts all objects on top while cleared.
+    fn mix_pixel(&self, background: BackgroundPixel, object: Option<ObjectPixel>) -> [u8; 4] {
+        let background_enabled = self.lcdc & LCDC_BG_ENABLE != 0;
+        let behind_background = |object: &ObjectPixel| {
+            (object.attributes | background.attributes) & ATTRIBUTE_PRIORITY != 0
+                && background_enabled
+                && background.color != 0
+        };
+        let object = object.filter(|object| {
+            object.color != 0 && self.lcdc & LCDC_OBJ_ENABLE != 0 && !behind_background(object)
+        });
+        if self.cgb_mode {
+            return match object {
+                Some(object) => self.cgb_color(&self.obj_palettes, object.attributes, object.color),
+                None => self.cgb_color(&self.bg_palettes, background.attributes, background.color),
+            };
+        }
+        let shade = match object {
+            Some(object) => {
+                let palette = if object.attributes & ATTRIBUTE_DMG_PALETTE != 0 {
+                    self.obp1
+                } else {
+                    self.obp0
+                };
+                apply_palette(palette, object.color)
+            }
+            None if !background_enabled => 0,
+            None => apply_palette(self.bgp, background.color),
+        };
+        SHADES[shade as usize]
+    }
+
+    /// Returns colour `color` of the CGB palette selected by `attributes` in palette RAM
+    /// `palettes`, converted to RGBA.
+    fn cgb_color(&self, palettes: &[u8; PALETTE_RAM_SIZE], attributes: u8, color: u8) -> [u8; 4] {
+        let index = (attributes & ATTRIBUTE_CGB_PALETTE) as usize * 8 + color as usize * 2;
+        let rgb555 = u16::from_le_bytes([palettes[index], palettes[index + 1]]);
+        self.color_correction.convert(rgb555)
+    }
+
+    fn set_pixel(&mut self, x: usize, rgba: [u8; 4]) {
+        let index = (self.ly as usize * SCREEN_WIDTH + x) * 4;
+        self.back_buffer[index..index + 4].copy_from_slice(&rgba);
+    }
+}
+
+/// Returns the offset of the VRAM bank selected by bit 3 of tile `attributes`.
+fn bank_offset(attributes: u8) -> usize {
+    if attributes & ATTRIBUTE_BANK != 0 {
+        0x2000
+    } else {
+        0
+    }
+}
+
+/// Writes palette RAM at the index in `specification`, a BCPS or OCPS value, unless the PPU is
+/// drawing. The index advances if enabled even when the write is ignored.
+fn write_palette(
+    palettes: &mut [u8; PALETTE_RAM_SIZE],
+    specification: &mut u8,
+    value: u8,
+    accessible: bool,
+) {
+    let index = *specification & 0x3F;
+    if accessible {
+        palettes[index as usize] = value;
+    }
+    if *specification & PALETTE_AUTO_INCREMENT != 0 {
+        *specification = PALETTE_AUTO_INCREMENT | ((index + 1) & 0x3F);
+    }
+}
+
+/// Maps color index `color` through a DMG palette register.
+fn apply_palette(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0b11
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled_ppu() -> Ppu {
+        let mut ppu = Ppu::new(Model::Dmg, false);
+        ppu.write_register(LCDC_ADDRESS, LCDC_ENABLE | LCDC_BG_ENABLE);
+        ppu
+    }
+
+    #[test]
+    fn mode_timing() {
+        let mut ppu = enabled_ppu();
+        // the first line shows mode 0 instead of the OAM scan
+        assert_eq!(ppu.read_register(STAT_ADDRESS) & 0b11, Mode::HBlank as u8);
+        ppu.tick(80);
+        assert_eq!(ppu.mode, Mode::Drawing);
+        assert!(!ppu.vram_accessible());
+        let events = ppu.tick(172);
+        assert!(events.hblank);
+        assert_eq!(ppu.mode, Mode::HBlank);
+        ppu.tick(204);
+        assert_eq!(ppu.read_register(LY_ADDRESS), 1);
+        assert_eq!(ppu.mode, Mode::OamScan);
+
+        let mut interrupts = 0;
+        for _ in 0..143 * LINE_DOTS {
+            interrupts |= ppu.tick(1).interrupts;
+        }
+        assert_eq!(ppu.read_register(LY_ADDRESS), 144);
+        assert_eq!(interrupts, Interrupt::VBlank.mask());
+        assert_eq!(ppu.frames(), 1);
+        for _ in 0..10 * LINE_DOTS {
+            ppu.tick(1);
+        }
+        assert_eq!(ppu.read_register(LY_ADDRESS), 0);
+    }
+
+    #[test]
+    fn frame_only_shows_completed_frames() {
+        let mut ppu = enabled_ppu();
+        ppu.write_register(BGP_ADDRESS, 0xFF);
+        run_until(&mut ppu, SCREEN_HEIGHT as u8, 0);
+        assert_eq!(ppu.frames(), 1);
+        assert!(ppu.frame().chunks(4).all(|rgba| rgba[0] == 0x00));
+        // halfway through the next, white, frame the black one is still shown
+        ppu.write_register(BGP_ADDRESS, 0x00);
+        run_until(&mut ppu, 72, 0);
+        assert_eq!(pixel(&ppu, 0, 0), 0xFF);
+        assert!(ppu.frame().chunks(4).all(|rgba| rgba[0] == 0x00));
+        run_until(&mut ppu, SCREEN_HEIGHT as u8, 0);
+        assert_eq!(ppu.frames(), 2);
+        assert!(ppu.frame().chunks(4).all(|rgba| rgba[0] == 0xFF));
+    }
+
+    /// Returns the shade of pixel (`x`, `y`) of the frame being drawn by its red channel.
+    fn pixel(ppu: &Ppu, x: usize, y: usize) -> u8 {
+        ppu.back_buffer[(y * SCREEN_WIDTH + x) * 4]
+    }
+
+    #[test]
+    fn renders_background_and_objects() {
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            let ppu = draw_test_line(renderer);
+            assert_eq!(pixel(&ppu, 0, 1), 0x00, "{renderer}");
+            assert_eq!(pixel(&ppu, 1, 1), 0xFF, "{renderer}");
+            assert_eq!(pixel(&ppu, 19, 1), 0xFF, "{renderer}");
+            assert_eq!(pixel(&ppu, 20, 1), 0x55, "{renderer}");
+            assert_eq!(pixel(&ppu, 27, 1), 0x55, "{renderer}");
+            assert_eq!(pixel(&ppu, 28, 1), 0xFF, "{renderer}");
+        }
+    }
+
+    /// Draws the second line, the first after enabling the LCD having no objects, of a scrolled
+    /// background with an object on top.
+    fn draw_test_line(renderer: Renderer) -> Ppu {
+        let mut ppu = Ppu::new(Model::Dmg, false);
+        ppu.set_renderer(renderer);
+        // tile 1 is black in the left column only, tile 2 is color 1 everywhere
+        for row in 0..8 {
+            ppu.write_vram(0x10 + row * 2, 0x80);
+            ppu.write_vram(0x11 + row * 2, 0x80);
+            ppu.write_vram(0x20 + row * 2, 0xFF);
+        }
+        // the second map entry, scrolled to the left edge
+        ppu.write_vram(0x1801, 1);
+        ppu.write_register(SCX_ADDRESS, 8);
+        ppu.write_register(BGP_ADDRESS, 0b11_10_01_00);
+        ppu.write_register(OBP1_ADDRESS, 0b00_00_10_00);
+        // an X-flipped object with tile 2 at (20, 1) using OBP1
+        ppu.write_oam(0, 17);
+        ppu.write_oam(1, 28);
+        ppu.write_oam(2, 2);
+        ppu.write_oam(3, 0x30);
+        ppu.write_register(
+            LCDC_ADDRESS,
+            LCDC_ENABLE | LCDC_TILE_DATA | LCDC_OBJ_ENABLE | LCDC_BG_ENABLE,
+        );
+        while ppu.ly <= 1 {
+            ppu.tick(1);
+        }
+        ppu
+    }
+
+    /// Draws line 1 with two overlapping objects of colour 3 at X 8 and 12, the one at 12 first
+    /// in OAM and black, the one at 8 light gray, over a background with colour 1 from X 16 on.
+    /// The light gray of the CGB palette is 0xAD.
+    fn draw_overlapping_objects(renderer: Renderer, cgb_mode: bool, attributes: u8) -> Ppu {
+        let mut ppu = Ppu::new(Model::Cgb, cgb_mode);
+        ppu.set_renderer(renderer);
+        for index in 0..16 {
+            ppu.write_vram(0x10 + index, 0xFF);
+            ppu.write_vram(0x20 + index, if index % 2 == 0 { 0xFF } else { 0x00 });
+        }
+        ppu.write_vram(0x1802, 2);
+        // colour 1 is dark gray
+        ppu.write_register(BGP_ADDRESS, 0b11_10_10_00);
+        ppu.write_register(OBP0_ADDRESS, 0b11_10_01_00);
+        ppu.write_register(OBP1_ADDRESS, 0b01_00_00_00);
+        write_colors(&mut ppu, OCPS_ADDRESS, 6, &[0x0000]);
+        write_colors(&mut ppu, OCPS_ADDRESS, 8 + 6, &[0x56B5]);
+        for (offset, x, palette) in [(0, 20, 0), (4, 16, ATTRIBUTE_DMG_PALETTE | 1)] {
+            ppu.write_oam(offset, 17);
+            ppu.write_oam(offset + 1, x);
+            ppu.write_oam(offset + 2, 1);
+            ppu.write_oam(offset + 3, palette | attributes);
+        }
+        ppu.write_register(
+            LCDC_ADDRESS,
+            LCDC_ENABLE | LCDC_TILE_DATA | LCDC_OBJ_ENABLE | LCDC_BG_ENABLE,
+        );
+        while ppu.ly <= 1 {
+            ppu.tick(1);
+        }
+        ppu
+    }
+
+    #[test]
+    fn object_priority() {
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            // the object further left wins in DMG mode
+            let ppu = draw_overlapping_objects(renderer, false, 0);
+            let row: Vec<_> = (8..20).map(|x| pixel(&ppu, x, 1)).collect();
+            assert_eq!(row, [&[0xAA; 8][..], &[0x00; 4]].concat(), "{renderer}");
+            // the object first in OAM wins in CGB mode
+            let ppu = draw_overlapping_objects(renderer, true, 0);
+            let row: Vec<_> = (8..20).map(|x| pixel(&ppu, x, 1)).collect();
+            assert_eq!(row, [&[0xAD; 4][..], &[0x00; 8]].concat(), "{renderer}");
+            // objects behind the background only show over its colour 0
+            let ppu = draw_overlapping_objects(renderer, false, ATTRIBUTE_PRIORITY);
+            let row: Vec<_> = (8..20).map(|x| pixel(&ppu, x, 1)).collect();
+            assert_eq!(row, [&[0xAA; 8][..], &[0x55; 4]].concat(), "{renderer}");
+        }
+    }
+
+    #[test]
+    fn tall_objects_ignore_tile_lsb() {
+        let mut ppu = Ppu::new(Model::Dmg, false);
+        ppu.write_register(LCDC_ADDRESS, LCDC_OBJ_SIZE);
+        ppu.write_oam(0, 16);
+        ppu.write_oam(2, 3);
+        ppu.write_oam(3, ATTRIBUTE_Y_FLIP);
+        ppu.write_vram(0x20, 0x80);
+        ppu.write_vram(0x3E, 0x01);
+        // the top row of the flipped object is the bottom row of tile 3
+        assert_eq!(ppu.object_row(0), [0, 0, 0, 0, 0, 0, 0, 1]);
+        ppu.ly = 15;
+        assert_eq!(ppu.object_row(0), [1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn object_size_changed_after_oam_scan() {
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            let mut ppu = Ppu::new(Model::Dmg, false);
+            ppu.set_renderer(renderer);
+            // tile 2 is black, tile 3 below it white
+            for index in 0..16 {
+                ppu.write_vram(0x20 + index, 0xFF);
+            }
+            ppu.write_register(OBP0_ADDRESS, 0b11_00_00_00);
+            // a flipped 8×16 object showing its row 9 on line 1
+            ppu.write_oam(0, 8);
+            ppu.write_oam(1, 8);
+            ppu.write_oam(2, 2);
+            ppu.write_oam(3, ATTRIBUTE_Y_FLIP);
+            let lcdc = LCDC_ENABLE | LCDC_TILE_DATA | LCDC_OBJ_ENABLE | LCDC_BG_ENABLE;
+            ppu.write_register(LCDC_ADDRESS, lcdc | LCDC_OBJ_SIZE);
+            run_until(&mut ppu, 1, OAM_SCAN_DOTS - 1);
+            // the object is drawn 8×8 as selected, with row 1 of tile 2
+            ppu.write_register(LCDC_ADDRESS, lcdc);
+            run_until(&mut ppu, 2, 0);
+            assert_eq!(pixel(&ppu, 0, 1), 0x00, "{renderer}");
+            assert_eq!(pixel(&ppu, 8, 1), 0xFF, "{renderer}");
+        }
+    }
+
+    const WINDOW_LCDC: u8 =
+        LCDC_ENABLE | LCDC_WINDOW_MAP | LCDC_WINDOW_ENABLE | LCDC_TILE_DATA | LCDC_BG_ENABLE;
+
+    /// Returns a PPU with a white background and a window whose first 8 lines show the tiles in
+    /// `first_row`, tile 1 being black and tile 2 light gray, and light gray below.
+    fn window_ppu(renderer: Renderer, first_row: &[u8]) -> Ppu {
+        let mut ppu = Ppu::new(Model::Dmg, false);
+        ppu.set_renderer(renderer);
+        for index in 0..16 {
+            ppu.write_vram(0x10 + index, 0xFF);
+            ppu.write_vram(0x20 + index, if index % 2 == 0 { 0xFF } else { 0x00 });
+        }
+        for (column, &tile) in first_row.iter().enumerate() {
+            ppu.write_vram(0x1C00 + column, tile);
+        }
+        for column in 0..32 {
+            ppu.write_vram(0x1C20 + column, 2);
+        }
+        ppu.write_register(BGP_ADDRESS, 0b11_10_01_00);
+        ppu
+    }
+
+    #[test]
+    fn window_line_counter() {
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            let mut ppu = window_ppu(renderer, &[1; 32]);
+            ppu.write_register(WX_ADDRESS, 7);
+            ppu.write_register(LCDC_ADDRESS, WINDOW_LCDC);
+            run_until(&mut ppu, 4, 0);
+            ppu.write_register(LCDC_ADDRESS, WINDOW_LCDC & !LCDC_WINDOW_ENABLE);
+            run_until(&mut ppu, 10, 0);
+            ppu.write_register(LCDC_ADDRESS, WINDOW_LCDC);
+            run_until(&mut ppu, 20, 0);
+            assert_eq!(pixel(&ppu, 0, 3), 0x00, "{renderer}");
+            assert_eq!(pixel(&ppu, 0, 5), 0xFF, "{renderer}");
+            // the window continues with its line 4
+            assert_eq!(pixel(&ppu, 0, 10), 0x00, "{renderer}");
+            assert_eq!(pixel(&ppu, 0, 13), 0x00, "{renderer}");
+            assert_eq!(pixel(&ppu, 0, 14), 0xAA, "{renderer}");
+        }
+    }
+
+    #[test]
+    fn window_x_special_cases() {
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            let mut ppu = window_ppu(renderer, &[1, 2]);
+            ppu.write_register(WX_ADDRESS, 3);
+            ppu.write_register(LCDC_ADDRESS, WINDOW_LCDC);
+            run_until(&mut ppu, 1, 0);
+            // the first 4 pixels of the window are cut off
+            assert_eq!(pixel(&ppu, 3, 0), 0x00, "{renderer}");
+            assert_eq!(pixel(&ppu, 4, 0), 0xAA, "{renderer}");
+            assert_eq!(pixel(&ppu, 12, 0), 0xFF, "{renderer}");
+
+            let mut ppu = window_ppu(renderer, &[1, 2]);
+            ppu.write_register(SCX_ADDRESS, 2);
+            ppu.write_register(WX_ADDRESS, 0);
+            ppu.write_register(LCDC_ADDRESS, WINDOW_LCDC);
+            run_until(&mut ppu, 1, 0);
+            // the fine scroll cuts off another 2 pixels
+            assert_eq!(pixel(&ppu, 0, 0), 0xAA, "{renderer}");
+            assert_eq!(pixel(&ppu, 6, 0), 0xAA, "{renderer}");
+            assert_eq!(pixel(&ppu, 7, 0), 0xFF, "{renderer}");
+
+            let mut ppu = window_ppu(renderer, &[1, 2]);
+            ppu.write_register(WX_ADDRESS, WX_WRAPPING);
+            ppu.write_register(LCDC_ADDRESS, WINDOW_LCDC);
+            run_until(&mut ppu, 2, 0);
+            assert_eq!(pixel(&ppu, 158, 0), 0xFF, "{renderer}");
+            assert_eq!(pixel(&ppu, 159, 0), 0x00, "{renderer}");
+            // the next line is covered by the window from the left edge
+            assert_eq!(pixel(&ppu, 0, 1), 0x00, "{renderer}");
+            assert_eq!(pixel(&ppu, 8, 1), 0xAA, "{renderer}");
+        }
+    }
+
+    #[test]
+    fn window_disabled_mid_line() {
+        let mut ppu = window_ppu(Renderer::PixelFifo, &[1; 32]);
+        ppu.write_register(WX_ADDRESS, 7);
+        ppu.write_register(LCDC_ADDRESS, WINDOW_LCDC);
+        run_until(&mut ppu, 1, OAM_SCAN_DOTS + 80);
+        ppu.write_register(LCDC_ADDRESS, WINDOW_LCDC & !LCDC_WINDOW_ENABLE);
+        run_until(&mut ppu, 2, 0);
+        assert_eq!(pixel(&ppu, 10, 1), 0x00);
+        assert_eq!(pixel(&ppu, 150, 1), 0xFF);
+    }
+
+    /// Writes colours in RGB555 to palette RAM through BCPS/BCPD or OCPS/OCPD from `index` on.
+    fn write_colors(ppu: &mut Ppu, specification_address: u16, index: u8, colors: &[u16]) {
+        ppu.write_register(specification_address, PALETTE_AUTO_INCREMENT | index);
+        for byte in colors.iter().flat_map(|color| color.to_le_bytes()) {
+            ppu.write_register(specification_address + 1, byte);
+        }
+    }
+
+    #[test]
+    fn palette_auto_increment() {
+        let mut ppu = Ppu::new(Model::Cgb, true);
+        write_colors(&mut ppu, BCPS_ADDRESS, 0x3E, &[0x1234]);
+        // the index wraps around after the last byte
+        assert_eq!(ppu.read_register(BCPS_ADDRESS), 0xC0);
+        ppu.write_register(BCPS_ADDRESS, 0x3F);
+        assert_eq!(ppu.read_register(BCPD_ADDRESS), 0x12);
+        // without auto-increment, the index stays
+        ppu.write_register(BCPD_ADDRESS, 0x56);
+        assert_eq!(ppu.read_register(BCPS_ADDRESS), 0x7F);
+        assert_eq!(ppu.bg_palettes[0x3F], 0x56);
+
+        ppu.write_register(OCPS_ADDRESS, PALETTE_AUTO_INCREMENT | 4);
+        ppu.write_register(LCDC_ADDRESS, LCDC_ENABLE);
+        run_until(&mut ppu, 1, OAM_SCAN_DOTS + 10);
+        // palette RAM is inaccessible while drawing, but writes still advance the index
+        ppu.write_register(OCPD_ADDRESS, 0x78);
+        assert_eq!(ppu.read_register(OCPD_ADDRESS), 0xFF);
+        assert_eq!(ppu.read_register(OCPS_ADDRESS), 0xC5);
+        assert_eq!(ppu.obj_palettes[4], 0);
+    }
+
+    #[test]
+    fn cgb_tile_attributes() {
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            let mut ppu = Ppu::new(Model::Cgb, true);
+            ppu.set_renderer(renderer);
+            // tile 1 in bank 1 has colour 1 in the left pixel of its bottom row only
+            ppu.write_vram(0x2010 + 7 * 2, 0x80);
+            // the first map entry is flipped both ways and uses palette 2
+            ppu.write_vram(0x1800, 1);
+            ppu.write_vram(
+                0x3800,
+                ATTRIBUTE_BANK | ATTRIBUTE_Y_FLIP | ATTRIBUTE_X_FLIP | 2,
+            );
+            write_colors(&mut ppu, BCPS_ADDRESS, 2 * 8, &[0x7FFF, 0x001F]);
+            // line 1 shows the top row of the map
+            ppu.write_register(SCY_ADDRESS, 0xFF);
+            ppu.write_register(LCDC_ADDRESS, LCDC_ENABLE | LCDC_TILE_DATA | LCDC_BG_ENABLE);
+            run_until(&mut ppu, 2, 0);
+            let rgba = |x: usize| &ppu.back_buffer[(SCREEN_WIDTH + x) * 4..][..4];
+            assert_eq!(rgba(0), [0xFF, 0xFF, 0xFF, 0xFF], "{renderer}");
+            assert_eq!(rgba(7), [0xFF, 0x00, 0x00, 0xFF], "{renderer}");
+        }
+    }
+
+    #[test]
+    fn cgb_master_priority() {
+        let mut ppu = Ppu::new(Model::Cgb, true);
+        write_colors(&mut ppu, BCPS_ADDRESS, 8, &[0, 0x001F]);
+        write_colors(&mut ppu, OCPS_ADDRESS, 0, &[0, 0x03E0]);
+        ppu.write_register(LCDC_ADDRESS, LCDC_OBJ_ENABLE | LCDC_BG_ENABLE);
+        let red = [0xFF, 0x00, 0x00, 0xFF];
+        let green = [0x00, 0xFF, 0x00, 0xFF];
+        let background = BackgroundPixel {
+            color: 1,
+            attributes: ATTRIBUTE_PRIORITY | 1,
+        };
+        let object = Some(ObjectPixel {
+            color: 1,
+            ..Default::default()
+        });
+        // the tile has priority over the object
+        assert_eq!(ppu.mix_pixel(background, object), red);
+        let background = BackgroundPixel {
+            attributes: 1,
+            ..background
+        };
+        assert_eq!(ppu.mix_pixel(background, object), green);
+        // the background stays visible without LCDC bit 0, but loses all priority
+        ppu.write_register(LCDC_ADDRESS, LCDC_OBJ_ENABLE);
+        assert_eq!(ppu.mix_pixel(background, None), red);
+        let background = BackgroundPixel {
+            attributes: ATTRIBUTE_PRIORITY | 1,
+            ..background
+        };
+        assert_eq!(ppu.mix_pixel(background, object), green);
+    }
+
+    #[test]
+    fn color_correction() {
+        assert_eq!(
+            ColorCorrection::Raw.convert(0x7FFF),
+            [0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            ColorCorrection::Raw.convert(0x0010),
+            [0x84, 0x00, 0x00, 0xFF]
+        );
+        assert_eq!(
+            ColorCorrection::Matrix.convert(0x7FFF),
+            [240, 240, 240, 0xFF]
+        );
+        assert_eq!(ColorCorrection::Matrix.convert(0x001F), [201, 0, 46, 0xFF]);
+        assert_eq!(ColorCorrection::Lcd.convert(0x7FFF), [248, 248, 248, 0xFF]);
+        assert_eq!(ColorCorrection::Lcd.convert(0x0000), [0, 0, 0, 0xFF]);
+        assert_eq!(ColorCorrection::Lcd.convert(0x001F), [227, 96, 118, 0xFF]);
+        // the LCD and display gamma cancel out, leaving grays only dimmed by the luminance
+        assert_eq!(ColorCorrection::Lcd.convert(0x3DEF), [120, 120, 120, 0xFF]);
+        assert_eq!("LCD".parse(), Ok(ColorCorrection::Lcd));
+        assert_eq!("Matrix".parse(), Ok(ColorCorrection::Matrix));
+        assert!("sepia".parse::<ColorCorrection>().is_err());
+    }
+
+    #[test]
+    fn lyc_interrupt() {
+        let mut ppu = enabled_ppu();
+        // LY and LYC are both 0
+        assert_eq!(
+            ppu.write_register(STAT_ADDRESS, STAT_LYC_SOURCE),
+            Interrupt::Stat.mask()
+        );
+        ppu.write_register(LYC_ADDRESS, 2);
+        let mut interrupts = 0;
+        for _ in 0..2 * LINE_DOTS - 1 {
+            interrupts |= ppu.tick(1).interrupts;
+        }
+        assert_eq!(interrupts, 0);
+        assert_eq!(ppu.tick(1).interrupts, Interrupt::Stat.mask());
+        assert_ne!(ppu.read_register(STAT_ADDRESS) & STAT_COINCIDENCE, 0);
+    }
+
+    /// Ticks until `line` and `dot`, returning the interrupts requested on the way.
+    fn run_until(ppu: &mut Ppu, line: u8, dot: u16) -> u8 {
+        let mut interrupts = 0;
+        while (ppu.line, ppu.dot) != (line, dot) {
+            interrupts |= ppu.tick(1).interrupts;
+        }
+        interrupts
+    }
+
+    #[test]
+    fn stat_sources_share_one_line() {
+        let mut ppu = enabled_ppu();
+        ppu.write_register(STAT_ADDRESS, STAT_HBLANK_SOURCE | STAT_OAM_SCAN_SOURCE);
+        run_until(&mut ppu, 1, 1);
+        // mode 0 held the line high until mode 2 took over, the line never fell
+        assert_eq!(run_until(&mut ppu, 2, 0), Interrupt::Stat.mask());
+
+        let mut ppu = enabled_ppu();
+        ppu.write_register(LYC_ADDRESS, 1);
+        ppu.write_register(STAT_ADDRESS, STAT_HBLANK_SOURCE | STAT_LYC_SOURCE);
+        run_until(&mut ppu, 0, 300);
+        // LY=LYC on line 1 follows mode 0 directly
+        assert_eq!(run_until(&mut ppu, 1, 1), 0);
+        // the OAM source also fires at the start of VBlank
+        let mut ppu = enabled_ppu();
+        ppu.write_register(STAT_ADDRESS, STAT_OAM_SCAN_SOURCE);
+        run_until(&mut ppu, 143, 100);
+        assert_eq!(
+            run_until(&mut ppu, 144, 1),
+            Interrupt::Stat.mask() | Interrupt::VBlank.mask()
+        );
+    }
+
+    #[test]
+    fn dmg_stat_write_bug() {
+        let mut ppu = enabled_ppu();
+        run_until(&mut ppu, 1, 300);
+        assert_eq!(ppu.write_register(STAT_ADDRESS, 0), Interrupt::Stat.mask());
+        run_until(&mut ppu, 1, 100);
+        assert_eq!(ppu.write_register(STAT_ADDRESS, 0), 0);
+
+        let mut ppu = Ppu::new(Model::Cgb, false);
+        ppu.write_register(LCDC_ADDRESS, LCDC_ENABLE);
+        run_until(&mut ppu, 1, 300);
+        assert_eq!(ppu.write_register(STAT_ADDRESS, 0), 0);
+    }
+
+    #[test]
+    fn ly_wraps_early_on_line_153() {
+        let mut ppu = enabled_ppu();
+        ppu.write_register(LYC_ADDRESS, 0);
+        ppu.write_register(STAT_ADDRESS, STAT_LYC_SOURCE);
+        run_until(&mut ppu, 153, 0);
+        assert_eq!(ppu.read_register(LY_ADDRESS), 153);
+        assert_eq!(run_until(&mut ppu, 153, 4), Interrupt::Stat.mask());
+        assert_eq!(ppu.read_register(LY_ADDRESS), 0);
+        assert_eq!(ppu.read_register(STAT_ADDRESS) & 0b11, Mode::VBlank as u8);
+        // LY stays 0 into the next frame, without another interrupt
+        assert_eq!(run_until(&mut ppu, 0, 10), 0);
+    }
+}
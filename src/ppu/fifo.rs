@@ -0,0 +1,351 @@
+//! Draws the line dot by dot like the hardware: a fetcher reads tiles into the background FIFO,
+//! which shifts out one pixel per dot, and fetching an object pauses both while its pixels are
+//! merged into the object FIFO. Mode 3 ends once 160 pixels have been shifted out.
+
+use std::collections::VecDeque;
+
+use super::{
+    BackgroundPixel, ObjectPixel, Ppu, ATTRIBUTE_X_FLIP, ATTRIBUTE_Y_FLIP, LCDC_BG_MAP,
+    LCDC_OBJ_ENABLE, LCDC_WINDOW_ENABLE, LCDC_WINDOW_MAP, SCREEN_WIDTH,
+};
+
+/// Dots of the first tile fetch of a line, whose pixels are thrown away.
+const STARTUP_DOTS: u8 = 6;
+/// Dots each step of the background fetcher takes, except for pushing.
+const FETCH_STEP_DOTS: u8 = 2;
+/// Dots an object fetch takes once the background fetcher has a tile ready.
+const OBJECT_FETCH_DOTS: u8 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum FetchStep {
+    #[default]
+    Tile,
+    DataLow,
+    DataHigh,
+    /// waits until the background FIFO is empty
+    Push,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct PixelFifo {
+    /// pixels of the background or window
+    background: VecDeque<BackgroundPixel>,
+    objects: VecDeque<ObjectPixel>,
+    step: FetchStep,
+    /// dots spent in the current fetcher step
+    step_dots: u8,
+    /// tile column fetched next, counted from SCX or from the left edge of the window
+    fetch_x: u8,
+    tile: u8,
+    /// attributes of `tile` in CGB mode
+    attributes: u8,
+    low: u8,
+    high: u8,
+    /// the fetcher reads window tiles
+    window: bool,
+    /// dots left of the first fetch
+    startup_dots: u8,
+    /// pixels left to throw away for the fine scroll of SCX
+    discard: u8,
+    /// screen X of the next pixel shifted out
+    x: u8,
+    /// bit per entry of `line_objects` that has been fetched
+    fetched_objects: u16,
+    /// entry of `line_objects` being fetched, with the dots it has waited or been fetched for
+    object_fetch: Option<(usize, u8)>,
+}
+
+/// Prepares drawing the current line, called when mode 3 starts.
+pub(super) fn start_line(ppu: &mut Ppu) {
+    let fifo = &mut ppu.fifo;
+    fifo.background.clear();
+    fifo.objects.clear();
+    fifo.step = FetchStep::Tile;
+    fifo.step_dots = 0;
+    fifo.fetch_x = 0;
+    fifo.window = false;
+    fifo.startup_dots = STARTUP_DOTS;
+    fifo.discard = ppu.scx % 8;
+    fifo.x = 0;
+    fifo.fetched_objects = 0;
+    fifo.object_fetch = None;
+}
+
+/// Advances drawing by a dot, returning whether the line is complete.
+pub(super) fn step(ppu: &mut Ppu) -> bool {
+    if ppu.fifo.x as usize == SCREEN_WIDTH {
+        return true;
+    }
+    if ppu.fifo.startup_dots > 0 {
+        ppu.fifo.startup_dots -= 1;
+        return false;
+    }
+    start_window(ppu);
+    if ppu.fifo.object_fetch.is_none() {
+        ppu.fifo.object_fetch = next_object(ppu).map(|index| (index, 0));
+    }
+    match ppu.fifo.object_fetch {
+        Some((index, dots)) => {
+            // the background fetcher finishes the tile it is fetching first
+            if !tile_ready(ppu) {
+                fetch_background(ppu);
+            }
+            if tile_ready(ppu) {
+                if dots + 1 == OBJECT_FETCH_DOTS {
+                    fetch_object(ppu, index);
+                    ppu.fifo.object_fetch = None;
+                } else {
+                    ppu.fifo.object_fetch = Some((index, dots + 1));
+                }
+            }
+        }
+        None => {
+            shift_pixel(ppu);
+            fetch_background(ppu);
+        }
+    }
+    false
+}
+
+/// Restarts the fetcher on the window once the pixel where it starts is reached, and back on the
+/// background if LCDC bit 5 is cleared in the middle of the line.
+fn start_window(ppu: &mut Ppu) {
+    if ppu.fifo.window {
+        if ppu.lcdc & LCDC_WINDOW_ENABLE == 0 {
+            let fifo = &mut ppu.fifo;
+            fifo.window = false;
+            fifo.step = FetchStep::Tile;
+            fifo.step_dots = 0;
+            // continue with the background tile at the first pixel not yet in the FIFO
+            let next_x = fifo.x as usize + fifo.background.len() + (ppu.scx % 8) as usize;
+            fifo.fetch_x = (next_x / 8) as u8;
+        }
+        return;
+    }
+    let Some((start, hidden)) = ppu.window_start() else {
+        return;
+    };
+    // with WX 0, the window starts before the fine scroll discards pixels, which then come from
+    // the window
+    let before_discard = ppu.wx == 0 && !ppu.window_wraps;
+    let fifo = &mut ppu.fifo;
+    if fifo.x < start || (fifo.discard > 0 && !before_discard) {
+        return;
+    }
+    fifo.window = true;
+    fifo.discard += hidden;
+    fifo.background.clear();
+    fifo.step = FetchStep::Tile;
+    fifo.step_dots = 0;
+    fifo.fetch_x = 0;
+    ppu.window_drawn = true;
+}
+
+/// Returns the entry of `line_objects` to fetch at the current pixel, the one furthest left
+/// first and OAM order among objects at the same X.
+fn next_object(ppu: &Ppu) -> Option<usize> {
+    if ppu.fifo.discard > 0 || ppu.lcdc & LCDC_OBJ_ENABLE == 0 {
+        return None;
+    }
+    ppu.line_objects
+        .iter()
+        .enumerate()
+        .filter(|&(index, _)| ppu.fifo.fetched_objects & (1 << index) == 0)
+        .map(|(index, &offset)| (ppu.oam[offset + 1], index))
+        .filter(|&(x, _)| x <= ppu.fifo.x + 8)
+        .min()
+        .map(|(_, index)| index)
+}
+
+/// Whether the background fetcher has fetched a tile and is waiting to push it.
+fn tile_ready(ppu: &Ppu) -> bool {
+    ppu.fifo.step == FetchStep::Push
+}
+
+fn fetch_background(ppu: &mut Ppu) {
+    let fifo = &mut ppu.fifo;
+    if fifo.step != FetchStep::Push {
+        fifo.step_dots += 1;
+        if fifo.step_dots < FETCH_STEP_DOTS {
+            return;
+        }
+        fifo.step_dots = 0;
+    }
+    let (map, column, y) = if ppu.fifo.window {
+        (
+            ppu.tile_map(LCDC_WINDOW_MAP),
+            ppu.fifo.fetch_x,
+            ppu.window_line,
+        )
+    } else {
+        (
+            ppu.tile_map(LCDC_BG_MAP),
+            (ppu.scx / 8).wrapping_add(ppu.fifo.fetch_x),
+            ppu.ly.wrapping_add(ppu.scy),
+        )
+    };
+    let data_index = || {
+        let attributes = ppu.fifo.attributes;
+        let row = if attributes & ATTRIBUTE_Y_FLIP != 0 {
+            7 - y % 8
+        } else {
+            y % 8
+        };
+        ppu.bg_tile_index(ppu.fifo.tile, attributes) + row as usize * 2
+    };
+    match ppu.fifo.step {
+        FetchStep::Tile => {
+            let index = map + (y as usize / 8) * 32 + (column & 31) as usize;
+            ppu.fifo.tile = ppu.vram[index];
+            ppu.fifo.attributes = ppu.map_attributes(index);
+            ppu.fifo.step = FetchStep::DataLow;
+        }
+        FetchStep::DataLow => {
+            ppu.fifo.low = ppu.vram[data_index()];
+            ppu.fifo.step = FetchStep::DataHigh;
+        }
+        FetchStep::DataHigh => {
+            ppu.fifo.high = ppu.vram[data_index() + 1];
+            ppu.fifo.step = FetchStep::Push;
+            push_tile(ppu);
+        }
+        FetchStep::Push => push_tile(ppu),
+    }
+}
+
+/// Pushes the fetched tile row into the background FIFO if it is empty.
+fn push_tile(ppu: &mut Ppu) {
+    let fifo = &mut ppu.fifo;
+    if !fifo.background.is_empty() {
+        return;
+    }
+    let attributes = fifo.attributes;
+    for column in 0..8 {
+        let bit = if attributes & ATTRIBUTE_X_FLIP != 0 {
+            column
+        } else {
+            7 - column
+        };
+        let color = ((fifo.high >> bit) & 1) << 1 | (fifo.low >> bit) & 1;
+        fifo.background
+            .push_back(BackgroundPixel { color, attributes });
+    }
+    fifo.fetch_x = fifo.fetch_x.wrapping_add(1);
+    fifo.step = FetchStep::Tile;
+}
+
+/// Merges the pixels of an object into the object FIFO. Pixels of objects fetched before, which
+/// are further left, stay on top unless transparent. On the CGB, objects earlier in OAM are
+/// drawn on top instead.
+fn fetch_object(ppu: &mut Ppu, index: usize) {
+    ppu.fifo.fetched_objects |= 1 << index;
+    let offset = ppu.line_objects[index];
+    let row = ppu.object_row(offset);
+    let attributes = ppu.oam[offset + 3];
+    // pixels left of the current one, for objects partially left of the screen
+    let hidden = (ppu.fifo.x + 8 - ppu.oam[offset + 1]) as usize;
+    let cgb_mode = ppu.cgb_mode;
+    let objects = &mut ppu.fifo.objects;
+    for (slot, &color) in row.iter().skip(hidden).enumerate() {
+        let pixel = ObjectPixel {
+            color,
+            attributes,
+            oam_offset: offset as u8,
+        };
+        match objects.get_mut(slot) {
+            Some(existing) if existing.color == 0 => *existing = pixel,
+            Some(existing) if cgb_mode && color != 0 && pixel.oam_offset < existing.oam_offset => {
+                *existing = pixel
+            }
+            Some(_) => {}
+            None => objects.push_back(pixel),
+        }
+    }
+}
+
+/// Shifts a pixel out to the screen if the background FIFO has one.
+fn shift_pixel(ppu: &mut Ppu) {
+    let Some(background) = ppu.fifo.background.pop_front() else {
+        return;
+    };
+    if ppu.fifo.discard > 0 {
+        ppu.fifo.discard -= 1;
+        return;
+    }
+    let object = ppu.fifo.objects.pop_front();
+    let x = ppu.fifo.x as usize;
+    let rgba = ppu.mix_pixel(background, object);
+    ppu.set_pixel(x, rgba);
+    ppu.fifo.x += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boot::Model;
+    use crate::ppu::{
+        Mode, Renderer, LCDC_ADDRESS, LCDC_BG_ENABLE, LCDC_ENABLE, SCX_ADDRESS, WX_ADDRESS,
+    };
+
+    /// Returns the length of mode 3 on the second line, the first having no OAM scan.
+    fn drawing_dots(setup: impl FnOnce(&mut Ppu)) -> usize {
+        let mut ppu = Ppu::new(Model::Dmg, false);
+        ppu.set_renderer(Renderer::PixelFifo);
+        setup(&mut ppu);
+        let lcdc = ppu.read_register(LCDC_ADDRESS);
+        ppu.write_register(LCDC_ADDRESS, LCDC_ENABLE | LCDC_BG_ENABLE | lcdc);
+        while ppu.ly == 0 || ppu.mode != Mode::Drawing {
+            ppu.tick(1);
+        }
+        let mut dots = 0;
+        while ppu.mode == Mode::Drawing {
+            ppu.tick(1);
+            dots += 1;
+        }
+        dots
+    }
+
+    fn add_object(ppu: &mut Ppu, index: usize, x: u8) {
+        ppu.write_oam(index * 4, 17);
+        ppu.write_oam(index * 4 + 1, x);
+    }
+
+    #[test]
+    fn mode_3_length() {
+        assert_eq!(drawing_dots(|_| {}), 172);
+        assert_eq!(
+            drawing_dots(|ppu| {
+                ppu.write_register(SCX_ADDRESS, 3);
+            }),
+            175
+        );
+    }
+
+    #[test]
+    fn object_penalties() {
+        let with_objects = |objects: &[u8]| {
+            drawing_dots(|ppu| {
+                ppu.write_register(LCDC_ADDRESS, LCDC_OBJ_ENABLE);
+                for (index, &x) in objects.iter().enumerate() {
+                    add_object(ppu, index, x);
+                }
+            })
+        };
+        // the fetcher has to finish the first tile
+        assert_eq!(with_objects(&[0]), 172 + 11);
+        // the fetcher is waiting to push, the object fetch alone remains
+        assert_eq!(with_objects(&[8 + 7]), 172 + 6);
+        assert_eq!(with_objects(&[8 + 7, 8 + 7]), 172 + 12);
+        // objects are only fetched while enabled
+        assert_eq!(drawing_dots(|ppu| add_object(ppu, 0, 0)), 172);
+    }
+
+    #[test]
+    fn window_restarts_fetcher() {
+        let dots = drawing_dots(|ppu| {
+            ppu.write_register(LCDC_ADDRESS, LCDC_WINDOW_ENABLE);
+            ppu.write_register(WX_ADDRESS, 7 + 80);
+        });
+        assert_eq!(dots, 172 + 6);
+    }
+}
@@ -0,0 +1,106 @@
+//! Draws a whole line at once, at the start of mode 3.
+
+use super::{
+    BackgroundPixel, ObjectPixel, Ppu, ATTRIBUTE_X_FLIP, ATTRIBUTE_Y_FLIP, LCDC_BG_MAP,
+    LCDC_OBJ_ENABLE, LCDC_WINDOW_MAP, MAX_LINE_OBJECTS, SCREEN_WIDTH,
+};
+
+/// Draws the current line of the frame from the background, the window and the objects.
+pub(super) fn render_line(ppu: &mut Ppu) {
+    // colour indices and tile attributes before applying the palettes
+    let mut background = [BackgroundPixel::default(); SCREEN_WIDTH];
+    // opaque object pixels, drawn over the background
+    let mut objects = [None; SCREEN_WIDTH];
+
+    // the background is fetched even while disabled, advancing the window line counter
+    draw_background(ppu, &mut background);
+    ppu.window_drawn = draw_window(ppu, &mut background);
+    if ppu.lcdc & LCDC_OBJ_ENABLE != 0 {
+        draw_objects(ppu, &mut objects);
+    }
+
+    for x in 0..SCREEN_WIDTH {
+        let rgba = ppu.mix_pixel(background[x], objects[x]);
+        ppu.set_pixel(x, rgba);
+    }
+}
+
+/// Returns pixel (`x`, `y`) of the 256×256 tile map at VRAM index `map`.
+fn map_pixel(ppu: &Ppu, map: usize, x: u8, y: u8) -> BackgroundPixel {
+    let map_index = map + (y as usize / 8) * 32 + x as usize / 8;
+    let attributes = ppu.map_attributes(map_index);
+    let tile_index = ppu.bg_tile_index(ppu.vram[map_index], attributes);
+    let flip = |position: u8, bit: u8| {
+        if attributes & bit != 0 {
+            7 - position % 8
+        } else {
+            position % 8
+        }
+    };
+    BackgroundPixel {
+        color: ppu.tile_pixel(
+            tile_index,
+            flip(y, ATTRIBUTE_Y_FLIP),
+            flip(x, ATTRIBUTE_X_FLIP),
+        ),
+        attributes,
+    }
+}
+
+fn draw_background(ppu: &Ppu, line: &mut [BackgroundPixel; SCREEN_WIDTH]) {
+    let map = ppu.tile_map(LCDC_BG_MAP);
+    let y = ppu.ly.wrapping_add(ppu.scy);
+    for (x, pixel) in line.iter_mut().enumerate() {
+        *pixel = map_pixel(ppu, map, (x as u8).wrapping_add(ppu.scx), y);
+    }
+}
+
+/// Draws the window over the background, returning whether it is shown on this line.
+fn draw_window(ppu: &Ppu, line: &mut [BackgroundPixel; SCREEN_WIDTH]) -> bool {
+    let Some((start, mut hidden)) = ppu.window_start() else {
+        return false;
+    };
+    if ppu.wx == 0 && !ppu.window_wraps {
+        // the fine scroll discards window pixels instead of background pixels
+        hidden += ppu.scx % 8;
+    }
+    let map = ppu.tile_map(LCDC_WINDOW_MAP);
+    for (x, pixel) in line.iter_mut().enumerate().skip(start as usize) {
+        let window_x = x - start as usize + hidden as usize;
+        *pixel = map_pixel(ppu, map, window_x as u8, ppu.window_line);
+    }
+    true
+}
+
+/// Draws the objects selected by the OAM scan. Where objects overlap, the one further left is
+/// drawn on top on the DMG and the one earlier in OAM on the CGB, which is also the tie-breaker on
+/// the DMG.
+fn draw_objects(ppu: &Ppu, line: &mut [Option<ObjectPixel>; SCREEN_WIDTH]) {
+    let mut order = [0; MAX_LINE_OBJECTS];
+    let order = &mut order[..ppu.line_objects.len()];
+    order.copy_from_slice(&ppu.line_objects);
+    if !ppu.cgb_mode {
+        // the sort is stable, keeping OAM order for objects at the same X
+        order.sort_by_key(|&offset| ppu.oam[offset + 1]);
+    }
+    // objects drawn last end up on top
+    for &offset in order.iter().rev() {
+        let x = ppu.oam[offset + 1];
+        let row = ppu.object_row(offset);
+        for (column, color) in row.into_iter().enumerate() {
+            // object X coordinates are offset by 8 so that objects can be partially left of the
+            // screen
+            let Some(screen_x) = (x as usize + column).checked_sub(8) else {
+                continue;
+            };
+            // colour 0 is transparent
+            if screen_x < SCREEN_WIDTH && color != 0 {
+                line[screen_x] = Some(ObjectPixel {
+                    color,
+                    attributes: ppu.oam[offset + 3],
+                    oam_offset: offset as u8,
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{
+    errors::EmulatorError,
+    gameboy::GameBoy,
+    mooneye::{TestOutcome, EXIT_INSTRUCTION, MAX_STEPS},
+    pnm::Pnm,
+    ppu::{SCREEN_HEIGHT, SCREEN_WIDTH},
+};
+
+/// Runs a test ROM that draws a picture, like dmg-acid2 and cgb-acid2, and compares the result
+/// with `reference`, a binary PPM of the expected screen. Like the Mooneye tests, these ROMs
+/// execute ld b, b once they are done, and the first frame drawn entirely after that is compared.
+pub fn run_reference_test(
+    gameboy: &mut GameBoy,
+    reference: &[u8],
+) -> Result<TestOutcome, EmulatorError> {
+    let reference = Pnm::parse(reference, "P6")?;
+    if (reference.width, reference.height) != (SCREEN_WIDTH, SCREEN_HEIGHT) {
+        return Err(EmulatorError::ImageError(format!(
+            "The reference picture must be {SCREEN_WIDTH}×{SCREEN_HEIGHT} pixels, but is {}×{}.",
+            reference.width, reference.height
+        )));
+    }
+
+    let mut steps = 0..MAX_STEPS;
+    if !steps.any(|_| gameboy.step() == Some(EXIT_INSTRUCTION)) {
+        return Ok(TestOutcome::TimedOut);
+    }
+    // the frame being drawn may have started before the picture was complete
+    let done_frame = gameboy.frames() + 2;
+    if !steps.any(|_| {
+        gameboy.step();
+        gameboy.frames() == done_frame
+    }) {
+        return Ok(TestOutcome::TimedOut);
+    }
+
+    if mismatched_pixels(gameboy.frame(), &reference) == 0 {
+        Ok(TestOutcome::Passed)
+    } else {
+        Ok(TestOutcome::Failed)
+    }
+}
+
+/// Counts the pixels of an RGBA frame that differ from an RGB reference.
+fn mismatched_pixels(frame: &[u8], reference: &Pnm) -> usize {
+    let scale = |sample: u8| (sample as usize * 255 / reference.max_value).min(255) as u8;
+    frame
+        .chunks_exact(4)
+        .zip(reference.samples.chunks_exact(3))
+        .filter(|(pixel, expected)| {
+            pixel[..3]
+                .iter()
+                .zip(expected.iter())
+                .any(|(&actual, &expected)| actual != scale(expected))
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        boot::Model,
+        cartridge::{
+            header::{LOGO_START, NINTENDO_LOGO},
+            Cartridge,
+        },
+        pnm::encode_ppm,
+        ppu::{ColorCorrection, Renderer},
+    };
+
+    /// Builds a ROM drawing two overlapping 8×8 objects of colour 3 at (8, 8) and (12, 8), the
+    /// left one in light gray from OBP1 and the right one, which comes first in OAM, in black.
+    fn objects_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[LOGO_START..LOGO_START + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        // jp 0x0150
+        rom[0x0100..0x0103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        #[rustfmt::skip]
+        let program = [
+            0x3E, 0x00,       // ld a, 0x00
+            0xE0, 0x40,       // ldh (LCDC), a, turning the LCD off
+            0x21, 0x10, 0x80, // ld hl, 0x8010
+            0x3E, 0xFF,       // ld a, 0xFF
+            0x06, 0x10,       // ld b, 16
+            0x22,             // ld (hl+), a, filling tile 1 with colour 3
+            0x05,             // dec b
+            0x20, 0xFC,       // jr nz, -4
+            0x21, 0x00, 0xFE, // ld hl, 0xFE00
+            0x36, 0x18,       // ld (hl), 24
+            0x2C,             // inc l
+            0x36, 0x14,       // ld (hl), 20
+            0x2C,             // inc l
+            0x36, 0x01,       // ld (hl), 1
+            0x2C,             // inc l
+            0x36, 0x00,       // ld (hl), 0x00
+            0x2C,             // inc l
+            0x36, 0x18,       // ld (hl), 24
+            0x2C,             // inc l
+            0x36, 0x10,       // ld (hl), 16
+            0x2C,             // inc l
+            0x36, 0x01,       // ld (hl), 1
+            0x2C,             // inc l
+            0x36, 0x10,       // ld (hl), 0x10, using OBP1
+            0x3E, 0xE4,       // ld a, 0xE4
+            0xE0, 0x48,       // ldh (OBP0), a
+            0x3E, 0x54,       // ld a, 0x54
+            0xE0, 0x49,       // ldh (OBP1), a
+            0x3E, 0x93,       // ld a, 0x93
+            0xE0, 0x40,       // ldh (LCDC), a, turning the LCD on with objects
+            0x40,             // ld b, b
+            0x18, 0xFE,       // jr -2
+        ];
+        rom[0x0150..0x0150 + program.len()].copy_from_slice(&program);
+        rom
+    }
+
+    /// The screen drawn by `objects_rom` on the DMG, where the left object is drawn on top.
+    fn objects_reference() -> Vec<u8> {
+        let mut frame = vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+        for y in 8..16 {
+            for x in 8..20 {
+                let shade = if x < 16 { 0xAA } else { 0x00 };
+                let index = (y * SCREEN_WIDTH + x) * 4;
+                frame[index..index + 3].fill(shade);
+            }
+        }
+        encode_ppm(SCREEN_WIDTH, SCREEN_HEIGHT, &frame)
+    }
+
+    /// Runs test ROM `rom` from the directory in the environment variable `ACID2_DIR`, comparing
+    /// it with `reference` there, converted to a binary PPM, with both renderers and the raw colour
+    /// correction.
+    fn run_acid2(rom: &str, reference: &str, model: Model) {
+        let directory = std::path::PathBuf::from(
+            std::env::var_os("ACID2_DIR").expect("ACID2_DIR should name the acid2 directory"),
+        );
+        let reference = std::fs::read(directory.join(reference)).unwrap();
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            let rom = std::fs::read(directory.join(rom)).unwrap();
+            let mut gameboy = GameBoy::new(Cartridge::new(rom).unwrap(), model);
+            gameboy.set_renderer(renderer);
+            gameboy.set_color_correction(ColorCorrection::Raw);
+            let outcome = run_reference_test(&mut gameboy, &reference).unwrap();
+            assert_eq!(outcome, TestOutcome::Passed, "{renderer}");
+        }
+    }
+
+    #[test]
+    #[ignore = "needs dmg-acid2.gb and dmg-acid2.ppm in ACID2_DIR"]
+    fn dmg_acid2() {
+        run_acid2("dmg-acid2.gb", "dmg-acid2.ppm", Model::Dmg);
+    }
+
+    /// The reference picture has the palette colours scaled as stored, which is the raw colour
+    /// correction.
+    #[test]
+    #[ignore = "needs cgb-acid2.gbc and cgb-acid2.ppm in ACID2_DIR"]
+    fn cgb_acid2() {
+        run_acid2("cgb-acid2.gbc", "cgb-acid2.ppm", Model::Cgb);
+    }
+
+    #[test]
+    fn object_priority_matches_reference() {
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            let cartridge = Cartridge::new(objects_rom()).unwrap();
+            let mut gameboy = GameBoy::new(cartridge, Model::Dmg);
+            gameboy.set_renderer(renderer);
+            let outcome = run_reference_test(&mut gameboy, &objects_reference()).unwrap();
+            assert_eq!(outcome, TestOutcome::Passed, "{renderer}");
+        }
+
+        let cartridge = Cartridge::new(objects_rom()).unwrap();
+        let mut gameboy = GameBoy::new(cartridge, Model::Dmg);
+        let mut reference = objects_reference();
+        let last = reference.len() - 1;
+        reference[last] = 0;
+        let outcome = run_reference_test(&mut gameboy, &reference).unwrap();
+        assert_eq!(outcome, TestOutcome::Failed);
+    }
+}
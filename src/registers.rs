@@ -1,5 +1,5 @@
 #[derive(Debug, Clone, Copy)]
-enum FlagKind {
+pub enum FlagKind {
     Z,
     N,
     H,
@@ -7,7 +7,7 @@ enum FlagKind {
 }
 
 #[derive(Debug, Clone, Copy)]
-enum R8Kind {
+pub enum R8Kind {
     A,
     B,
     C,
@@ -18,7 +18,7 @@ enum R8Kind {
 }
 
 #[derive(Debug, Clone, Copy)]
-enum R16Kind {
+pub enum R16Kind {
     AF,
     BC,
     DE,
@@ -31,7 +31,7 @@ const MOST_SIGNIFICANT_BYTE: usize = 1;
 const LEAST_SIGNIFICANT_BYTE: usize = 0;
 
 #[derive(Debug)]
-struct Registers {
+pub struct Registers {
     /// AF Register, high byte A, low byte F
     /// Layout of flags in F register: ZHNC----
     af: [u8; 2],
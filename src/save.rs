@@ -0,0 +1,114 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{cartridge::Cartridge, errors::EmulatorError, gameboy::CYCLES_PER_SECOND};
+
+/// Emulated time between writes of the save file while the game runs, so that little progress is
+/// lost if the emulator does not exit normally.
+const FLUSH_INTERVAL: u64 = 5 * CYCLES_PER_SECOND;
+
+/// Returns the path of the save file belonging to `game_file`, which has the extension `.sav`.
+pub fn save_path(game_file: &Path) -> PathBuf {
+    game_file.with_extension("sav")
+}
+
+/// The save file of a cartridge with a battery, keeping track of what has been written to it.
+pub struct SaveFile {
+    path: PathBuf,
+    /// battery data as last loaded or written, to skip writing it again unchanged
+    stored: Option<Vec<u8>>,
+    next_flush: u64,
+}
+
+impl SaveFile {
+    pub fn new(path: PathBuf) -> Self {
+        SaveFile {
+            path,
+            stored: None,
+            next_flush: FLUSH_INTERVAL,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Restores the battery-backed memory of `cartridge` from the save file. A missing save file
+    /// leaves the memory untouched, as on a new cartridge.
+    pub fn load(&mut self, cartridge: &mut Cartridge) -> Result<(), EmulatorError> {
+        if !cartridge.has_battery() {
+            return Ok(());
+        }
+        match fs::read(&self.path) {
+            Ok(data) => {
+                cartridge.load_battery_data(&data);
+                self.stored = cartridge.battery_data();
+                Ok(())
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Writes the battery-backed memory of `cartridge` to the save file if it has a battery and
+    /// the memory changed since it was last loaded or written.
+    pub fn store(&mut self, cartridge: &Cartridge) -> Result<(), EmulatorError> {
+        let Some(data) = cartridge.battery_data() else {
+            return Ok(());
+        };
+        if self.stored.as_ref() == Some(&data) {
+            return Ok(());
+        }
+        write_atomically(&self.path, &data)?;
+        self.stored = Some(data);
+        Ok(())
+    }
+
+    /// Calls `store` every `FLUSH_INTERVAL`, `time` being the emulated time so far in M-cycles of
+    /// normal speed.
+    pub fn flush(&mut self, cartridge: &Cartridge, time: u64) -> Result<(), EmulatorError> {
+        if time < self.next_flush {
+            return Ok(());
+        }
+        self.next_flush = time + FLUSH_INTERVAL;
+        self.store(cartridge)
+    }
+}
+
+/// Replaces the file at `path` with `data` through a temporary file, so that the file either
+/// keeps its old contents or has the new ones even if the emulator crashes while writing.
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    let result = File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_file_without_leftovers() {
+        let dir = std::env::temp_dir().join(format!("gameboy-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.sav");
+        write_atomically(&path, &[1, 2, 3]).unwrap();
+        write_atomically(&path, &[4, 5]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), [4, 5]);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
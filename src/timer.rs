@@ -0,0 +1,143 @@
+/// The DIV, TIMA, TMA and TAC registers, driven by the 16-bit system counter whose upper byte is
+/// visible as DIV.
+#[derive(Debug, Default)]
+pub struct Timer {
+    /// system counter, incremented every T-cycle
+    counter: u16,
+    /// TIMA, the timer counter
+    tima: u8,
+    /// TMA, the value TIMA is reloaded with after overflowing
+    tma: u8,
+    /// TAC, the timer enable bit and clock select
+    tac: u8,
+    /// TIMA overflowed during the last M-cycle and is reloaded during the next one
+    reload_pending: bool,
+    /// TIMA was reloaded during the current M-cycle, writes to TIMA are ignored in this cycle
+    reloading: bool,
+}
+
+impl Timer {
+    /// Creates a timer whose system counter starts at `counter`.
+    pub fn with_counter(counter: u16) -> Self {
+        Timer {
+            counter,
+            ..Self::default()
+        }
+    }
+
+    /// Advances the timer by one M-cycle. Returns whether the timer interrupt is requested.
+    pub fn tick(&mut self) -> bool {
+        self.reloading = false;
+        let mut interrupt = false;
+        if self.reload_pending {
+            self.reload_pending = false;
+            self.reloading = true;
+            self.tima = self.tma;
+            interrupt = true;
+        }
+
+        let old_signal = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        self.detect_falling_edge(old_signal);
+        interrupt
+    }
+
+    pub fn read_div(&self) -> u8 {
+        (self.counter >> 8) as u8
+    }
+
+    pub fn read_tima(&self) -> u8 {
+        self.tima
+    }
+
+    pub fn read_tma(&self) -> u8 {
+        self.tma
+    }
+
+    pub fn read_tac(&self) -> u8 {
+        self.tac | 0b1111_1000
+    }
+
+    /// Any write to DIV resets the whole system counter.
+    pub fn write_div(&mut self) {
+        let old_signal = self.signal();
+        self.counter = 0;
+        self.detect_falling_edge(old_signal);
+    }
+
+    pub fn write_tima(&mut self, value: u8) {
+        if self.reloading {
+            return;
+        }
+        // writing during the cycle after an overflow cancels the reload and the interrupt
+        self.reload_pending = false;
+        self.tima = value;
+    }
+
+    pub fn write_tma(&mut self, value: u8) {
+        self.tma = value;
+        if self.reloading {
+            self.tima = value;
+        }
+    }
+
+    pub fn write_tac(&mut self, value: u8) {
+        let old_signal = self.signal();
+        self.tac = value & 0b0000_0111;
+        self.detect_falling_edge(old_signal);
+    }
+
+    /// TIMA is incremented on the falling edge of the selected system counter bit ANDed with the
+    /// enable bit. This is also why writes to DIV and TAC can increment TIMA.
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0b11 {
+            0b00 => 9,
+            0b01 => 3,
+            0b10 => 5,
+            _ => 7,
+        };
+        self.tac & 0b100 != 0 && self.counter & (1 << bit) != 0
+    }
+
+    fn detect_falling_edge(&mut self, old_signal: bool) {
+        if old_signal && !self.signal() {
+            let (tima, overflow) = self.tima.overflowing_add(1);
+            self.tima = tima;
+            self.reload_pending = overflow;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overflow_reloads_after_one_cycle() {
+        let mut timer = Timer::default();
+        timer.write_tma(0x42);
+        timer.write_tima(0xFF);
+        // enabled, incrementing every 4 M-cycles
+        timer.write_tac(0b101);
+        let mut interrupt = false;
+        for _ in 0..4 {
+            interrupt |= timer.tick();
+        }
+        assert_eq!(timer.read_tima(), 0x00);
+        assert!(!interrupt);
+        assert!(timer.tick());
+        assert_eq!(timer.read_tima(), 0x42);
+    }
+
+    #[test]
+    fn div_write_increments_tima() {
+        let mut timer = Timer::default();
+        timer.write_tac(0b101);
+        timer.tick();
+        timer.tick();
+        assert_eq!(timer.read_tima(), 0);
+        timer.write_div();
+        assert_eq!(timer.read_tima(), 1);
+        assert_eq!(timer.read_div(), 0);
+    }
+}
@@ -0,0 +1,182 @@
+use std::{fmt, str::FromStr};
+
+use crate::instructions::Instruction;
+
+/// The kind of memory access made by the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Which accesses a watchpoint triggers on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// both reads and writes
+    Access,
+}
+
+/// Breaks execution when the CPU accesses an address in `start..=end`, optionally only if the
+/// byte read or written is `value` and `bank` is mapped at the address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub kind: WatchKind,
+    pub start: u16,
+    pub end: u16,
+    pub value: Option<u8>,
+    pub bank: Option<u16>,
+}
+
+impl Watchpoint {
+    /// Whether the watchpoint covers `address` for the given kind of access, regardless of the
+    /// value and bank.
+    pub fn watches(&self, access: Access, address: u16) -> bool {
+        let kind_matches = match self.kind {
+            WatchKind::Read => access == Access::Read,
+            WatchKind::Write => access == Access::Write,
+            WatchKind::Access => true,
+        };
+        kind_matches && (self.start..=self.end).contains(&address)
+    }
+
+    pub fn matches(&self, access: &WatchAccess) -> bool {
+        self.watches(access.access, access.address)
+            && self.value.is_none_or(|value| value == access.value)
+            && self.bank.is_none_or(|bank| bank == access.bank)
+    }
+}
+
+/// Parses watchpoints written as `[r:|w:|rw:]START[-END][=VALUE][@BANK]` with hexadecimal
+/// numbers, e.g. `w:C000-C0FF=42` or `4000@1F`. Without a prefix, reads and writes are watched.
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let hex = |text: &str| {
+            let digits = text.trim_start_matches("0x");
+            u16::from_str_radix(digits, 16).map_err(|_| format!("invalid hex number {text}"))
+        };
+        let (kind, rest) = match text.split_once(':') {
+            Some(("r", rest)) => (WatchKind::Read, rest),
+            Some(("w", rest)) => (WatchKind::Write, rest),
+            Some(("rw", rest)) => (WatchKind::Access, rest),
+            Some((prefix, _)) => return Err(format!("expected r, w or rw instead of {prefix}")),
+            None => (WatchKind::Access, text),
+        };
+        let (rest, bank) = match rest.split_once('@') {
+            Some((rest, bank)) => (rest, Some(hex(bank)?)),
+            None => (rest, None),
+        };
+        let (range, value) = match rest.split_once('=') {
+            Some((range, value)) => {
+                let value = u8::try_from(hex(value)?).map_err(|_| "value must be a byte")?;
+                (range, Some(value))
+            }
+            None => (rest, None),
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (hex(start)?, hex(end)?),
+            None => (hex(range)?, hex(range)?),
+        };
+        if start > end {
+            return Err(format!(
+                "range start {start:04X} is after the end {end:04X}"
+            ));
+        }
+        Ok(Watchpoint {
+            kind,
+            start,
+            end,
+            value,
+            bank,
+        })
+    }
+}
+
+/// A memory access made by the CPU, together with the bank mapped at the address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchAccess {
+    pub access: Access,
+    pub address: u16,
+    pub value: u8,
+    pub bank: u16,
+}
+
+/// A triggered watchpoint: the access and the instruction that made it, or `None` if the access
+/// was made while dispatching an interrupt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchHit {
+    pub access: WatchAccess,
+    /// address of the instruction
+    pub pc: u16,
+    pub instruction: Option<Instruction>,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let WatchAccess {
+            access,
+            address,
+            value,
+            bank,
+        } = self.access;
+        let access = match access {
+            Access::Read => "read",
+            Access::Write => "write",
+        };
+        write!(
+            f,
+            "{access} of {value:#04X} at {address:#06X} (bank {bank}) by {:#06X}",
+            self.pc
+        )?;
+        match &self.instruction {
+            Some(instruction) => write!(f, ": {instruction:?}"),
+            None => write!(f, " while dispatching an interrupt"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_watchpoints() {
+        assert_eq!(
+            "w:C000-C0FF=42".parse(),
+            Ok(Watchpoint {
+                kind: WatchKind::Write,
+                start: 0xC000,
+                end: 0xC0FF,
+                value: Some(0x42),
+                bank: None,
+            })
+        );
+        let watchpoint: Watchpoint = "0x4000@1F".parse().unwrap();
+        assert_eq!(watchpoint.kind, WatchKind::Access);
+        assert_eq!((watchpoint.start, watchpoint.end), (0x4000, 0x4000));
+        assert_eq!(watchpoint.bank, Some(0x1F));
+        assert!("x:C000".parse::<Watchpoint>().is_err());
+        assert!("C100-C000".parse::<Watchpoint>().is_err());
+        assert!("C000=100".parse::<Watchpoint>().is_err());
+    }
+
+    #[test]
+    fn matching() {
+        let watchpoint: Watchpoint = "r:D000-DFFF@2".parse().unwrap();
+        let mut access = WatchAccess {
+            access: Access::Read,
+            address: 0xD123,
+            value: 0,
+            bank: 2,
+        };
+        assert!(watchpoint.matches(&access));
+        access.bank = 1;
+        assert!(!watchpoint.matches(&access));
+        access.bank = 2;
+        access.access = Access::Write;
+        assert!(!watchpoint.matches(&access));
+    }
+}